@@ -0,0 +1,235 @@
+//! Scripted stand-in for the `claude` CLI, for tests that run Q9gent end to end
+//!
+//! Called like Claude Code in headless mode (`-p <prompt> --output-format
+//! stream-json ...`), it writes stream-json messages to stdout as the prompt
+//! tells it to. A prompt whose every non-empty line starts with a directive is
+//! a script:
+//!
+//! ```text
+//! session <id>        session ID to report (default: the --resume ID or a new one)
+//! say <text>          assistant text message
+//! tool <name> [json]  assistant tool_use message and the tool_result after it
+//! args                assistant message with the command-line arguments as JSON
+//! stderr <text>       line on stderr
+//! raw <line>          line on stdout as is
+//! sleep <ms>          pause
+//! hang                sleep until killed
+//! result <text>       successful final result (default: the last `say` text)
+//! error <text>        failed final result (`is_error`) and exit code 1
+//! exit <code>         exit at once, without a result
+//! ```
+//!
+//! Any other prompt is echoed back as the assistant's reply. The `system`
+//! `init` message is written before the first other stdout message, so a
+//! script that fails before saying anything produces no stream-json at all.
+//! `--version` prints a Claude Code version.
+
+use serde_json::{json, Value};
+use std::io::Write;
+use std::process::ExitCode;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// What `--version` reports
+const VERSION: &str = "2.0.14 (Claude Code)";
+
+const MODEL: &str = "claude-fake";
+
+const DIRECTIVES: &[&str] = &[
+    "session", "say", "tool", "args", "stderr", "raw", "sleep", "hang", "result", "error", "exit",
+];
+
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args.iter().any(|arg| arg == "--version") {
+        println!("{}", VERSION);
+        return ExitCode::SUCCESS;
+    }
+    let value = |flag: &str| {
+        args.iter()
+            .position(|arg| arg == flag)
+            .and_then(|i| args.get(i + 1))
+            .cloned()
+    };
+    let Some(prompt) = value("-p") else {
+        eprintln!("Error: fake-claude only runs in print mode (-p <prompt>)");
+        return ExitCode::from(2);
+    };
+
+    let session_id = value("--resume").unwrap_or_else(new_session_id);
+    let tools = value("--allowedTools")
+        .map(|tools| tools.split(',').map(str::to_string).collect())
+        .unwrap_or_default();
+    let mut run = Run {
+        args: args.clone(),
+        session_id,
+        tools,
+        started: Instant::now(),
+        initialized: false,
+        turns: 0,
+        last_text: String::new(),
+    };
+    run.execute(&script(&prompt))
+}
+
+/// Directives of a prompt, or a reply echoing it
+fn script(prompt: &str) -> Vec<(String, String)> {
+    let lines: Vec<(String, String)> = prompt
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| {
+            let line = line.trim();
+            match line.split_once(' ') {
+                Some((directive, rest)) => (directive.to_string(), rest.to_string()),
+                None => (line.to_string(), String::new()),
+            }
+        })
+        .collect();
+    let is_script = !lines.is_empty()
+        && lines
+            .iter()
+            .all(|(directive, _)| DIRECTIVES.contains(&directive.as_str()));
+    if is_script {
+        lines
+    } else {
+        vec![("say".to_string(), prompt.to_string())]
+    }
+}
+
+fn new_session_id() -> String {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_nanos())
+        .unwrap_or_default();
+    format!("fake-{:x}-{:x}", std::process::id(), nanos)
+}
+
+struct Run {
+    args: Vec<String>,
+    session_id: String,
+    tools: Vec<String>,
+    started: Instant,
+    initialized: bool,
+    turns: u32,
+    last_text: String,
+}
+
+impl Run {
+    fn execute(&mut self, script: &[(String, String)]) -> ExitCode {
+        for (directive, rest) in script {
+            match directive.as_str() {
+                "session" => self.session_id = rest.clone(),
+                "say" => self.say(rest),
+                "tool" => self.tool(rest),
+                "args" => self.say(&json!(self.args).to_string()),
+                "stderr" => {
+                    eprintln!("{}", rest);
+                    let _ = std::io::stderr().flush();
+                }
+                "raw" => line(rest),
+                "sleep" => std::thread::sleep(Duration::from_millis(rest.parse().unwrap_or(0))),
+                "hang" => loop {
+                    std::thread::sleep(Duration::from_secs(3600));
+                },
+                "result" => {
+                    self.result(false, rest);
+                    return ExitCode::SUCCESS;
+                }
+                "error" => {
+                    self.result(true, rest);
+                    return ExitCode::FAILURE;
+                }
+                "exit" => return ExitCode::from(rest.parse::<u8>().unwrap_or(1)),
+                _ => unreachable!("script() only keeps known directives"),
+            }
+        }
+        let text = self.last_text.clone();
+        self.result(false, &text);
+        ExitCode::SUCCESS
+    }
+
+    /// Write a stream-json message, after the `init` message if it has not
+    /// been written yet
+    fn message(&mut self, message: Value) {
+        if !self.initialized {
+            self.initialized = true;
+            let cwd = std::env::current_dir()
+                .map(|dir| dir.display().to_string())
+                .unwrap_or_default();
+            line(
+                &json!({
+                    "type": "system",
+                    "subtype": "init",
+                    "session_id": self.session_id,
+                    "cwd": cwd,
+                    "tools": self.tools,
+                    "model": MODEL,
+                    "permissionMode": "default",
+                })
+                .to_string(),
+            );
+        }
+        line(&message.to_string());
+    }
+
+    fn assistant(&mut self, content: Value) {
+        self.turns += 1;
+        let message = json!({
+            "type": "assistant",
+            "message": {
+                "id": format!("msg_fake_{}", self.turns),
+                "type": "message",
+                "role": "assistant",
+                "model": MODEL,
+                "content": content,
+                "stop_reason": null,
+                "usage": { "input_tokens": 10, "output_tokens": 5 },
+            },
+            "session_id": self.session_id,
+        });
+        self.message(message);
+    }
+
+    fn say(&mut self, text: &str) {
+        self.last_text = text.to_string();
+        self.assistant(json!([{ "type": "text", "text": text }]));
+    }
+
+    fn tool(&mut self, spec: &str) {
+        let (name, input) = match spec.split_once(' ') {
+            Some((name, input)) => (name, serde_json::from_str(input).unwrap_or(json!({}))),
+            None => (spec, json!({})),
+        };
+        let id = format!("toolu_fake_{}", self.turns + 1);
+        self.assistant(json!([{ "type": "tool_use", "id": id, "name": name, "input": input }]));
+        let result = json!({
+            "type": "user",
+            "message": {
+                "role": "user",
+                "content": [{ "type": "tool_result", "tool_use_id": id, "content": "ok" }],
+            },
+            "session_id": self.session_id,
+        });
+        self.message(result);
+    }
+
+    fn result(&mut self, is_error: bool, text: &str) {
+        let result = json!({
+            "type": "result",
+            "subtype": if is_error { "error_during_execution" } else { "success" },
+            "is_error": is_error,
+            "duration_ms": self.started.elapsed().as_millis() as u64,
+            "num_turns": self.turns,
+            "result": text,
+            "session_id": self.session_id,
+            "total_cost_usd": 0.0001 * f64::from(self.turns),
+            "usage": { "input_tokens": 10 * self.turns, "output_tokens": 5 * self.turns },
+        });
+        self.message(result);
+    }
+}
+
+fn line(text: &str) {
+    let mut stdout = std::io::stdout().lock();
+    let _ = writeln!(stdout, "{}", text);
+    let _ = stdout.flush();
+}
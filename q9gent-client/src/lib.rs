@@ -0,0 +1,394 @@
+//! Async Rust client for the Q9gent server
+//!
+//! ```no_run
+//! use futures::StreamExt;
+//! use q9gent_client::{Client, SpawnRequest, StreamEvent};
+//!
+//! # async fn example() -> Result<(), q9gent_client::ClientError> {
+//! let client = Client::new("http://localhost:8080");
+//! let mut events = client
+//!     .spawn(&SpawnRequest {
+//!         agent_type: "reviewer".to_string(),
+//!         prompt: "Review src/main.rs".to_string(),
+//!         create_session: true,
+//!         ..Default::default()
+//!     })
+//!     .await?;
+//!
+//! while let Some(event) = events.next().await {
+//!     if let StreamEvent::Output { data } = event? {
+//!         println!("{}", data);
+//!     }
+//! }
+//! # Ok(())
+//! # }
+//! ```
+
+pub mod sse;
+pub mod types;
+
+use std::pin::Pin;
+
+use futures::{Stream, StreamExt};
+use percent_encoding::{utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+
+pub use types::{
+    AuditList, AuditRecord, ErrorCode, ErrorResponse, HealthResponse, MessageRequest, ReadyCheck,
+    ReadyResponse, ReloadResponse, ResourceUsage, RunInfo, RunList, RunStatus, RunnerStats,
+    SessionDiff, SessionList, SessionMetadata, SessionWorktree, SpawnRequest, StreamEvent,
+    TerminateResponse, Termination,
+};
+
+/// Escaped in path segments: everything but letters, digits, `-` and `_`, so
+/// an ID can neither add segments nor be read as `.` or `..`
+const SEGMENT: &AsciiSet = &NON_ALPHANUMERIC.remove(b'-').remove(b'_');
+
+/// Errors returned by [`Client`]
+#[derive(thiserror::Error, Debug)]
+pub enum ClientError {
+    #[error("HTTP error: {0}")]
+    Http(#[from] reqwest::Error),
+
+    #[error("Server returned {status}: {message}")]
+    Api {
+        status: u16,
+        /// Error code, if the server sent a structured error body
+        code: Option<ErrorCode>,
+        message: String,
+        request_id: Option<String>,
+    },
+
+    #[error("Invalid event payload: {0}")]
+    InvalidEvent(#[from] serde_json::Error),
+
+    #[error("Bearer token contains characters not allowed in a header")]
+    InvalidToken,
+}
+
+pub type ClientResult<T> = Result<T, ClientError>;
+
+/// Stream of typed events from `/spawn` or `/message`
+pub type EventStream = Pin<Box<dyn Stream<Item = ClientResult<StreamEvent>> + Send>>;
+
+/// HTTP client for a Q9gent server
+#[derive(Debug, Clone)]
+pub struct Client {
+    base_url: String,
+    http: reqwest::Client,
+}
+
+impl Client {
+    /// Create a client for the server at `base_url` (e.g. `http://localhost:8080`)
+    pub fn new(base_url: impl Into<String>) -> Self {
+        Self::with_http_client(base_url, reqwest::Client::new())
+    }
+
+    /// Create a client using a preconfigured `reqwest::Client`
+    pub fn with_http_client(base_url: impl Into<String>, http: reqwest::Client) -> Self {
+        Self {
+            base_url: base_url.into().trim_end_matches('/').to_string(),
+            http,
+        }
+    }
+
+    /// Create a client that sends `token` as a bearer token with every
+    /// request, for servers with authentication enabled
+    pub fn with_token(base_url: impl Into<String>, token: &str) -> ClientResult<Self> {
+        let mut value = reqwest::header::HeaderValue::from_str(&format!("Bearer {}", token))
+            .map_err(|_| ClientError::InvalidToken)?;
+        value.set_sensitive(true);
+        let mut headers = reqwest::header::HeaderMap::new();
+        headers.insert(reqwest::header::AUTHORIZATION, value);
+        let http = reqwest::Client::builder()
+            .default_headers(headers)
+            .build()?;
+        Ok(Self::with_http_client(base_url, http))
+    }
+
+    /// URL of the endpoint whose path is made of `segments`
+    fn url(&self, segments: &[&str]) -> String {
+        let mut url = self.base_url.clone();
+        for segment in segments {
+            url.push('/');
+            url.extend(utf8_percent_encode(segment, SEGMENT));
+        }
+        url
+    }
+
+    /// Check server health
+    pub async fn health(&self) -> ClientResult<HealthResponse> {
+        let response = self.http.get(self.url(&["health"])).send().await?;
+        Ok(check(response).await?.json().await?)
+    }
+
+    /// Check whether the server is ready to start runs; a server that is not
+    /// ready still answers with the checks that failed
+    pub async fn ready(&self) -> ClientResult<ReadyResponse> {
+        let response = self.http.get(self.url(&["ready"])).send().await?;
+        if response.status() == reqwest::StatusCode::SERVICE_UNAVAILABLE {
+            return Ok(response.json().await?);
+        }
+        Ok(check(response).await?.json().await?)
+    }
+
+    /// Spawn a new agent and stream its events
+    pub async fn spawn(&self, request: &SpawnRequest) -> ClientResult<EventStream> {
+        let response = self
+            .http
+            .post(self.url(&["spawn"]))
+            .json(request)
+            .send()
+            .await?;
+        Ok(event_stream(check(response).await?))
+    }
+
+    /// Send a message to an existing session and stream its events
+    pub async fn message(
+        &self,
+        session_id: &str,
+        request: &MessageRequest,
+    ) -> ClientResult<EventStream> {
+        let response = self
+            .http
+            .post(self.url(&["message", session_id]))
+            .json(request)
+            .send()
+            .await?;
+        Ok(event_stream(check(response).await?))
+    }
+
+    /// Terminate the running process of a session
+    pub async fn terminate(&self, session_id: &str) -> ClientResult<TerminateResponse> {
+        let response = self
+            .http
+            .post(self.url(&["terminate", session_id]))
+            .send()
+            .await?;
+        Ok(check(response).await?.json().await?)
+    }
+
+    /// Reload the server's configuration
+    pub async fn reload(&self) -> ClientResult<ReloadResponse> {
+        let response = self
+            .http
+            .post(self.url(&["admin", "reload"]))
+            .send()
+            .await?;
+        Ok(check(response).await?.json().await?)
+    }
+
+    /// Audit records of runs active between the Unix timestamps `since` and
+    /// `until`, and whether the hash chain is intact
+    pub async fn audit(&self, since: Option<u64>, until: Option<u64>) -> ClientResult<AuditList> {
+        let mut query = Vec::new();
+        if let Some(since) = since {
+            query.push(("since", since));
+        }
+        if let Some(until) = until {
+            query.push(("until", until));
+        }
+        let response = self
+            .http
+            .get(self.url(&["admin", "audit"]))
+            .query(&query)
+            .send()
+            .await?;
+        Ok(check(response).await?.json().await?)
+    }
+
+    /// List stored sessions
+    pub async fn list_sessions(&self) -> ClientResult<Vec<SessionMetadata>> {
+        let response = self.http.get(self.url(&["sessions"])).send().await?;
+        let list: SessionList = check(response).await?.json().await?;
+        Ok(list.sessions)
+    }
+
+    /// Get a stored session
+    pub async fn get_session(&self, session_id: &str) -> ClientResult<SessionMetadata> {
+        let response = self
+            .http
+            .get(self.url(&["sessions", session_id]))
+            .send()
+            .await?;
+        Ok(check(response).await?.json().await?)
+    }
+
+    /// Delete a session and its git worktree, if any
+    pub async fn delete_session(&self, session_id: &str) -> ClientResult<()> {
+        let response = self
+            .http
+            .delete(self.url(&["sessions", session_id]))
+            .send()
+            .await?;
+        check(response).await?;
+        Ok(())
+    }
+
+    /// List recorded runs, newest first
+    pub async fn list_runs(&self) -> ClientResult<Vec<RunInfo>> {
+        let response = self.http.get(self.url(&["runs"])).send().await?;
+        let list: RunList = check(response).await?.json().await?;
+        Ok(list.runs)
+    }
+
+    /// Get a recorded run
+    pub async fn get_run(&self, run_id: &str) -> ClientResult<RunInfo> {
+        let response = self.http.get(self.url(&["runs", run_id])).send().await?;
+        Ok(check(response).await?.json().await?)
+    }
+
+    /// Get the changes made in a session's git worktree
+    pub async fn session_diff(&self, session_id: &str) -> ClientResult<SessionDiff> {
+        let response = self
+            .http
+            .get(self.url(&["sessions", session_id, "diff"]))
+            .send()
+            .await?;
+        Ok(check(response).await?.json().await?)
+    }
+}
+
+/// Turn non-2xx responses into [`ClientError::Api`]
+async fn check(response: reqwest::Response) -> ClientResult<reqwest::Response> {
+    let status = response.status();
+    if status.is_success() {
+        return Ok(response);
+    }
+
+    let body = response.text().await.unwrap_or_default();
+    let error = match serde_json::from_str::<ErrorResponse>(&body) {
+        Ok(e) => ClientError::Api {
+            status: status.as_u16(),
+            code: Some(e.code),
+            message: e.error,
+            request_id: e.request_id,
+        },
+        Err(_) => ClientError::Api {
+            status: status.as_u16(),
+            code: None,
+            message: body,
+            request_id: None,
+        },
+    };
+
+    Err(error)
+}
+
+/// Decode an SSE response body into typed events
+fn event_stream(response: reqwest::Response) -> EventStream {
+    let mut body = response.bytes_stream();
+
+    Box::pin(async_stream::stream! {
+        let mut decoder = sse::SseDecoder::new();
+
+        while let Some(chunk) = body.next().await {
+            match chunk {
+                Ok(bytes) => {
+                    for payload in decoder.push(&bytes) {
+                        yield serde_json::from_str::<StreamEvent>(&payload).map_err(ClientError::from);
+                    }
+                }
+                Err(e) => {
+                    yield Err(ClientError::from(e));
+                    break;
+                }
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_url_escapes_ids() {
+        let client = Client::new("http://localhost:8080");
+        assert_eq!(
+            client.url(&["sessions", "0a1b-2c3d", "diff"]),
+            "http://localhost:8080/sessions/0a1b-2c3d/diff"
+        );
+        assert_eq!(
+            client.url(&["sessions", "a/b?c#d"]),
+            "http://localhost:8080/sessions/a%2Fb%3Fc%23d"
+        );
+        assert_eq!(
+            client.url(&["runs", ".."]),
+            "http://localhost:8080/runs/%2E%2E"
+        );
+    }
+
+    #[test]
+    fn test_stream_event_wire_format() {
+        let event: StreamEvent =
+            serde_json::from_str(r#"{"type":"session_created","session_id":"abc"}"#).unwrap();
+        assert_eq!(
+            event,
+            StreamEvent::SessionCreated {
+                session_id: "abc".to_string()
+            }
+        );
+
+        // Older servers sent a bare completion event
+        let event: StreamEvent = serde_json::from_str(r#"{"type":"completed"}"#).unwrap();
+        assert_eq!(
+            event,
+            StreamEvent::Completed {
+                exit_code: None,
+                terminated: false,
+                workspace_retained: false,
+                redactions: 0,
+                resources: None,
+                termination: None,
+            }
+        );
+
+        let json = serde_json::to_value(StreamEvent::Completed {
+            exit_code: Some(0),
+            terminated: false,
+            workspace_retained: false,
+            redactions: 0,
+            resources: None,
+            termination: None,
+        })
+        .unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!({ "type": "completed", "exit_code": 0, "terminated": false })
+        );
+    }
+
+    #[test]
+    fn test_error_event_wire_format() {
+        let event: StreamEvent = serde_json::from_str(
+            r#"{"type":"error","code":"session_busy","error":"busy","request_id":"r1","extra":1}"#,
+        )
+        .unwrap();
+        let StreamEvent::Error(error) = event else {
+            panic!("expected error event");
+        };
+        assert_eq!(error.code, ErrorCode::SessionBusy);
+        assert_eq!(error.request_id.as_deref(), Some("r1"));
+
+        let unknown: ErrorResponse =
+            serde_json::from_str(r#"{"code":"brand_new_code","error":"x"}"#).unwrap();
+        assert_eq!(unknown.code, ErrorCode::Unknown);
+    }
+
+    #[test]
+    fn test_spawn_request_defaults() {
+        let request: SpawnRequest =
+            serde_json::from_str(r#"{"agent_type":"a","prompt":"p"}"#).unwrap();
+        assert!(request.flags.is_empty());
+        assert!(!request.create_session);
+
+        let json = serde_json::to_value(&request).unwrap();
+        assert!(json.get("resume_id").is_none());
+    }
+
+    #[test]
+    fn test_base_url_trailing_slash() {
+        let client = Client::new("http://localhost:8080/");
+        assert_eq!(client.url(&["health"]), "http://localhost:8080/health");
+    }
+}
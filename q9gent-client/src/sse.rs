@@ -0,0 +1,68 @@
+//! Minimal Server-Sent Events decoder
+
+/// Incremental SSE decoder that yields the `data` payload of each event
+///
+/// Only the parts of the format Q9gent uses are handled: `data:` fields
+/// (joined with newlines when repeated), comment lines such as keep-alives,
+/// and LF or CRLF line endings. Other fields are ignored.
+#[derive(Debug, Default)]
+pub struct SseDecoder {
+    buffer: Vec<u8>,
+    data: Vec<String>,
+}
+
+impl SseDecoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Feed a chunk of bytes and return the payloads of all completed events
+    pub fn push(&mut self, chunk: &[u8]) -> Vec<String> {
+        self.buffer.extend_from_slice(chunk);
+        let mut events = Vec::new();
+
+        while let Some(pos) = self.buffer.iter().position(|&b| b == b'\n') {
+            let mut line: Vec<u8> = self.buffer.drain(..=pos).collect();
+            line.pop();
+            if line.last() == Some(&b'\r') {
+                line.pop();
+            }
+            let line = String::from_utf8_lossy(&line);
+
+            if line.is_empty() {
+                // Blank line dispatches the event
+                if !self.data.is_empty() {
+                    events.push(self.data.join("\n"));
+                    self.data.clear();
+                }
+            } else if let Some(value) = line.strip_prefix("data:") {
+                self.data
+                    .push(value.strip_prefix(' ').unwrap_or(value).to_string());
+            }
+        }
+
+        events
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode_split_chunks() {
+        let mut decoder = SseDecoder::new();
+
+        assert!(decoder.push(b"data: {\"type\":").is_empty());
+        let events = decoder.push(b"\"completed\"}\n\n: keep-alive\n\n");
+        assert_eq!(events, vec!["{\"type\":\"completed\"}"]);
+    }
+
+    #[test]
+    fn test_decode_crlf_and_multiline() {
+        let mut decoder = SseDecoder::new();
+
+        let events = decoder.push(b"event: message\r\ndata: a\r\ndata:b\r\n\r\ndata: c\n\n");
+        assert_eq!(events, vec!["a\nb", "c"]);
+    }
+}
@@ -0,0 +1,526 @@
+//! Request, response and event types shared by the server and the client
+
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// Spawn request payload (`POST /spawn`)
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct SpawnRequest {
+    /// Type of agent (informational, recorded in session metadata)
+    pub agent_type: String,
+
+    /// The prompt to send to the agent
+    pub prompt: String,
+
+    /// Additional CLI flags passed through to claude
+    #[serde(default)]
+    pub flags: Vec<String>,
+
+    /// Allowed tools for the agent (--allowedTools flag)
+    #[serde(default)]
+    pub tools_allowed: Vec<String>,
+
+    /// System prompt to append (--append-system-prompt flag)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub system_append: Option<String>,
+
+    /// Resume session ID (--resume flag)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub resume_id: Option<String>,
+
+    /// Whether to create a new session for resumption
+    #[serde(default)]
+    pub create_session: bool,
+
+    /// Named server-side profile to run with
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub profile: Option<String>,
+
+    /// Run in this directory; must be inside one of the server's allowed roots
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub workdir: Option<String>,
+
+    /// Run in a fresh temporary workspace instead of the server's directory
+    #[serde(default)]
+    pub temp_workspace: bool,
+
+    /// Seed the temporary workspace with a copy of this directory (implies
+    /// `temp_workspace`); must be inside one of the server's allowed roots
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub workspace_template: Option<String>,
+
+    /// Extra environment variables; each must be permitted by the profile's
+    /// `request_env`
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub env: BTreeMap<String, String>,
+}
+
+/// Message request payload (`POST /message/:session_id`)
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct MessageRequest {
+    /// The prompt to send to the resumed session
+    pub prompt: String,
+
+    /// Additional CLI flags passed through to claude
+    #[serde(default)]
+    pub flags: Vec<String>,
+
+    /// Allowed tools for the agent (--allowedTools flag)
+    #[serde(default)]
+    pub tools_allowed: Vec<String>,
+
+    /// System prompt to append (--append-system-prompt flag)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub system_append: Option<String>,
+
+    /// Extra environment variables; each must be permitted by the `request_env`
+    /// of the profile the session was created with
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub env: BTreeMap<String, String>,
+}
+
+/// Health check response (`GET /health`)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct HealthResponse {
+    pub status: String,
+    pub version: String,
+
+    /// Seconds since the server started
+    #[serde(default)]
+    pub uptime_secs: u64,
+
+    /// Claude CLI executable found at startup
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub claude_path: Option<String>,
+
+    /// Output of `claude --version`, absent when it cannot be run
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub claude_version: Option<String>,
+
+    #[serde(default)]
+    pub runner: RunnerStats,
+}
+
+/// Claude processes of a server, reported by `GET /health`
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct RunnerStats {
+    /// Processes that have not yet exited
+    pub active_processes: usize,
+
+    /// Events of running processes their clients have not received yet
+    pub queued_events: usize,
+
+    /// Most processes allowed to run at once, if limited
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_runs: Option<usize>,
+}
+
+/// Readiness response (`GET /ready`), returned with 503 when not ready
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct ReadyResponse {
+    /// Whether every check passed
+    pub ready: bool,
+    pub checks: Vec<ReadyCheck>,
+}
+
+/// One readiness check: `claude`, `session_store` or `capacity`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct ReadyCheck {
+    pub name: String,
+    pub ok: bool,
+
+    /// What was found, or why the check failed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+}
+
+/// Minimal session metadata
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct SessionMetadata {
+    pub session_id: String,
+    pub agent_type: String,
+    pub created_at: u64,
+    pub last_used: u64,
+
+    /// Directory the session runs in; resumed turns reuse it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub workdir: Option<String>,
+
+    /// Profile the session was created with
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub profile: Option<String>,
+
+    /// Git worktree the session works in, for profiles with a repository
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub worktree: Option<SessionWorktree>,
+
+    /// `workdir` is a temporary workspace created for the session and is
+    /// removed along with it
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub temp_workspace: bool,
+}
+
+/// Git worktree owned by a session
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct SessionWorktree {
+    /// Repository the worktree belongs to
+    pub repo: String,
+
+    /// Branch checked out in the worktree
+    pub branch: String,
+
+    /// Commit the branch was created from
+    pub base_commit: String,
+}
+
+/// Changes made in a session's worktree (`GET /sessions/:session_id/diff`)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct SessionDiff {
+    pub session_id: String,
+    pub branch: String,
+    pub base_commit: String,
+
+    /// Unified diff from `base_commit` to the worktree, including uncommitted
+    /// and untracked files
+    pub diff: String,
+}
+
+/// Session listing (`GET /sessions`)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct SessionList {
+    pub sessions: Vec<SessionMetadata>,
+}
+
+/// State of a run in the run journal
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[serde(rename_all = "snake_case")]
+pub enum RunStatus {
+    /// The claude process is running
+    Running,
+    /// The process exited successfully
+    Completed,
+    /// The process exited with a classified failure
+    Failed,
+    /// The process was stopped through `/terminate` or by a server shutdown
+    Terminated,
+    /// The server stopped while the run was in progress; its leftover
+    /// processes were killed when the server started again
+    Interrupted,
+}
+
+/// A run recorded in the run journal (`GET /runs/:run_id`)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct RunInfo {
+    pub run_id: String,
+    pub status: RunStatus,
+    pub agent_type: String,
+
+    /// Session the run resumed or created, once known; an interrupted run can
+    /// be continued with `/message/:session_id`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub session_id: Option<String>,
+
+    /// Process ID of the claude process
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pid: Option<u32>,
+
+    /// Working directory of the run
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub workdir: Option<String>,
+
+    /// Unix timestamps in seconds
+    pub started_at: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ended_at: Option<u64>,
+
+    /// Exit code of the claude process, absent if it was killed by a signal
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub exit_code: Option<i32>,
+}
+
+/// Run listing (`GET /runs`), newest first
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct RunList {
+    pub runs: Vec<RunInfo>,
+}
+
+/// One finished run in the audit log
+///
+/// Records are chained: `hash` is the SHA-256 of the record serialized with
+/// an empty `hash`, which includes the `prev_hash` of the record before it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct AuditRecord {
+    pub run_id: String,
+
+    /// Request that started the run; absent for `q9gent run`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<String>,
+
+    /// `token:<fingerprint>` of the bearer token used, `anonymous` without
+    /// authentication, or `local:<user>` for `q9gent run`
+    pub caller: String,
+
+    /// Peer address of the connection; absent for Unix socket connections
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source: Option<String>,
+
+    pub agent_type: String,
+
+    /// Arguments claude was started with, the prompt hashed or truncated
+    pub args: Vec<String>,
+
+    pub tools_allowed: Vec<String>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub workdir: Option<String>,
+
+    /// Claude CLI executable and version found when the server started
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub claude_path: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub claude_version: Option<String>,
+
+    /// Unix timestamps in seconds
+    pub started_at: u64,
+    pub ended_at: u64,
+
+    pub status: RunStatus,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub exit_code: Option<i32>,
+
+    /// Token usage from Claude's final `result` message
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[cfg_attr(feature = "openapi", schema(value_type = Option<Object>))]
+    pub usage: Option<serde_json::Value>,
+
+    /// Cost Claude reported for the run, in US dollars
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cost_usd: Option<f64>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub resources: Option<ResourceUsage>,
+
+    /// Hash of the previous record, or 64 zeros for the first
+    pub prev_hash: String,
+    pub hash: String,
+}
+
+/// Audit log query result (`GET /admin/audit`), oldest first
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct AuditList {
+    pub records: Vec<AuditRecord>,
+
+    /// Whether every record in the files read, from the start of the first
+    /// day in range, matches its hash and links to the one before it
+    pub chain_intact: bool,
+}
+
+/// Terminate response (`POST /terminate/:session_id`)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct TerminateResponse {
+    pub message: String,
+}
+
+/// Reload response (`POST /admin/reload`)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct ReloadResponse {
+    /// Changed config keys that only take effect after a restart; their
+    /// running values are kept
+    #[serde(default)]
+    pub restart_required: Vec<String>,
+}
+
+/// Stable machine-readable error codes
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorCode {
+    /// The request body or a path parameter is malformed (400)
+    InvalidRequest,
+    /// A pass-through flag conflicts with flags Q9gent manages itself (400)
+    InvalidFlag,
+    /// The request has no accepted bearer token (401)
+    Unauthorized,
+    /// No stored session with this ID (404)
+    SessionNotFound,
+    /// No run with this ID in the run journal (404)
+    RunNotFound,
+    /// The session has no running process to terminate (404)
+    ProcessNotRunning,
+    /// The session already has a running process (409)
+    SessionBusy,
+    /// The requested working directory or template is outside the allowed roots (403)
+    WorkdirNotAllowed,
+    /// The requested profile is not configured (400)
+    UnknownProfile,
+    /// A per-request environment variable is not permitted by the profile (403)
+    EnvNotAllowed,
+    /// A secret the profile injects could not be read (500)
+    SecretUnavailable,
+    /// The session was not created with a git worktree (404)
+    NoWorktree,
+    /// The claude process could not be started (500)
+    SpawnFailed,
+    /// The claude process failed while running (500)
+    ProcessFailed,
+    /// The claude executable could not be found (503)
+    ClaudeNotFound,
+    /// Claude CLI is not logged in or its credentials were rejected (502)
+    AuthFailed,
+    /// The Anthropic API rate or usage limit was hit (429)
+    RateLimited,
+    /// Claude does not know the session being resumed (404)
+    InvalidSession,
+    /// A git command for a session worktree failed (500)
+    GitFailed,
+    /// The server is shutting down and starts no new runs (503)
+    ShuttingDown,
+    /// The configuration could not be reloaded; the previous one stays active (500)
+    ReloadFailed,
+    /// The server keeps no audit log (404)
+    AuditDisabled,
+    /// The server replays recorded runs and none matches the request (404)
+    CassetteNotFound,
+    /// Unexpected server-side failure (500)
+    InternalError,
+    /// A code introduced by a newer server
+    #[serde(other)]
+    Unknown,
+}
+
+/// Error body returned with non-2xx statuses and carried by SSE `error` events
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct ErrorResponse {
+    /// Stable error code
+    pub code: ErrorCode,
+
+    /// Human-readable message
+    pub error: String,
+
+    /// Structured context, e.g. the offending session ID or flag
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[cfg_attr(feature = "openapi", schema(value_type = Option<Object>))]
+    pub details: Option<serde_json::Value>,
+
+    /// ID of the request that produced the error (also sent as `x-request-id`)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<String>,
+}
+
+/// Event carried in the `data` field of each SSE message and each JSONL line
+/// printed by `q9gent run`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum StreamEvent {
+    /// The claude process started; always the first event
+    RunStarted {
+        /// ID of the run in the run journal (`/runs/:run_id`)
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        run_id: Option<String>,
+
+        /// Process ID of the claude process
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pid: Option<u32>,
+
+        /// Working directory of the run, absent if it inherited the server's
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        workdir: Option<String>,
+    },
+
+    /// Claude's session was recorded and can be resumed
+    SessionCreated { session_id: String },
+
+    /// One stream-json line from Claude's stdout
+    Output { data: String },
+
+    /// One line from Claude's stderr; informational, never ends the run
+    Stderr { data: String },
+
+    /// The run failed; the code classifies the failure
+    Error(ErrorResponse),
+
+    /// The server is shutting down; the run may continue for up to
+    /// `drain_timeout_secs` before it is terminated
+    ServerShutdown { drain_timeout_secs: u64 },
+
+    /// The run finished; always the last event
+    Completed {
+        /// Exit code of the claude process, absent if it was killed by a signal
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        exit_code: Option<i32>,
+
+        /// Whether the process was stopped through `/terminate` or by the
+        /// server shutting down
+        #[serde(default)]
+        terminated: bool,
+
+        /// Whether the run's temporary workspace was kept on disk
+        #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+        workspace_retained: bool,
+
+        /// Number of values redacted from `output` and `stderr` events
+        #[serde(default, skip_serializing_if = "is_zero")]
+        redactions: usize,
+
+        /// Peak memory and CPU time of the run, where the platform reports them
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        resources: Option<ResourceUsage>,
+
+        /// How a terminated run's processes were stopped
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        termination: Option<Termination>,
+    },
+}
+
+/// How the processes of a terminated run were stopped
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[serde(rename_all = "snake_case")]
+pub enum Termination {
+    /// Every process exited within the grace period after SIGTERM
+    Graceful,
+
+    /// Processes were still running after the grace period and were killed
+    /// (always the case on Windows)
+    Forced,
+}
+
+/// Resource usage of a finished run
+///
+/// Covers the whole process tree when the run had its own cgroup, otherwise
+/// the claude process and the subprocesses it waited for.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct ResourceUsage {
+    /// Peak resident memory in bytes
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub peak_memory_bytes: Option<u64>,
+
+    /// User plus system CPU time in milliseconds
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cpu_time_ms: Option<u64>,
+}
+
+fn is_zero(n: &usize) -> bool {
+    *n == 0
+}
@@ -1,10 +1,44 @@
+use q9gent_client::types::{ResourceUsage, RunInfo, RunStatus, Termination};
 use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, VecDeque};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 use tokio::io::{AsyncBufReadExt, BufReader};
-use tokio::process::{Child, Command};
-use tokio::sync::mpsc;
-use tracing::{debug, info, warn};
+use tokio::process::Command;
+use tokio::sync::{mpsc, oneshot, watch};
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, field, info, info_span, warn, Instrument, Span};
+use uuid::Uuid;
 
+use crate::audit::{AuditLog, Caller};
+use crate::cassette::{self, CassetteKey, Cassettes, Recorder, Stream};
+use crate::claude;
 use crate::error::{AppError, AppResult};
+use crate::journal::RunJournal;
+use crate::limits::{self, Cgroup, RunLimits};
+use crate::metrics::{self, Metrics};
+use crate::redact::Redactor;
+use crate::session::extract_session_id;
+use crate::telemetry;
+use crate::transcript::Transcript;
+use crate::workspace::Workspace;
+
+mod classify;
+#[cfg(unix)]
+pub mod tree;
+
+/// Number of trailing stderr lines kept for error classification
+const STDERR_TAIL_LINES: usize = 20;
+
+/// Time a terminated process group gets between SIGTERM and SIGKILL
+pub const DEFAULT_GRACE_PERIOD: Duration = Duration::from_secs(10);
+
+/// Events buffered per run before reading the process's output waits
+pub const DEFAULT_EVENT_BUFFER: usize = 100;
+
+/// How long the output of `claude --version` is reused by health checks
+const VERSION_CACHE_TTL: Duration = Duration::from_secs(60);
 
 /// Agent spawn request
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -28,16 +62,319 @@ pub struct AgentRequest {
 
     /// Resume session ID (--resume flag)
     pub resume_id: Option<String>,
+
+    /// Complete environment of the process; the server's own is not inherited
+    #[serde(default)]
+    pub env: BTreeMap<String, String>,
+
+    /// Removes secrets from stdout and stderr lines
+    #[serde(skip)]
+    pub redactor: Redactor,
+
+    /// Resource limits for the process (Linux only)
+    #[serde(skip)]
+    pub limits: RunLimits,
+
+    /// Who asked for the run, for the audit log
+    #[serde(skip)]
+    pub caller: Caller,
+}
+
+/// Event produced by a running claude process
+#[derive(Debug)]
+pub enum AgentEvent {
+    /// One non-empty stream-json line from stdout
+    Stdout(String),
+
+    /// One non-empty line from stderr
+    Stderr(String),
+
+    /// The process exited and both pipes are drained; always the last event
+    Exited(ProcessExit),
+}
+
+/// Final status of a claude process
+#[derive(Debug)]
+pub struct ProcessExit {
+    /// Exit code, `None` if the process was killed by a signal
+    pub exit_code: Option<i32>,
+
+    /// Whether the process was stopped through [`AgentRunner::terminate`]
+    pub terminated: bool,
+
+    /// Number of stdout lines read
+    pub stdout_lines: usize,
+
+    /// Number of stderr lines read
+    pub stderr_lines: usize,
+
+    /// Classified failure, `None` on success or termination
+    pub error: Option<AppError>,
+
+    /// Claude's session ID, if the process reported one
+    pub session_id: Option<String>,
+
+    /// Whether the run's temporary workspace was kept on disk
+    pub workspace_retained: bool,
+
+    /// Number of values redacted from stdout and stderr
+    pub redactions: usize,
+
+    /// Peak memory and CPU time, where the platform reports them
+    pub resources: Option<ResourceUsage>,
+
+    /// How the process tree was stopped, if it was terminated
+    pub termination: Option<Termination>,
+}
+
+/// Handle to a running claude process, used to terminate it
+#[derive(Debug)]
+pub struct RunHandle {
+    run_id: String,
+    pid: Option<u32>,
+    kill_tx: Option<oneshot::Sender<oneshot::Sender<AppResult<()>>>>,
+}
+
+impl RunHandle {
+    /// ID of the run in the run journal
+    pub fn run_id(&self) -> &str {
+        &self.run_id
+    }
+
+    /// Process ID of the claude process
+    pub fn pid(&self) -> Option<u32> {
+        self.pid
+    }
+}
+
+/// Flags Q9gent sets itself; passing them through would break the stream-json
+/// contract or session handling
+const MANAGED_FLAGS: &[&str] = &[
+    "-p",
+    "--print",
+    "--output-format",
+    "--input-format",
+    "-r",
+    "--resume",
+    "-c",
+    "--continue",
+];
+
+/// Reject pass-through flags that collide with flags Q9gent manages
+pub fn validate_flags(flags: &[String]) -> AppResult<()> {
+    for flag in flags {
+        let name = flag.split_once('=').map_or(flag.as_str(), |(name, _)| name);
+        if MANAGED_FLAGS.contains(&name) {
+            return Err(AppError::InvalidFlag(name.to_string()));
+        }
+    }
+    Ok(())
+}
+
+/// The stream-json `result` message in `line`, if it is one
+fn result_message(line: &str) -> Option<serde_json::Value> {
+    if !line.contains("\"result\"") {
+        return None;
+    }
+    serde_json::from_str::<serde_json::Value>(line)
+        .ok()
+        .filter(|value| value.get("type").and_then(|t| t.as_str()) == Some("result"))
+}
+
+fn now() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
+
+/// Map a failed `Command::spawn` to the error reported to the caller
+fn spawn_error(e: std::io::Error, cmd_exe: &str, cmd_args: &[String]) -> AppError {
+    warn!("❌ Failed to spawn Claude process: {}", e);
+    warn!("   Command: {} {:?}", cmd_exe, cmd_args);
+    if e.kind() == std::io::ErrorKind::NotFound {
+        AppError::ClaudeNotFound(format!("'{}' could not be found: {}", cmd_exe, e))
+    } else {
+        AppError::ProcessSpawnFailed(format!(
+            "Failed to spawn '{}' with args {:?}: {}",
+            cmd_exe, cmd_args, e
+        ))
+    }
 }
 
 /// Agent runner - spawns and manages claude CLI processes
 pub struct AgentRunner {
     claude_path: String,
+    transcript_dir: Option<PathBuf>,
+    cgroup_root: Option<PathBuf>,
+    grace_period: Duration,
+    event_buffer: usize,
+    journal: Option<Arc<RunJournal>>,
+    audit: Option<Arc<AuditLog>>,
+    metrics: Arc<Metrics>,
+    cassettes: Option<Cassettes>,
+
+    /// Senders of the runs' event channels, to count the events waiting for
+    /// their consumer
+    queues: std::sync::Mutex<Vec<mpsc::WeakSender<AgentEvent>>>,
+
+    /// Cancelled to terminate every running process
+    terminate_all: CancellationToken,
+
+    /// Number of processes whose monitor has not finished
+    active: Arc<watch::Sender<usize>>,
+
+    /// Last result of `claude --version` and when it was taken
+    version: tokio::sync::Mutex<Option<(Instant, Result<String, String>)>>,
+}
+
+/// Counts a run as active until its monitor task ends
+struct ActiveRun(Arc<watch::Sender<usize>>);
+
+impl ActiveRun {
+    fn new(active: Arc<watch::Sender<usize>>) -> Self {
+        active.send_modify(|count| *count += 1);
+        Self(active)
+    }
+}
+
+impl Drop for ActiveRun {
+    fn drop(&mut self) {
+        self.0.send_modify(|count| *count -= 1);
+    }
 }
 
 impl AgentRunner {
     pub fn new(claude_path: String) -> Self {
-        Self { claude_path }
+        Self {
+            claude_path,
+            transcript_dir: None,
+            cgroup_root: None,
+            grace_period: DEFAULT_GRACE_PERIOD,
+            event_buffer: DEFAULT_EVENT_BUFFER,
+            journal: None,
+            audit: None,
+            metrics: Arc::new(Metrics::new()),
+            cassettes: None,
+            queues: std::sync::Mutex::new(Vec::new()),
+            terminate_all: CancellationToken::new(),
+            active: Arc::new(watch::channel(0).0),
+            version: tokio::sync::Mutex::new(None),
+        }
+    }
+
+    /// Record every run in `journal`
+    pub fn with_journal(mut self, journal: Arc<RunJournal>) -> Self {
+        self.journal = Some(journal);
+        self
+    }
+
+    /// Append a record of every run to `audit` when it ends
+    pub fn with_audit(mut self, audit: Option<Arc<AuditLog>>) -> Self {
+        self.audit = audit;
+        self
+    }
+
+    /// Record spawns, output and run durations in `metrics`
+    pub fn with_metrics(mut self, metrics: Arc<Metrics>) -> Self {
+        self.metrics = metrics;
+        self
+    }
+
+    /// Number of events of running processes that their consumers have not
+    /// received yet
+    pub fn queued_events(&self) -> usize {
+        let mut queues = self.queues.lock().unwrap();
+        let mut queued = 0;
+        queues.retain(|queue| match queue.upgrade() {
+            Some(tx) => {
+                queued += tx.max_capacity() - tx.capacity();
+                true
+            }
+            None => false,
+        });
+        queued
+    }
+
+    /// Number of processes that have not yet exited and been reported
+    pub fn active_runs(&self) -> usize {
+        *self.active.borrow()
+    }
+
+    /// Output of `claude --version`, or why it could not be run
+    ///
+    /// The result is reused for a minute so health checks do not start a
+    /// process on every request.
+    pub async fn claude_version(&self) -> Result<String, String> {
+        let mut cached = self.version.lock().await;
+        if let Some((checked, ref result)) = *cached {
+            if checked.elapsed() < VERSION_CACHE_TTL {
+                return result.clone();
+            }
+        }
+        let result = claude::version(&self.claude_path).await;
+        if let Err(ref e) = result {
+            warn!("⚠️  Claude CLI check failed: {}", e);
+        }
+        *cached = Some((Instant::now(), result.clone()));
+        result
+    }
+
+    /// Wait until no process is running
+    pub async fn wait_idle(&self) {
+        let mut active = self.active.subscribe();
+        let _ = active.wait_for(|count| *count == 0).await;
+    }
+
+    /// Terminate every running process, as [`AgentRunner::terminate`] does,
+    /// including runs whose handle was dropped
+    ///
+    /// Processes spawned afterwards are terminated as soon as they start.
+    pub fn terminate_all(&self) {
+        info!("🛑 Terminating {} running process(es)", self.active_runs());
+        self.terminate_all.cancel();
+    }
+
+    /// Time terminated processes get to exit after SIGTERM before they are
+    /// killed (Unix only)
+    pub fn with_grace_period(mut self, grace_period: Duration) -> Self {
+        self.grace_period = grace_period;
+        self
+    }
+
+    /// Events buffered per run before reading the process's output waits for
+    /// the consumer
+    pub fn with_event_buffer(mut self, event_buffer: usize) -> Self {
+        self.event_buffer = event_buffer;
+        self
+    }
+
+    /// Create a cgroup v2 below `root` for each run whose limits ask for one
+    pub fn with_cgroup_root(mut self, root: Option<PathBuf>) -> Self {
+        self.cgroup_root = root;
+        self
+    }
+
+    /// Keep a transcript of each run's output, before redaction rules are
+    /// applied, in `dir`
+    pub fn with_transcript_dir(mut self, dir: Option<PathBuf>) -> Self {
+        self.transcript_dir = dir;
+        self
+    }
+
+    /// Record finished runs as cassettes, or serve runs from them instead of
+    /// starting claude
+    pub fn with_cassettes(mut self, cassettes: Option<Cassettes>) -> Self {
+        self.cassettes = cassettes;
+        self
+    }
+
+    /// Directory runs are replayed from, if claude is never started
+    pub fn replay_dir(&self) -> Option<&Path> {
+        match self.cassettes {
+            Some(Cassettes::Replay { ref dir, .. }) => Some(dir),
+            _ => None,
+        }
     }
 
     /// Build the command line arguments for claude
@@ -74,38 +411,58 @@ impl AgentRunner {
         args
     }
 
-    /// Prepare platform-specific command for execution
-    /// On Windows, .cmd and .bat files must be executed through cmd.exe
-    #[cfg(target_os = "windows")]
+    /// Program and arguments that run claude with `args` on this platform
     fn prepare_platform_command(&self, args: &[String]) -> (String, Vec<String>) {
-        // Check if we're trying to execute a .cmd or .bat file
-        let needs_cmd_wrapper = self.claude_path.to_lowercase().ends_with(".cmd")
-            || self.claude_path.to_lowercase().ends_with(".bat");
-
-        if needs_cmd_wrapper {
-            debug!("🪟 Windows: Detected .cmd/.bat file, using cmd.exe wrapper");
-            let mut cmd_args = vec!["/c".to_string(), self.claude_path.clone()];
-            cmd_args.extend_from_slice(args);
-            ("cmd.exe".to_string(), cmd_args)
-        } else {
-            debug!("🪟 Windows: Direct execution of {}", self.claude_path);
-            (self.claude_path.clone(), args.to_vec())
-        }
+        claude::command_line(&self.claude_path, args)
     }
 
-    /// Prepare platform-specific command for execution
-    /// On Unix-like systems, execute the command directly
-    #[cfg(not(target_os = "windows"))]
-    fn prepare_platform_command(&self, args: &[String]) -> (String, Vec<String>) {
-        debug!("🐧 Unix: Direct execution of {}", self.claude_path);
-        (self.claude_path.clone(), args.to_vec())
+    /// Spawn a claude process and return a handle plus a channel of its events
+    ///
+    /// The channel yields stdout and stderr lines as they arrive and ends with
+    /// a single [`AgentEvent::Exited`] once the process has exited and both
+    /// pipes are drained.
+    ///
+    /// The process runs in `workspace` if one is given; the workspace's
+    /// retention policy is applied after the process exits, before
+    /// [`AgentEvent::Exited`] is sent.
+    ///
+    /// The run is traced as a `spawn` span, with `first_output` and
+    /// `process_exit` spans that outlive it, all children of the current span.
+    /// The process gets `TRACEPARENT` continuing the trace from `spawn`. The
+    /// run ID, PID and session ID are recorded on the current span as they
+    /// become known, and the output readers log within it.
+    pub async fn spawn(
+        &self,
+        request: AgentRequest,
+        workspace: Option<Workspace>,
+    ) -> AppResult<(RunHandle, mpsc::Receiver<AgentEvent>)> {
+        let run_span = Span::current();
+        let span = info_span!(
+            "spawn",
+            agent_type = %request.agent_type,
+            run_id = field::Empty,
+            pid = field::Empty,
+        );
+        self.start(request, workspace, run_span)
+            .instrument(span)
+            .await
     }
 
-    /// Spawn a claude process and return a channel to receive JSONL output
-    pub async fn spawn(
+    async fn start(
         &self,
         request: AgentRequest,
-    ) -> AppResult<(Child, mpsc::Receiver<AppResult<String>>)> {
+        workspace: Option<Workspace>,
+        run_span: Span,
+    ) -> AppResult<(RunHandle, mpsc::Receiver<AgentEvent>)> {
+        if let Err(e) = validate_flags(&request.flags) {
+            if let Some(workspace) = workspace {
+                workspace.discard().await;
+            }
+            return Err(e);
+        }
+        if let Some(Cassettes::Replay { ref dir, speed }) = self.cassettes {
+            return self.replay(request, workspace, run_span, dir, speed).await;
+        }
         let args = self.build_command(&request);
 
         info!("🔨 Building Claude command - {} args", args.len());
@@ -116,13 +473,27 @@ impl AgentRunner {
         info!("📋 Executing: {} {:?}", cmd_exe, cmd_args);
         debug!("Full command: {} {}", cmd_exe, cmd_args.join(" "));
 
+        debug!("Environment: {:?}", request.env.keys().collect::<Vec<_>>());
+
         let mut command = Command::new(&cmd_exe);
         command
             .args(&cmd_args)
+            .env_clear()
+            .envs(&request.env)
+            .envs(telemetry::trace_env(&Span::current()))
             .stdout(std::process::Stdio::piped())
             .stderr(std::process::Stdio::piped())
             .kill_on_drop(true);
 
+        // A group of its own lets termination reach every descendant
+        #[cfg(unix)]
+        command.process_group(0);
+
+        if let Some(ref workspace) = workspace {
+            debug!("📁 Working directory: {}", workspace.path().display());
+            command.current_dir(workspace.path());
+        }
+
         // On Windows, set environment variables to prevent Node.js buffering issues
         #[cfg(target_os = "windows")]
         {
@@ -133,49 +504,197 @@ impl AgentRunner {
             debug!("🪟 Windows: Set Node.js environment variables to prevent buffering");
         }
 
-        let mut child = command.spawn().map_err(|e| {
-            warn!("❌ Failed to spawn Claude process: {}", e);
-            warn!("   Command: {} {:?}", cmd_exe, cmd_args);
-            AppError::ProcessSpawnFailed(format!(
-                "Failed to spawn '{}' with args {:?}: {}",
-                cmd_exe, cmd_args, e
-            ))
-        })?;
+        let cgroup = match (request.limits.cgroup.as_ref(), self.cgroup_root.as_deref()) {
+            (Some(cgroup_limits), Some(root)) => match Cgroup::create(root, cgroup_limits) {
+                Ok(cgroup) => {
+                    info!("📦 Running in cgroup {}", cgroup.path().display());
+                    Some(cgroup)
+                }
+                Err(e) => {
+                    if let Some(workspace) = workspace {
+                        workspace.discard().await;
+                    }
+                    self.metrics.spawn_failed(&request.agent_type);
+                    return Err(AppError::ProcessSpawnFailed(format!(
+                        "could not create cgroup in {}: {}",
+                        root.display(),
+                        e
+                    )));
+                }
+            },
+            (Some(_), None) => {
+                warn!("⚠️  Cgroup limits ignored: no --cgroup-root configured");
+                None
+            }
+            (None, _) => None,
+        };
+        limits::apply(&mut command, &request.limits, cgroup.as_ref());
+
+        let started = Instant::now();
+        let mut child = match command.spawn() {
+            Ok(child) => child,
+            Err(e) => {
+                if let Some(workspace) = workspace {
+                    workspace.discard().await;
+                }
+                if let Some(cgroup) = cgroup {
+                    cgroup.remove();
+                }
+                self.metrics.spawn_failed(&request.agent_type);
+                return Err(spawn_error(e, &cmd_exe, &cmd_args));
+            }
+        };
+
+        // Taken before the run is journaled, so a failure leaves nothing behind
+        let (Some(stdout), Some(stderr)) = (child.stdout.take(), child.stderr.take()) else {
+            if let Err(e) = child.kill().await {
+                warn!("Failed to kill process: {}", e);
+            }
+            if let Some(workspace) = workspace {
+                workspace.discard().await;
+            }
+            if let Some(cgroup) = cgroup {
+                cgroup.remove();
+            }
+            self.metrics.spawn_failed(&request.agent_type);
+            return Err(AppError::ProcessSpawnFailed(
+                "Failed to capture stdout and stderr".to_string(),
+            ));
+        };
 
         let pid = child.id();
         info!("✓ Claude process spawned - PID: {:?}", pid);
+        let recorder = match self.cassettes {
+            Some(Cassettes::Record(ref dir)) => Some(Arc::new(Recorder::new(dir, &request))),
+            _ => None,
+        };
 
-        let stdout = child
-            .stdout
-            .take()
-            .ok_or_else(|| AppError::ProcessSpawnFailed("Failed to capture stdout".to_string()))?;
+        let run_id = Uuid::new_v4().to_string();
+        for span in [&Span::current(), &run_span] {
+            span.record("run_id", run_id.as_str());
+        }
+        for span in [&Span::current(), &run_span] {
+            span.record("pid", pid);
+        }
+        let first_output_span = info_span!(parent: &run_span, "first_output", run_id = %run_id);
+        let exit_span = info_span!(
+            parent: &run_span,
+            "process_exit",
+            run_id = %run_id,
+            exit_code = field::Empty,
+            outcome = field::Empty,
+        );
+        let workdir = workspace
+            .as_ref()
+            .map(|w| w.path().to_string_lossy().to_string());
+        let audit = self.audit.clone().map(|audit| {
+            let record = audit.begin(&request, &args, &run_id, workdir.clone());
+            (audit, record)
+        });
+        let journal = self.journal.clone();
+        if let Some(ref journal) = journal {
+            journal
+                .start(RunInfo {
+                    run_id: run_id.clone(),
+                    status: RunStatus::Running,
+                    agent_type: request.agent_type.clone(),
+                    session_id: request.resume_id.clone(),
+                    pid,
+                    workdir,
+                    started_at: now(),
+                    ended_at: None,
+                    exit_code: None,
+                })
+                .await;
+        }
 
-        let stderr = child
-            .stderr
-            .take()
-            .ok_or_else(|| AppError::ProcessSpawnFailed("Failed to capture stderr".to_string()))?;
+        let (tx, rx) = mpsc::channel(self.event_buffer);
+        self.queues.lock().unwrap().push(tx.downgrade());
 
-        let (tx, rx) = mpsc::channel(100);
+        let transcript = match self.transcript_dir {
+            Some(ref dir) => match Transcript::create(dir).await {
+                Ok(transcript) => {
+                    info!(
+                        "📝 Transcript for PID {:?}: {}",
+                        pid,
+                        transcript.path().display()
+                    );
+                    Some(Arc::new(transcript))
+                }
+                Err(e) => {
+                    warn!(
+                        "⚠️  Failed to create transcript in {}: {}",
+                        dir.display(),
+                        e
+                    );
+                    None
+                }
+            },
+            None => None,
+        };
+
+        // Secrets are redacted before lines are logged, classified or sent;
+        // the transcript sees lines before the redaction rules are applied
+        let redactor = request.redactor;
+        let stderr_redactor = redactor.clone();
+        let stdout_transcript = transcript.clone();
+        let stderr_transcript = transcript.clone();
+        let stdout_recorder = recorder.clone();
+        let stderr_recorder = recorder.clone();
 
         // Spawn task to read stdout line-by-line
-        let tx_clone = tx.clone();
-        tokio::spawn(async move {
+        let tx_stdout = tx.clone();
+        let stdout_journal = journal.clone();
+        let stdout_run_id = run_id.clone();
+        let stdout_metrics = self.metrics.clone();
+        let stdout_reader = async move {
             let reader = BufReader::new(stdout);
             let mut lines = reader.lines();
+            let mut first_output_span = Some(first_output_span);
             let mut line_count = 0;
+            let mut last_result = None;
+            let mut session_id = None;
+            let mut redactions = 0;
 
             debug!("📖 Started stdout reader task");
             while let Ok(Some(line)) = lines.next_line().await {
                 if !line.is_empty() {
+                    let (line, secrets) = redactor.redact_secrets(line);
+                    if let Some(ref transcript) = stdout_transcript {
+                        transcript.record("stdout", &line).await;
+                    }
+                    let (line, matches) = redactor.redact_patterns(line);
+                    redactions += secrets + matches;
+                    if let Some(ref recorder) = stdout_recorder {
+                        recorder.record(Stream::Stdout, &line);
+                    }
                     line_count += 1;
+                    stdout_metrics.output_line();
                     if line_count == 1 {
                         info!("📥 First line from Claude stdout");
+                        stdout_metrics.first_line(started.elapsed());
+                        first_output_span.take();
                     }
                     debug!("Claude stdout line {}: {} chars", line_count, line.len());
 
+                    if session_id.is_none() && line.contains("\"session_id\"") {
+                        session_id = extract_session_id(&line);
+                        if let Some(ref sid) = session_id {
+                            Span::current().record("session_id", sid.as_str());
+                        }
+                        if let (Some(ref sid), Some(ref journal)) = (&session_id, &stdout_journal) {
+                            journal.set_session(&stdout_run_id, sid).await;
+                        }
+                    }
+
+                    // Keep the final `result` message for error classification
+                    if let Some(result) = result_message(&line) {
+                        last_result = Some(result);
+                    }
+
                     // Try to send, but don't stop reading if channel is closed
                     // This prevents EPIPE errors on Windows when client disconnects
-                    if tx_clone.send(Ok(line)).await.is_err() {
+                    if tx_stdout.send(AgentEvent::Stdout(line)).await.is_err() {
                         debug!("Channel closed, but continuing to drain stdout to prevent EPIPE");
                         // Continue reading to EOF to avoid breaking the pipe
                     }
@@ -186,75 +705,510 @@ impl AgentRunner {
             } else {
                 info!("📊 Stdout reader finished - {} lines read", line_count);
             }
-        });
+            (line_count, last_result, session_id, redactions)
+        };
+        let stdout_task = tokio::spawn(stdout_reader.instrument(run_span.clone()));
 
-        // Spawn task to log stderr
-        let tx_error = tx.clone();
-        tokio::spawn(async move {
+        // Spawn task to forward stderr as its own event channel
+        let tx_stderr = tx.clone();
+        let stderr_metrics = self.metrics.clone();
+        let stderr_reader = async move {
             let reader = BufReader::new(stderr);
             let mut lines = reader.lines();
             let mut stderr_count = 0;
+            let mut tail = VecDeque::with_capacity(STDERR_TAIL_LINES);
+            let mut redactions = 0;
 
             debug!("📖 Started stderr reader task");
             while let Ok(Some(line)) = lines.next_line().await {
                 if !line.is_empty() {
+                    let (line, secrets) = stderr_redactor.redact_secrets(line);
+                    if let Some(ref transcript) = stderr_transcript {
+                        transcript.record("stderr", &line).await;
+                    }
+                    let (line, matches) = stderr_redactor.redact_patterns(line);
+                    redactions += secrets + matches;
+                    if let Some(ref recorder) = stderr_recorder {
+                        recorder.record(Stream::Stderr, &line);
+                    }
                     stderr_count += 1;
-                    // Log at ERROR level for visibility
-                    tracing::error!("🔴 Claude stderr [{}]: {}", stderr_count, line);
-
-                    // If this looks like a critical error, send it to the output channel too
-                    if line.contains("Error")
-                        || line.contains("error")
-                        || line.contains("failed")
-                        || line.contains("Failed")
-                        || line.contains("cannot")
-                        || line.contains("Cannot")
-                    {
-                        let _ = tx_error
-                            .send(Err(AppError::ProcessExecutionError(format!(
-                                "Claude CLI error: {}",
-                                line
-                            ))))
-                            .await;
+                    stderr_metrics.stderr_line();
+                    warn!("🟠 Claude stderr [{}]: {}", stderr_count, line);
+
+                    if tail.len() == STDERR_TAIL_LINES {
+                        tail.pop_front();
                     }
+                    tail.push_back(line.clone());
+
+                    let _ = tx_stderr.send(AgentEvent::Stderr(line)).await;
                 }
             }
-            if stderr_count > 0 {
-                warn!(
-                    "⚠️  Stderr reader finished - {} error lines logged",
-                    stderr_count
+            debug!("Stderr reader finished - {} lines", stderr_count);
+            (stderr_count, Vec::from(tail), redactions)
+        };
+        let stderr_task = tokio::spawn(stderr_reader.instrument(run_span.clone()));
+
+        // Spawn task that owns the child, handles termination requests and
+        // reports the classified exit once both pipes are drained
+        let (kill_tx, mut kill_rx) = oneshot::channel::<oneshot::Sender<AppResult<()>>>();
+        let grace_period = self.grace_period;
+        // Only process groups have a grace period between SIGTERM and SIGKILL
+        #[cfg(not(unix))]
+        let _ = grace_period;
+        let terminate_all = self.terminate_all.clone();
+        let active = ActiveRun::new(self.active.clone());
+        let monitor_run_id = run_id.clone();
+        let metrics = self.metrics.clone();
+        let agent_type = request.agent_type.clone();
+        let monitor = async move {
+            let _active = active;
+            let mut terminated = false;
+
+            // Wait for the exit without reaping so the resource usage of the
+            // process can still be read, then stop what is left of its group
+            #[cfg(unix)]
+            let (status, mut resources, termination) = {
+                let mut tree = tree::ProcessTree::new(pid, cgroup.as_ref(), grace_period);
+                let exited = async {
+                    let pid = pid?;
+                    tokio::task::spawn_blocking(move || limits::wait_exited(pid))
+                        .await
+                        .ok()?
+                        .map_err(|e| warn!("Failed to wait for process {}: {}", pid, e))
+                        .ok()
+                        .flatten()
+                };
+                tokio::pin!(exited);
+                let mut pending_ack = None;
+                // The receiver must not be polled again once it has resolved,
+                // including when the handle is dropped without terminating
+                let mut kill_resolved = false;
+                let resources = loop {
+                    tokio::select! {
+                        usage = &mut exited => break usage,
+                        request = &mut kill_rx, if !kill_resolved => {
+                            kill_resolved = true;
+                            let Ok(ack) = request else { continue };
+                            terminated = true;
+                            match tree.terminate() {
+                                Ok(()) => pending_ack = Some(ack),
+                                Err(e) => {
+                                    warn!("Failed to terminate process: {}", e);
+                                    let _ = ack.send(Err(AppError::ProcessExecutionError(e.to_string())));
+                                }
+                            }
+                        }
+                        () = terminate_all.cancelled(), if !terminated => {
+                            terminated = true;
+                            if let Err(e) = tree.terminate() {
+                                warn!("Failed to terminate process: {}", e);
+                            }
+                        }
+                        () = tree.grace_expired() => tree.kill(),
+                    }
+                };
+                let status = child.wait().await;
+                tree.reap().await;
+                if let Some(ack) = pending_ack {
+                    let _ = ack.send(Ok(()));
+                }
+                (status, resources, tree.termination())
+            };
+
+            #[cfg(not(unix))]
+            let (status, mut resources, termination) = (
+                tokio::select! {
+                    status = child.wait() => status,
+                    Ok(ack) = &mut kill_rx => {
+                        terminated = true;
+                        let killed = child.kill().await.map_err(|e| {
+                            warn!("Failed to kill process: {}", e);
+                            AppError::ProcessExecutionError(e.to_string())
+                        });
+                        let _ = ack.send(killed);
+                        child.wait().await
+                    }
+                    () = terminate_all.cancelled() => {
+                        terminated = true;
+                        if let Err(e) = child.kill().await {
+                            warn!("Failed to kill process: {}", e);
+                        }
+                        child.wait().await
+                    }
+                },
+                None,
+                Some(Termination::Forced),
+            );
+
+            // Leftovers of a run that exited by itself are not a termination
+            let termination = termination.filter(|_| terminated);
+            if let Some(termination) = termination {
+                info!("🛑 Process {:?} stopped: {:?}", pid, termination);
+            }
+
+            // A cgroup also accounts for descendants claude did not wait for
+            if let Some(cgroup) = cgroup {
+                resources = Some(cgroup.usage());
+                cgroup.remove();
+            }
+            if let Some(ref usage) = resources {
+                debug!(
+                    "Process {:?} peak memory: {:?} bytes, CPU time: {:?} ms",
+                    pid, usage.peak_memory_bytes, usage.cpu_time_ms
+                );
+            }
+
+            let (stdout_lines, last_result, session_id, stdout_redactions) =
+                stdout_task.await.unwrap_or_default();
+            let (stderr_lines, stderr_tail, stderr_redactions) =
+                stderr_task.await.unwrap_or_default();
+            let redactions = stdout_redactions + stderr_redactions;
+            if let Some(transcript) = transcript {
+                transcript.finish().await;
+            }
+            // A terminated run is incomplete and would replay as a failure
+            if let Some(recorder) = recorder.filter(|_| !terminated) {
+                recorder
+                    .finish(status.as_ref().ok().and_then(|s| s.code()))
+                    .await;
+            }
+            if redactions > 0 {
+                info!(
+                    "🔏 Redacted {} value(s) from process {:?} output",
+                    redactions, pid
                 );
+            }
+
+            let mut exit = match status {
+                Ok(status) => {
+                    if status.success() {
+                        info!(
+                            "✅ Process {:?} exited successfully with status: {}",
+                            pid, status
+                        );
+                    } else {
+                        warn!(
+                            "⚠️  Process {:?} exited with non-zero status: {}",
+                            pid, status
+                        );
+                    }
+                    let error = if terminated {
+                        None
+                    } else {
+                        classify::classify_exit(status, &stderr_tail, last_result.as_ref())
+                    };
+                    ProcessExit {
+                        exit_code: status.code(),
+                        terminated,
+                        stdout_lines,
+                        stderr_lines,
+                        error,
+                        session_id,
+                        workspace_retained: false,
+                        redactions,
+                        resources: resources.clone(),
+                        termination,
+                    }
+                }
+                Err(e) => {
+                    tracing::error!("❌ Failed to wait for process {:?}: {}", pid, e);
+                    ProcessExit {
+                        exit_code: None,
+                        terminated,
+                        stdout_lines,
+                        stderr_lines,
+                        error: Some(AppError::ProcessExecutionError(e.to_string())),
+                        session_id,
+                        workspace_retained: false,
+                        redactions,
+                        resources: resources.clone(),
+                        termination,
+                    }
+                }
+            };
+
+            if let Some(workspace) = workspace {
+                exit.workspace_retained = workspace
+                    .finish(exit.error.is_some(), exit.session_id.is_some())
+                    .await;
+            }
+
+            let status = if exit.terminated {
+                RunStatus::Terminated
+            } else if exit.error.is_some() {
+                RunStatus::Failed
             } else {
-                debug!("Stderr reader finished - no errors");
+                RunStatus::Completed
+            };
+            metrics.run_finished(&agent_type, status, started.elapsed());
+            let span = Span::current();
+            if let Some(code) = exit.exit_code {
+                span.record("exit_code", code);
+            }
+            span.record("outcome", metrics::outcome(status));
+            if let Some(journal) = journal {
+                journal
+                    .finish(
+                        &monitor_run_id,
+                        status,
+                        exit.exit_code,
+                        exit.session_id.clone(),
+                    )
+                    .await;
+            }
+            if let Some((audit, record)) = audit {
+                audit
+                    .finish(
+                        record,
+                        status,
+                        exit.exit_code,
+                        last_result.as_ref(),
+                        exit.resources.clone(),
+                    )
+                    .await;
             }
-        });
 
-        // Spawn task to monitor process exit status
-        let pid_monitor = pid;
-        let _tx_exit = tx.clone();
-        tokio::spawn(async move {
-            // Give the process a moment to start producing output
-            tokio::time::sleep(tokio::time::Duration::from_secs(5)).await;
+            let _ = tx.send(AgentEvent::Exited(exit)).await;
+            debug!("Process {:?} monitoring task completed", pid);
+        };
+        tokio::spawn(monitor.instrument(exit_span));
 
-            debug!(
-                "⏰ Process monitor: Checking if PID {:?} is still alive",
-                pid_monitor
-            );
-            // If we reach here and the channel is closed without sending data,
-            // it means the process likely failed silently
+        Ok((
+            RunHandle {
+                run_id,
+                pid,
+                kill_tx: Some(kill_tx),
+            },
+            rx,
+        ))
+    }
+
+    /// Serve a run from the cassette recorded for `request` instead of
+    /// starting claude
+    ///
+    /// Lines are sent `speed` times faster than recorded and pass through
+    /// the request's redactor like live output. The run is journaled and
+    /// counted like a process, but not audited since nothing is executed.
+    async fn replay(
+        &self,
+        request: AgentRequest,
+        workspace: Option<Workspace>,
+        run_span: Span,
+        dir: &Path,
+        speed: f64,
+    ) -> AppResult<(RunHandle, mpsc::Receiver<AgentEvent>)> {
+        let key = CassetteKey::new(&request);
+        let cassette = match cassette::load(dir, &key).await {
+            Ok(cassette) => cassette,
+            Err(e) => {
+                if let Some(workspace) = workspace {
+                    workspace.discard().await;
+                }
+                self.metrics.spawn_failed(&request.agent_type);
+                return Err(e);
+            }
+        };
+        info!(
+            "📼 Replaying cassette {} - {} lines",
+            key.file_name(),
+            cassette.lines.len()
+        );
+
+        let run_id = Uuid::new_v4().to_string();
+        for span in [&Span::current(), &run_span] {
+            span.record("run_id", run_id.as_str());
+        }
+        let journal = self.journal.clone();
+        if let Some(ref journal) = journal {
+            journal
+                .start(RunInfo {
+                    run_id: run_id.clone(),
+                    status: RunStatus::Running,
+                    agent_type: request.agent_type.clone(),
+                    session_id: request.resume_id.clone(),
+                    pid: None,
+                    workdir: workspace
+                        .as_ref()
+                        .map(|w| w.path().to_string_lossy().to_string()),
+                    started_at: now(),
+                    ended_at: None,
+                    exit_code: None,
+                })
+                .await;
+        }
+
+        let (tx, rx) = mpsc::channel(self.event_buffer);
+        self.queues.lock().unwrap().push(tx.downgrade());
+
+        // Terminating a replay needs no process group; the handle just stops
+        // the replay
+        let stop = self.terminate_all.child_token();
+        let (kill_tx, kill_rx) = oneshot::channel::<oneshot::Sender<AppResult<()>>>();
+        let kill_stop = stop.clone();
+        tokio::spawn(async move {
+            if let Ok(ack) = kill_rx.await {
+                kill_stop.cancel();
+                let _ = ack.send(Ok(()));
+            }
         });
 
-        Ok((child, rx))
+        let active = ActiveRun::new(self.active.clone());
+        let replay_run_id = run_id.clone();
+        let metrics = self.metrics.clone();
+        let agent_type = request.agent_type.clone();
+        let redactor = request.redactor;
+        let exit_span = info_span!(
+            parent: &run_span,
+            "process_exit",
+            run_id = %run_id,
+            exit_code = field::Empty,
+            outcome = field::Empty,
+        );
+        let replay = async move {
+            let _active = active;
+            let started = Instant::now();
+            let mut terminated = false;
+            let mut stdout_lines = 0;
+            let mut stderr_lines = 0;
+            let mut tail = VecDeque::with_capacity(STDERR_TAIL_LINES);
+            let mut last_result = None;
+            let mut session_id = None;
+            let mut redactions = 0;
+
+            // Each line waits for its recorded time, then the exit waits for
+            // the recorded duration
+            let steps = cassette.lines.into_iter().map(Some).chain([None]);
+            for step in steps {
+                let at_ms = step
+                    .as_ref()
+                    .map_or(cassette.duration_ms, |line| line.at_ms);
+                let due = started + cassette::replay_delay(at_ms, speed);
+                tokio::select! {
+                    () = tokio::time::sleep_until(due.into()) => {}
+                    () = stop.cancelled() => {
+                        terminated = true;
+                        break;
+                    }
+                }
+                let Some(recorded) = step else { break };
+
+                let (line, secrets) = redactor.redact_secrets(recorded.line);
+                let (line, matches) = redactor.redact_patterns(line);
+                redactions += secrets + matches;
+                let event = match recorded.stream {
+                    Stream::Stdout => {
+                        stdout_lines += 1;
+                        metrics.output_line();
+                        if stdout_lines == 1 {
+                            metrics.first_line(started.elapsed());
+                        }
+                        if session_id.is_none() && line.contains("\"session_id\"") {
+                            session_id = extract_session_id(&line);
+                            if let (Some(ref sid), Some(ref journal)) = (&session_id, &journal) {
+                                journal.set_session(&replay_run_id, sid).await;
+                            }
+                        }
+                        if let Some(result) = result_message(&line) {
+                            last_result = Some(result);
+                        }
+                        AgentEvent::Stdout(line)
+                    }
+                    Stream::Stderr => {
+                        stderr_lines += 1;
+                        metrics.stderr_line();
+                        if tail.len() == STDERR_TAIL_LINES {
+                            tail.pop_front();
+                        }
+                        tail.push_back(line.clone());
+                        AgentEvent::Stderr(line)
+                    }
+                };
+                let _ = tx.send(event).await;
+            }
+
+            let (exit_code, error, termination) = if terminated {
+                info!("🛑 Replay of run {} stopped", replay_run_id);
+                (None, None, Some(Termination::Graceful))
+            } else {
+                let tail = Vec::from(tail);
+                let error =
+                    classify::classify_exit_code(cassette.exit_code, &tail, last_result.as_ref());
+                (cassette.exit_code, error, None)
+            };
+            let mut exit = ProcessExit {
+                exit_code,
+                terminated,
+                stdout_lines,
+                stderr_lines,
+                error,
+                session_id,
+                workspace_retained: false,
+                redactions,
+                resources: None,
+                termination,
+            };
+            if let Some(workspace) = workspace {
+                exit.workspace_retained = workspace
+                    .finish(exit.error.is_some(), exit.session_id.is_some())
+                    .await;
+            }
+
+            let status = if exit.terminated {
+                RunStatus::Terminated
+            } else if exit.error.is_some() {
+                RunStatus::Failed
+            } else {
+                RunStatus::Completed
+            };
+            metrics.run_finished(&agent_type, status, started.elapsed());
+            let span = Span::current();
+            if let Some(code) = exit.exit_code {
+                span.record("exit_code", code);
+            }
+            span.record("outcome", metrics::outcome(status));
+            if let Some(journal) = journal {
+                journal
+                    .finish(
+                        &replay_run_id,
+                        status,
+                        exit.exit_code,
+                        exit.session_id.clone(),
+                    )
+                    .await;
+            }
+
+            let _ = tx.send(AgentEvent::Exited(exit)).await;
+        };
+        tokio::spawn(replay.instrument(exit_span));
+
+        Ok((
+            RunHandle {
+                run_id,
+                pid: None,
+                kill_tx: Some(kill_tx),
+            },
+            rx,
+        ))
     }
 
     /// Terminate a running process
-    pub async fn terminate(mut child: Child) -> AppResult<()> {
-        let pid = child.id();
-        info!("🛑 Terminating Claude process - PID: {:?}", pid);
-        child.kill().await.map_err(|e| {
-            warn!("Failed to kill process: {}", e);
-            AppError::ProcessExecutionError(e.to_string())
-        })?;
+    pub async fn terminate(mut handle: RunHandle) -> AppResult<()> {
+        info!("🛑 Terminating Claude process - PID: {:?}", handle.pid);
+        let (ack_tx, ack_rx) = oneshot::channel();
+
+        let requested = handle
+            .kill_tx
+            .take()
+            .map(|kill_tx| kill_tx.send(ack_tx).is_ok())
+            .unwrap_or(false);
+
+        // If the monitor is gone the process has already exited
+        if requested {
+            if let Ok(result) = ack_rx.await {
+                result?;
+            }
+        }
+
         info!("✓ Process terminated");
         Ok(())
     }
@@ -275,6 +1229,10 @@ mod tests {
             tools_allowed: vec!["read_file".to_string(), "write_file".to_string()],
             system_append: Some("You are a test agent".to_string()),
             resume_id: Some("session-123".to_string()),
+            env: BTreeMap::new(),
+            redactor: Redactor::default(),
+            limits: RunLimits::default(),
+            caller: Caller::default(),
         };
 
         let args = runner.build_command(&request);
@@ -291,6 +1249,16 @@ mod tests {
         assert!(args.contains(&"session-123".to_string()));
     }
 
+    #[test]
+    fn test_validate_flags() {
+        assert!(validate_flags(&["--model".to_string(), "sonnet".to_string()]).is_ok());
+        assert!(matches!(
+            validate_flags(&["--output-format=json".to_string()]),
+            Err(AppError::InvalidFlag(flag)) if flag == "--output-format"
+        ));
+        assert!(validate_flags(&["-p".to_string()]).is_err());
+    }
+
     #[test]
     #[cfg(target_os = "windows")]
     fn test_windows_cmd_wrapper() {
@@ -330,6 +1298,442 @@ mod tests {
         assert_eq!(cmd_args[0], "-p");
     }
 
+    /// Write an executable shell script standing in for the claude CLI
+    #[cfg(unix)]
+    fn fake_claude(dir: &std::path::Path, body: &str) -> String {
+        use std::os::unix::fs::PermissionsExt;
+
+        let path = dir.join("claude");
+        std::fs::write(&path, format!("#!/bin/sh\n{}\n", body)).unwrap();
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755)).unwrap();
+        path.to_string_lossy().to_string()
+    }
+
+    fn request(prompt: &str) -> AgentRequest {
+        AgentRequest {
+            agent_type: "test".to_string(),
+            prompt: prompt.to_string(),
+            flags: vec![],
+            tools_allowed: vec![],
+            system_append: None,
+            resume_id: None,
+            env: BTreeMap::new(),
+            redactor: Redactor::default(),
+            limits: RunLimits::default(),
+            caller: Caller::default(),
+        }
+    }
+
+    #[tokio::test]
+    #[cfg(unix)]
+    async fn test_stderr_does_not_end_stream() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let claude = fake_claude(
+            temp_dir.path(),
+            r#"echo '{"type":"system"}'
+echo 'error: optional telemetry failed' >&2
+echo '{"type":"result","is_error":false}'"#,
+        );
+        let runner = AgentRunner::new(claude);
+
+        let (_handle, mut rx) = runner.spawn(request("hi"), None).await.unwrap();
+        let mut stdout = 0;
+        let mut stderr = 0;
+        let mut exit = None;
+        while let Some(event) = rx.recv().await {
+            match event {
+                AgentEvent::Stdout(_) => stdout += 1,
+                AgentEvent::Stderr(_) => stderr += 1,
+                AgentEvent::Exited(e) => exit = Some(e),
+            }
+        }
+
+        let exit = exit.expect("exit event");
+        assert_eq!(stdout, 2);
+        assert_eq!(stderr, 1);
+        assert_eq!(exit.exit_code, Some(0));
+        assert!(exit.error.is_none());
+    }
+
+    #[tokio::test]
+    #[cfg(unix)]
+    async fn test_nonzero_exit_is_classified() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let claude = fake_claude(
+            temp_dir.path(),
+            "echo 'Invalid API key · Please run /login' >&2; exit 1",
+        );
+        let runner = AgentRunner::new(claude);
+
+        let (_handle, mut rx) = runner.spawn(request("hi"), None).await.unwrap();
+        let mut exit = None;
+        while let Some(event) = rx.recv().await {
+            if let AgentEvent::Exited(e) = event {
+                exit = Some(e);
+            }
+        }
+
+        let exit = exit.expect("exit event");
+        assert_eq!(exit.exit_code, Some(1));
+        assert!(matches!(exit.error, Some(AppError::ClaudeAuthFailed(_))));
+    }
+
+    #[tokio::test]
+    #[cfg(unix)]
+    async fn test_terminate_reports_terminated() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let claude = fake_claude(temp_dir.path(), "exec sleep 30");
+        let runner = AgentRunner::new(claude);
+
+        let (handle, mut rx) = runner.spawn(request("hi"), None).await.unwrap();
+        AgentRunner::terminate(handle).await.unwrap();
+
+        let event = tokio::time::timeout(std::time::Duration::from_secs(5), rx.recv())
+            .await
+            .unwrap();
+        let Some(AgentEvent::Exited(exit)) = event else {
+            panic!("expected exit event, got {:?}", event);
+        };
+        assert!(exit.terminated);
+        assert_eq!(exit.termination, Some(Termination::Graceful));
+        assert!(exit.error.is_none());
+    }
+
+    #[tokio::test]
+    #[cfg(unix)]
+    async fn test_terminate_all_stops_detached_runs() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let claude = fake_claude(temp_dir.path(), "exec sleep 30");
+        let runner = AgentRunner::new(claude);
+
+        // Runs whose handle was dropped are stopped too
+        let (_, mut rx) = runner.spawn(request("hi"), None).await.unwrap();
+        assert_eq!(runner.active_runs(), 1);
+        runner.terminate_all();
+
+        let event = tokio::time::timeout(std::time::Duration::from_secs(5), rx.recv())
+            .await
+            .unwrap();
+        let Some(AgentEvent::Exited(exit)) = event else {
+            panic!("expected exit event, got {:?}", event);
+        };
+        assert!(exit.terminated);
+        runner.wait_idle().await;
+        assert_eq!(runner.active_runs(), 0);
+    }
+
+    /// Whether `pid` is running; zombies left for init to reap do not count
+    #[cfg(target_os = "linux")]
+    fn running(pid: u32) -> bool {
+        std::fs::read_to_string(format!("/proc/{}/stat", pid))
+            .map(|stat| {
+                !stat
+                    .rsplit_once(')')
+                    .unwrap()
+                    .1
+                    .trim_start()
+                    .starts_with('Z')
+            })
+            .unwrap_or(false)
+    }
+
+    /// Spawn `claude`, which prints the PID of a descendant as its first line
+    #[cfg(target_os = "linux")]
+    async fn spawn_with_descendant(
+        runner: &AgentRunner,
+    ) -> (RunHandle, mpsc::Receiver<AgentEvent>, u32) {
+        let (handle, mut rx) = runner.spawn(request("hi"), None).await.unwrap();
+        let Some(AgentEvent::Stdout(line)) = rx.recv().await else {
+            panic!("expected the descendant's PID");
+        };
+        (handle, rx, line.parse().unwrap())
+    }
+
+    #[cfg(target_os = "linux")]
+    async fn wait_exit(rx: &mut mpsc::Receiver<AgentEvent>) -> ProcessExit {
+        let wait = async {
+            while let Some(event) = rx.recv().await {
+                if let AgentEvent::Exited(exit) = event {
+                    return exit;
+                }
+            }
+            panic!("stream ended without an exit event");
+        };
+        tokio::time::timeout(std::time::Duration::from_secs(10), wait)
+            .await
+            .expect("exit event")
+    }
+
+    #[tokio::test]
+    #[cfg(target_os = "linux")]
+    async fn test_terminate_stops_descendants() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let claude = fake_claude(temp_dir.path(), "sleep 30 &\necho $!\nwait");
+        let runner = AgentRunner::new(claude);
+
+        let (handle, mut rx, descendant) = spawn_with_descendant(&runner).await;
+        assert!(running(descendant));
+        AgentRunner::terminate(handle).await.unwrap();
+
+        let exit = wait_exit(&mut rx).await;
+        assert_eq!(exit.termination, Some(Termination::Graceful));
+        assert!(!running(descendant));
+    }
+
+    #[tokio::test]
+    #[cfg(target_os = "linux")]
+    async fn test_terminate_kills_after_grace_period() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        // Ignored signals stay ignored in the children
+        let claude = fake_claude(temp_dir.path(), "trap '' TERM\nsleep 30 &\necho $!\nwait");
+        let runner =
+            AgentRunner::new(claude).with_grace_period(std::time::Duration::from_millis(200));
+
+        let (handle, mut rx, descendant) = spawn_with_descendant(&runner).await;
+        AgentRunner::terminate(handle).await.unwrap();
+
+        let exit = wait_exit(&mut rx).await;
+        assert!(exit.terminated);
+        assert_eq!(exit.termination, Some(Termination::Forced));
+        assert_eq!(exit.exit_code, None);
+        assert!(!running(descendant));
+    }
+
+    #[tokio::test]
+    #[cfg(target_os = "linux")]
+    async fn test_leftover_processes_are_stopped() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let claude = fake_claude(temp_dir.path(), "sleep 30 &\necho $!");
+        let runner = AgentRunner::new(claude);
+
+        let (_handle, mut rx, descendant) = spawn_with_descendant(&runner).await;
+
+        // The leftover holds stdout open; the run only ends once it is gone
+        let exit = wait_exit(&mut rx).await;
+        assert_eq!(exit.exit_code, Some(0));
+        assert!(!exit.terminated);
+        assert_eq!(exit.termination, None);
+        assert!(!running(descendant));
+    }
+
+    #[tokio::test]
+    #[cfg(unix)]
+    async fn test_runs_in_workspace() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let workdir = tempfile::tempdir().unwrap();
+        let claude = fake_claude(temp_dir.path(), "pwd");
+        let runner = AgentRunner::new(claude);
+
+        let workspace = Workspace::existing(workdir.path().canonicalize().unwrap());
+        let (_handle, mut rx) = runner.spawn(request("hi"), Some(workspace)).await.unwrap();
+        let Some(AgentEvent::Stdout(line)) = rx.recv().await else {
+            panic!("expected stdout line");
+        };
+        assert_eq!(
+            std::path::Path::new(&line),
+            workdir.path().canonicalize().unwrap()
+        );
+    }
+
+    #[tokio::test]
+    #[cfg(unix)]
+    async fn test_environment_is_replaced() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let claude = fake_claude(temp_dir.path(), r#"echo "${HOME:-unset} $MODE""#);
+        let runner = AgentRunner::new(claude);
+
+        let mut request = request("hi");
+        request.env.insert("MODE".to_string(), "ci".to_string());
+        let (_handle, mut rx) = runner.spawn(request, None).await.unwrap();
+        let Some(AgentEvent::Stdout(line)) = rx.recv().await else {
+            panic!("expected stdout line");
+        };
+        assert_eq!(line, "unset ci");
+    }
+
+    #[tokio::test]
+    #[cfg(unix)]
+    async fn test_secrets_are_redacted() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let claude = fake_claude(
+            temp_dir.path(),
+            r#"echo "key=$API_KEY"; echo "$API_KEY" >&2"#,
+        );
+        let runner = AgentRunner::new(claude);
+
+        let mut request = request("hi");
+        request
+            .env
+            .insert("API_KEY".to_string(), "sk-test-123".to_string());
+        request.redactor = Redactor::new(Vec::new(), [("API_KEY", "sk-test-123")]);
+        let (_handle, mut rx) = runner.spawn(request, None).await.unwrap();
+        while let Some(event) = rx.recv().await {
+            match event {
+                AgentEvent::Stdout(line) => assert_eq!(line, "key=[REDACTED]"),
+                AgentEvent::Stderr(line) => assert_eq!(line, "[REDACTED]"),
+                AgentEvent::Exited(exit) => assert_eq!(exit.redactions, 2),
+            }
+        }
+    }
+
+    #[tokio::test]
+    #[cfg(unix)]
+    async fn test_transcript_keeps_unredacted_output() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let transcripts = tempfile::tempdir().unwrap();
+        let claude = fake_claude(temp_dir.path(), "echo 'ticket-42 sk-test-123'");
+        let runner =
+            AgentRunner::new(claude).with_transcript_dir(Some(transcripts.path().to_path_buf()));
+
+        let mut request = request("hi");
+        request
+            .env
+            .insert("API_KEY".to_string(), "sk-test-123".to_string());
+        request.redactor = Redactor::new(
+            vec![regex::Regex::new(r"ticket-\d+").unwrap()],
+            [("API_KEY", "sk-test-123")],
+        );
+        let (_handle, mut rx) = runner.spawn(request, None).await.unwrap();
+        let Some(AgentEvent::Stdout(line)) = rx.recv().await else {
+            panic!("expected stdout line");
+        };
+        assert_eq!(line, "[REDACTED] [REDACTED]");
+        while rx.recv().await.is_some() {}
+
+        // Only the rules are undone in the transcript; secrets stay redacted
+        let entry = std::fs::read_dir(transcripts.path())
+            .unwrap()
+            .next()
+            .unwrap()
+            .unwrap();
+        let transcript = std::fs::read_to_string(entry.path()).unwrap();
+        assert!(transcript.contains("ticket-42 [REDACTED]"));
+    }
+
+    #[tokio::test]
+    #[cfg(target_os = "linux")]
+    async fn test_limits_and_resource_usage() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let claude = fake_claude(temp_dir.path(), "ulimit -n");
+        let runner = AgentRunner::new(claude);
+
+        let mut request = request("hi");
+        request.limits.open_files = Some(256);
+        let (_handle, mut rx) = runner.spawn(request, None).await.unwrap();
+        let mut exit = None;
+        while let Some(event) = rx.recv().await {
+            match event {
+                AgentEvent::Stdout(line) => assert_eq!(line, "256"),
+                AgentEvent::Exited(e) => exit = Some(e),
+                AgentEvent::Stderr(_) => {}
+            }
+        }
+
+        let resources = exit.expect("exit event").resources.expect("resource usage");
+        assert!(resources.peak_memory_bytes.unwrap() > 0);
+        assert!(resources.cpu_time_ms.is_some());
+    }
+
+    #[tokio::test]
+    async fn test_missing_binary() {
+        let runner = AgentRunner::new("/nonexistent/claude".to_string());
+        let result = runner.spawn(request("hi"), None).await;
+        assert!(matches!(result, Err(AppError::ClaudeNotFound(_))));
+    }
+
+    #[tokio::test]
+    #[cfg(unix)]
+    async fn test_claude_version_is_cached() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let calls = temp_dir.path().join("calls");
+        let claude = fake_claude(
+            temp_dir.path(),
+            &format!("echo x >> {}\necho '2.0.1 (Claude Code)'", calls.display()),
+        );
+        let runner = AgentRunner::new(claude);
+
+        let version = "2.0.1 (Claude Code)";
+        assert_eq!(runner.claude_version().await.unwrap(), version);
+        assert_eq!(runner.claude_version().await.unwrap(), version);
+        assert_eq!(std::fs::read_to_string(&calls).unwrap().lines().count(), 1);
+
+        let missing = AgentRunner::new("/nonexistent/claude".to_string());
+        assert!(missing.claude_version().await.is_err());
+    }
+
+    /// Stdout and stderr lines of a run, then its exit
+    async fn collect(mut rx: mpsc::Receiver<AgentEvent>) -> (Vec<String>, ProcessExit) {
+        let mut lines = Vec::new();
+        while let Some(event) = rx.recv().await {
+            match event {
+                AgentEvent::Stdout(line) => lines.push(format!("out {}", line)),
+                AgentEvent::Stderr(line) => lines.push(format!("err {}", line)),
+                AgentEvent::Exited(exit) => return (lines, exit),
+            }
+        }
+        panic!("stream ended without an exit event");
+    }
+
+    #[tokio::test]
+    #[cfg(unix)]
+    async fn test_record_and_replay() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let cassettes = temp_dir.path().join("cassettes");
+        let claude = fake_claude(
+            temp_dir.path(),
+            r#"echo '{"type":"system","subtype":"init","session_id":"abc-123"}'
+echo 'Invalid API key · Please run /login' >&2
+sleep 0.3
+echo '{"type":"result","is_error":true,"result":"Invalid API key"}'
+exit 1"#,
+        );
+        let recorder =
+            AgentRunner::new(claude).with_cassettes(Some(Cassettes::Record(cassettes.clone())));
+        let (_handle, rx) = recorder.spawn(request("hi"), None).await.unwrap();
+        let (recorded, exit) = collect(rx).await;
+        assert_eq!(recorded.len(), 3);
+        assert_eq!(exit.exit_code, Some(1));
+
+        // Claude is never started when replaying
+        let replay = |speed: f64| {
+            AgentRunner::new("/nonexistent/claude".to_string()).with_cassettes(Some(
+                Cassettes::Replay {
+                    dir: cassettes.clone(),
+                    speed,
+                },
+            ))
+        };
+        let runner = replay(1.0);
+        let started = Instant::now();
+        let (handle, rx) = runner.spawn(request("  hi\n"), None).await.unwrap();
+        assert_eq!(handle.pid(), None);
+        let (replayed, exit) = collect(rx).await;
+        assert!(started.elapsed() >= Duration::from_millis(250));
+        assert_eq!(replayed, recorded);
+        assert_eq!(exit.exit_code, Some(1));
+        assert_eq!(exit.session_id.as_deref(), Some("abc-123"));
+        assert!(matches!(exit.error, Some(AppError::ClaudeAuthFailed(_))));
+
+        let runner = replay(0.0);
+        let started = Instant::now();
+        let (_handle, rx) = runner.spawn(request("hi"), None).await.unwrap();
+        assert_eq!(collect(rx).await.0, recorded);
+        assert!(started.elapsed() < Duration::from_millis(250));
+
+        assert!(matches!(
+            runner.spawn(request("hello"), None).await,
+            Err(AppError::CassetteNotFound(_))
+        ));
+
+        let runner = replay(0.01);
+        let (handle, rx) = runner.spawn(request("hi"), None).await.unwrap();
+        AgentRunner::terminate(handle).await.unwrap();
+        let (_, exit) = collect(rx).await;
+        assert!(exit.terminated);
+        assert!(exit.error.is_none());
+        runner.wait_idle().await;
+    }
+
     #[test]
     #[cfg(not(target_os = "windows"))]
     fn test_unix_direct_execution() {
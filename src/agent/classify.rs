@@ -0,0 +1,184 @@
+use std::process::ExitStatus;
+
+use crate::error::AppError;
+
+/// Exit code shells use when a command cannot be found
+const EXIT_COMMAND_NOT_FOUND: i32 = 127;
+
+const AUTH_PATTERNS: &[&str] = &[
+    "invalid api key",
+    "please run /login",
+    "authentication_error",
+    "authentication failed",
+    "oauth token has expired",
+    "401 unauthorized",
+];
+
+const RATE_LIMIT_PATTERNS: &[&str] = &[
+    "rate limit",
+    "rate_limit",
+    "429 too many requests",
+    "usage limit reached",
+    "overloaded_error",
+];
+
+const INVALID_SESSION_PATTERNS: &[&str] = &[
+    "no conversation found",
+    "session not found",
+    "invalid session",
+];
+
+const MISSING_BINARY_PATTERNS: &[&str] = &[
+    "command not found",
+    "is not recognized as an internal or external command",
+];
+
+/// Classify a finished process from its exit status, the tail of its stderr
+/// and the final stream-json `result` message, if any.
+///
+/// Returns `None` for successful runs. Stderr content alone never makes a run
+/// fail; it is only used to explain a non-zero exit or an error result.
+pub fn classify_exit(
+    status: ExitStatus,
+    stderr_tail: &[String],
+    last_result: Option<&serde_json::Value>,
+) -> Option<AppError> {
+    classify(status.code(), &status.to_string(), stderr_tail, last_result)
+}
+
+/// [`classify_exit`] for a recorded exit code; `None` means the process was
+/// killed by a signal
+pub fn classify_exit_code(
+    exit_code: Option<i32>,
+    stderr_tail: &[String],
+    last_result: Option<&serde_json::Value>,
+) -> Option<AppError> {
+    let status = match exit_code {
+        Some(code) => format!("exit status: {}", code),
+        None => "a signal".to_string(),
+    };
+    classify(exit_code, &status, stderr_tail, last_result)
+}
+
+fn classify(
+    exit_code: Option<i32>,
+    status: &str,
+    stderr_tail: &[String],
+    last_result: Option<&serde_json::Value>,
+) -> Option<AppError> {
+    let result_is_error = last_result
+        .and_then(|r| r.get("is_error"))
+        .and_then(|v| v.as_bool())
+        .unwrap_or(false);
+
+    if exit_code == Some(0) && !result_is_error {
+        return None;
+    }
+
+    let result_text = last_result
+        .and_then(|r| r.get("result"))
+        .and_then(|v| v.as_str())
+        .unwrap_or_default();
+
+    let mut evidence: Vec<&str> = stderr_tail.iter().map(String::as_str).collect();
+    if !result_text.is_empty() {
+        evidence.push(result_text);
+    }
+    let haystack = evidence.join("\n").to_lowercase();
+    let matches = |patterns: &[&str]| patterns.iter().any(|p| haystack.contains(p));
+
+    // Prefer the most specific line as the message
+    let message = evidence
+        .last()
+        .map(|line| line.to_string())
+        .unwrap_or_else(|| format!("Claude CLI exited with {}", status));
+
+    let error = if exit_code == Some(EXIT_COMMAND_NOT_FOUND) || matches(MISSING_BINARY_PATTERNS) {
+        AppError::ClaudeNotFound(message)
+    } else if matches(AUTH_PATTERNS) {
+        AppError::ClaudeAuthFailed(message)
+    } else if matches(RATE_LIMIT_PATTERNS) {
+        AppError::ClaudeRateLimited(message)
+    } else if matches(INVALID_SESSION_PATTERNS) {
+        AppError::ClaudeSessionInvalid(message)
+    } else {
+        AppError::ProcessExecutionError(format!("Claude CLI exited with {}: {}", status, message))
+    };
+
+    Some(error)
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::os::unix::process::ExitStatusExt;
+
+    fn exit(code: i32) -> ExitStatus {
+        ExitStatus::from_raw(code << 8)
+    }
+
+    fn lines(lines: &[&str]) -> Vec<String> {
+        lines.iter().map(|l| l.to_string()).collect()
+    }
+
+    #[test]
+    fn test_success_ignores_stderr_noise() {
+        let stderr = lines(&[
+            "Warning: failed to load optional plugin",
+            "error in telemetry",
+        ]);
+        assert!(classify_exit(exit(0), &stderr, None).is_none());
+    }
+
+    #[test]
+    fn test_error_result_with_zero_exit() {
+        let result = serde_json::json!({
+            "type": "result",
+            "is_error": true,
+            "result": "API Error: 429 Too Many Requests"
+        });
+        assert!(matches!(
+            classify_exit(exit(0), &[], Some(&result)),
+            Some(AppError::ClaudeRateLimited(_))
+        ));
+    }
+
+    #[test]
+    fn test_known_failures() {
+        let cases = [
+            (exit(1), "Invalid API key · Please run /login", "auth"),
+            (
+                exit(1),
+                "Error: No conversation found with session ID: abc",
+                "session",
+            ),
+            (exit(127), "sh: claude: command not found", "missing"),
+            (exit(2), "something unexpected", "generic"),
+        ];
+
+        for (status, line, kind) in cases {
+            let error = classify_exit(status, &lines(&[line]), None).unwrap();
+            let matched = match kind {
+                "auth" => matches!(error, AppError::ClaudeAuthFailed(_)),
+                "session" => matches!(error, AppError::ClaudeSessionInvalid(_)),
+                "missing" => matches!(error, AppError::ClaudeNotFound(_)),
+                _ => matches!(error, AppError::ProcessExecutionError(_)),
+            };
+            assert!(matched, "{} classified as {:?}", line, error);
+        }
+    }
+
+    #[test]
+    fn test_recorded_exit_codes() {
+        let stderr = lines(&["Error: Invalid API key"]);
+        assert!(classify_exit_code(Some(0), &stderr, None).is_none());
+        assert!(matches!(
+            classify_exit_code(Some(1), &stderr, None),
+            Some(AppError::ClaudeAuthFailed(_))
+        ));
+        assert!(matches!(
+            classify_exit_code(None, &[], None),
+            Some(AppError::ProcessExecutionError(message)) if message.contains("a signal")
+        ));
+    }
+}
@@ -0,0 +1,218 @@
+use std::time::Duration;
+use tokio::time::Instant;
+use tracing::{error, info, warn};
+
+use q9gent_client::types::Termination;
+
+use crate::limits::Cgroup;
+
+/// How often a process group is checked for remaining processes
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// How long killed processes may take to disappear before the run is reported
+/// as leaking processes
+const KILL_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// The processes of one run
+///
+/// claude leads its own process group, so the Node subprocesses, MCP servers
+/// and tools it starts are signalled together with it. Processes that start a
+/// new session leave the group; only a cgroup catches those.
+pub struct ProcessTree<'a> {
+    pgid: Option<u32>,
+    cgroup: Option<&'a Cgroup>,
+    grace_period: Duration,
+
+    /// When SIGTERM was sent plus the grace period
+    deadline: Option<Instant>,
+
+    /// Whether SIGKILL was sent
+    forced: bool,
+}
+
+impl<'a> ProcessTree<'a> {
+    pub fn new(pid: Option<u32>, cgroup: Option<&'a Cgroup>, grace_period: Duration) -> Self {
+        Self {
+            pgid: pid,
+            cgroup,
+            grace_period,
+            deadline: None,
+            forced: false,
+        }
+    }
+
+    /// How the run's processes were stopped, if they were signalled
+    pub fn termination(&self) -> Option<Termination> {
+        match (self.deadline, self.forced) {
+            (_, true) => Some(Termination::Forced),
+            (Some(_), false) => Some(Termination::Graceful),
+            (None, false) => None,
+        }
+    }
+
+    /// Send SIGTERM to the group and start the grace period
+    pub fn terminate(&mut self) -> std::io::Result<()> {
+        let Some(pgid) = self.pgid else {
+            return Ok(());
+        };
+        if self.deadline.is_some() {
+            return Ok(());
+        }
+        info!(
+            "📨 Sending SIGTERM to process group {} ({:?} grace period)",
+            pgid, self.grace_period
+        );
+        signal_group(pgid, libc::SIGTERM)?;
+        self.deadline = Some(Instant::now() + self.grace_period);
+        Ok(())
+    }
+
+    /// Resolves once the grace period after SIGTERM has passed; never
+    /// resolves before SIGTERM or after SIGKILL
+    pub async fn grace_expired(&self) {
+        match self.deadline {
+            Some(deadline) if !self.forced => tokio::time::sleep_until(deadline).await,
+            _ => std::future::pending().await,
+        }
+    }
+
+    /// SIGKILL the group and the cgroup, if any
+    pub fn kill(&mut self) {
+        let Some(pgid) = self.pgid else {
+            return;
+        };
+        warn!("⚠️  Sending SIGKILL to process group {}", pgid);
+        if let Err(e) = signal_group(pgid, libc::SIGKILL) {
+            warn!("Failed to kill process group {}: {}", pgid, e);
+        }
+        if let Some(cgroup) = self.cgroup {
+            cgroup.kill();
+        }
+        self.forced = true;
+    }
+
+    /// Stop whatever is left of the run once claude itself has exited and
+    /// been reaped, and check that nothing survives
+    ///
+    /// Leftover processes get SIGTERM and, after the grace period, SIGKILL.
+    /// They would otherwise keep running and hold claude's pipes open.
+    pub async fn reap(&mut self) {
+        let Some(pgid) = self.pgid else {
+            return;
+        };
+        if !self.alive(pgid) {
+            return;
+        }
+        if self.deadline.is_none() {
+            warn!(
+                "⚠️  Process group {} still has processes after claude exited",
+                pgid
+            );
+            if let Err(e) = self.terminate() {
+                warn!("Failed to terminate process group {}: {}", pgid, e);
+            }
+        }
+
+        let mut kill_deadline = self.forced.then(|| Instant::now() + KILL_TIMEOUT);
+        while self.alive(pgid) {
+            match kill_deadline {
+                None if self
+                    .deadline
+                    .is_some_and(|deadline| Instant::now() >= deadline) =>
+                {
+                    self.kill();
+                    kill_deadline = Some(Instant::now() + KILL_TIMEOUT);
+                }
+                Some(deadline) if Instant::now() >= deadline => {
+                    error!(
+                        "❌ Processes of group {} are still running after SIGKILL",
+                        pgid
+                    );
+                    return;
+                }
+                _ => {}
+            }
+            tokio::time::sleep(POLL_INTERVAL).await;
+        }
+    }
+
+    /// Whether any process of the run is still running
+    pub fn is_alive(&self) -> bool {
+        self.pgid.is_some_and(|pgid| self.alive(pgid))
+    }
+
+    fn alive(&self, pgid: u32) -> bool {
+        group_alive(pgid) || self.cgroup.is_some_and(Cgroup::populated)
+    }
+}
+
+/// Send `signal` to every process in group `pgid`; a group that no longer
+/// exists is not an error
+fn signal_group(pgid: u32, signal: libc::c_int) -> std::io::Result<()> {
+    // SAFETY: kill has no memory safety requirements
+    if unsafe { libc::kill(-(pgid as libc::pid_t), signal) } == 0 {
+        return Ok(());
+    }
+    let e = std::io::Error::last_os_error();
+    if e.raw_os_error() == Some(libc::ESRCH) {
+        Ok(())
+    } else {
+        Err(e)
+    }
+}
+
+/// Whether group `pgid` has a process that is not a zombie
+///
+/// Zombies are skipped: orphans are reaped by init, which may be slow or, in
+/// some containers, never happen.
+#[cfg(target_os = "linux")]
+fn group_alive(pgid: u32) -> bool {
+    let Ok(entries) = std::fs::read_dir("/proc") else {
+        return signal_group_alive(pgid);
+    };
+    entries.flatten().any(|entry| {
+        let Ok(stat) = std::fs::read_to_string(entry.path().join("stat")) else {
+            return false;
+        };
+        // `pid (comm) state ppid pgrp ...`; comm may contain spaces and parens
+        let Some((_, fields)) = stat.rsplit_once(')') else {
+            return false;
+        };
+        let mut fields = fields.split_whitespace();
+        let state = fields.next();
+        let pgrp = fields.nth(1).and_then(|pgrp| pgrp.parse::<u32>().ok());
+        pgrp == Some(pgid) && state != Some("Z")
+    })
+}
+
+/// Start time of process `pid` in clock ticks since boot; tells a process
+/// apart from a later one given the same PID
+#[cfg(target_os = "linux")]
+pub fn start_time(pid: u32) -> Option<u64> {
+    let stat = std::fs::read_to_string(format!("/proc/{}/stat", pid)).ok()?;
+    // Field 22, counted from the state field that follows `(comm)`
+    stat.rsplit_once(')')?
+        .1
+        .split_whitespace()
+        .nth(19)?
+        .parse()
+        .ok()
+}
+
+/// Process start times are only read on Linux
+#[cfg(not(target_os = "linux"))]
+pub fn start_time(_pid: u32) -> Option<u64> {
+    None
+}
+
+/// Whether group `pgid` has any process
+#[cfg(not(target_os = "linux"))]
+fn group_alive(pgid: u32) -> bool {
+    signal_group_alive(pgid)
+}
+
+fn signal_group_alive(pgid: u32) -> bool {
+    // SAFETY: signal 0 only checks that the group exists
+    let exists = unsafe { libc::kill(-(pgid as libc::pid_t), 0) } == 0;
+    exists || std::io::Error::last_os_error().raw_os_error() == Some(libc::EPERM)
+}
@@ -1,84 +1,179 @@
 use axum::{
-    extract::{Path, State},
-    http::StatusCode,
+    extract::{rejection::QueryRejection, DefaultBodyLimit, Path, Query, State},
+    handler::Handler,
+    http::{Method, StatusCode},
+    middleware,
     response::{
         sse::{Event, KeepAlive},
         IntoResponse, Sse,
     },
-    routing::{get, post},
-    Json, Router,
+    routing::{on, MethodFilter, MethodRouter},
+    Extension, Json, Router,
 };
 use futures::stream::Stream;
-use serde::{Deserialize, Serialize};
-use std::{collections::HashMap, convert::Infallible, sync::Arc};
-use tokio::sync::Mutex;
-use tower_http::{cors::CorsLayer, trace::TraceLayer};
-use tracing::{debug, error, info, warn};
+use serde::Deserialize;
+use std::{
+    collections::HashMap, convert::Infallible, net::SocketAddr, num::NonZeroUsize, path::PathBuf,
+    sync::Arc, time::Instant,
+};
+use tokio::sync::{mpsc, Mutex};
+use tower_http::{
+    cors::{AllowOrigin, Any, CorsLayer},
+    trace::TraceLayer,
+};
+use tracing::{debug, error, info, info_span, warn, Instrument, Span};
+
+mod auth;
+mod extract;
+mod metrics;
+mod openapi;
+
+use q9gent_client::types::{
+    AuditList, ErrorResponse, HealthResponse, MessageRequest, ReadyCheck, ReadyResponse,
+    ReloadResponse, RunInfo, RunList, RunnerStats, SessionDiff, SessionList, SessionMetadata,
+    SpawnRequest, StreamEvent, TerminateResponse,
+};
+
+use extract::{ApiJson, RequestId};
 
 use crate::{
-    agent::{AgentRequest, AgentRunner},
-    config::ServerConfig,
+    agent::{validate_flags, AgentEvent, AgentRequest, AgentRunner, RunHandle},
+    audit::{AuditLog, Caller},
+    cassette::Cassettes,
+    config::{CorsConfig, ListenerConfig, SharedConfig},
+    environment,
     error::{AppError, AppResult},
-    session::SessionStore,
+    journal::RunJournal,
+    metrics::Metrics,
+    reload::Reloader,
+    session::{extract_session_id, SessionPlacement, SessionStore},
+    shutdown::{self, Shutdown},
+    telemetry,
+    workspace::{self, WorkspaceRequest},
+    worktree,
 };
 
 /// Shared application state
 #[derive(Clone)]
 struct AppState {
-    config: Arc<ServerConfig>,
+    config: SharedConfig,
+    reloader: Arc<Reloader>,
     session_store: Arc<SessionStore>,
     agent_runner: Arc<AgentRunner>,
+    journal: Arc<RunJournal>,
+    audit: Option<Arc<AuditLog>>,
+    metrics: Arc<Metrics>,
     // Track running processes for optional termination
-    running_processes: Arc<Mutex<HashMap<String, tokio::process::Child>>>,
+    running_processes: Arc<Mutex<HashMap<String, RunHandle>>>,
+    shutdown: Shutdown,
+    started: Instant,
 }
 
-/// Spawn request payload
-#[derive(Debug, Deserialize)]
-struct SpawnRequest {
-    agent_type: String,
-    prompt: String,
-    #[serde(default)]
-    flags: Vec<String>,
-    #[serde(default)]
-    tools_allowed: Vec<String>,
-    system_append: Option<String>,
-    resume_id: Option<String>,
-    /// Whether to create a new session for resumption
-    #[serde(default)]
-    create_session: bool,
-}
-
-/// Message request payload (for resuming sessions)
-#[derive(Debug, Deserialize)]
-struct MessageRequest {
-    prompt: String,
-    #[serde(default)]
-    flags: Vec<String>,
-    #[serde(default)]
-    tools_allowed: Vec<String>,
-    system_append: Option<String>,
-}
-
-/// Health check response
-#[derive(Debug, Serialize)]
-struct HealthResponse {
-    status: String,
-    version: String,
-}
+/// How long open connections get to receive their final events once every
+/// process has exited during shutdown
+const CLOSE_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(5);
 
 /// Health check endpoint
-async fn health() -> Json<HealthResponse> {
+#[utoipa::path(
+    get,
+    path = "/health",
+    tag = "server",
+    responses((status = 200, description = "Server is running", body = HealthResponse))
+)]
+async fn health(State(state): State<AppState>) -> Json<HealthResponse> {
     debug!("Health check requested");
+    let config = state.config.current();
     Json(HealthResponse {
         status: "ok".to_string(),
         version: env!("CARGO_PKG_VERSION").to_string(),
+        uptime_secs: state.started.elapsed().as_secs(),
+        claude_path: config
+            .claude
+            .as_ref()
+            .map(|claude| claude.path.display().to_string()),
+        claude_version: state.agent_runner.claude_version().await.ok(),
+        runner: RunnerStats {
+            active_processes: state.agent_runner.active_runs(),
+            queued_events: state.agent_runner.queued_events(),
+            max_runs: config.max_runs.map(NonZeroUsize::get),
+        },
     })
 }
 
+/// Readiness endpoint - checks that runs can be started
+#[utoipa::path(
+    get,
+    path = "/ready",
+    tag = "server",
+    responses(
+        (status = 200, description = "Server is ready to start runs", body = ReadyResponse),
+        (status = 503, description = "A check failed", body = ReadyResponse)
+    )
+)]
+async fn ready(State(state): State<AppState>) -> (StatusCode, Json<ReadyResponse>) {
+    let config = state.config.current();
+    let active = state.agent_runner.active_runs();
+    let capacity = match config.max_runs {
+        _ if state.shutdown.is_started() => Err("server is shutting down".to_string()),
+        Some(max) if active >= max.get() => Err(format!("{} of {} runs active", active, max)),
+        Some(max) => Ok(format!("{} of {} runs active", active, max)),
+        None => Ok(format!("{} runs active", active)),
+    };
+    let session_store = match state.session_store.check_writable().await {
+        Ok(()) => Ok(config.session_dir.clone()),
+        Err(e) => Err(format!("{}: {}", config.session_dir, e)),
+    };
+    let claude = match state.agent_runner.replay_dir() {
+        Some(dir) => Ok(format!("replaying cassettes from {}", dir.display())),
+        None => state.agent_runner.claude_version().await,
+    };
+    let checks = vec![
+        ready_check("claude", claude),
+        ready_check("session_store", session_store),
+        ready_check("capacity", capacity),
+    ];
+
+    let ready = checks.iter().all(|check| check.ok);
+    if !ready {
+        warn!("⚠️  Not ready: {:?}", checks);
+    }
+    let status = if ready {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+    (status, Json(ReadyResponse { ready, checks }))
+}
+
+fn ready_check(name: &str, result: Result<String, String>) -> ReadyCheck {
+    let ok = result.is_ok();
+    ReadyCheck {
+        name: name.to_string(),
+        ok,
+        detail: Some(result.unwrap_or_else(|e| e)),
+    }
+}
+
 /// Spawn endpoint - creates a new agent process and streams JSONL output via SSE
+#[utoipa::path(
+    post,
+    path = "/spawn",
+    tag = "agents",
+    request_body = SpawnRequest,
+    responses(
+        (status = 200, description = "SSE stream; each event's data is a StreamEvent",
+            content_type = "text/event-stream", body = StreamEvent),
+        (status = 400, description = "Malformed body, flag or unknown profile", body = ErrorResponse),
+        (status = 403, description = "Working directory outside the allowed roots or env key not permitted", body = ErrorResponse),
+        (status = 404, description = "No cassette recorded for the request in replay mode", body = ErrorResponse),
+        (status = 500, description = "Process or worktree could not be created", body = ErrorResponse)
+    )
+)]
 async fn spawn(
     State(state): State<AppState>,
-    Json(payload): Json<SpawnRequest>,
+    Extension(RequestId(request_id)): Extension<RequestId>,
+    caller: Caller,
+    ApiJson(payload): ApiJson<SpawnRequest>,
 ) -> AppResult<Sse<impl Stream<Item = Result<Event, Infallible>>>> {
     info!(
         "🚀 Spawn request - agent_type: '{}', create_session: {}, tools: {:?}, prompt_length: {} chars",
@@ -87,6 +182,11 @@ async fn spawn(
         payload.tools_allowed,
         payload.prompt.len()
     );
+    if state.shutdown.is_started() {
+        return Err(AppError::ShuttingDown);
+    }
+    Span::current().record("agent_type", payload.agent_type.as_str());
+    let config = state.config.current();
     debug!(
         "Spawn request details - flags: {:?}, system_append: {:?}, resume_id: {:?}",
         payload.flags,
@@ -102,6 +202,34 @@ async fn spawn(
     let should_create_session = payload.create_session;
     let agent_type_for_session = payload.agent_type.clone();
 
+    let (env, redactor, limits, workspace) = async {
+        // Check flags and env before creating a workspace that would go unused
+        validate_flags(&payload.flags)?;
+        let (env, redactor) =
+            environment::for_run(&config, payload.profile.as_deref(), &payload.env).await?;
+        let limits = config.limits(payload.profile.as_deref())?;
+        let workspace = workspace::prepare_run(
+            &config,
+            WorkspaceRequest {
+                profile: payload.profile.as_deref(),
+                workdir: payload.workdir.as_deref(),
+                temp_workspace: payload.temp_workspace,
+                template: payload.workspace_template.as_deref(),
+                create_session: should_create_session,
+            },
+        )
+        .await?;
+        Ok::<_, AppError>((env, redactor, limits, workspace))
+    }
+    .instrument(info_span!("admission"))
+    .await?;
+    let placement = SessionPlacement {
+        profile: payload.profile.clone(),
+        workdir: workspace.as_ref().map(|w| w.path().to_path_buf()),
+        worktree: workspace.as_ref().and_then(|w| w.worktree().cloned()),
+        temp_workspace: workspace.as_ref().is_some_and(|w| w.is_temporary()),
+    };
+
     // Build agent request
     // IMPORTANT: Only use resume_id if explicitly provided in payload
     // When create_session=true, we DON'T pass --resume to Claude
@@ -112,150 +240,64 @@ async fn spawn(
         flags: payload.flags,
         tools_allowed: payload.tools_allowed,
         system_append: payload.system_append,
-        resume_id: payload.resume_id,  // Don't auto-populate with new session_id
+        resume_id: payload.resume_id, // Don't auto-populate with new session_id
+        env,
+        redactor,
+        limits,
+        caller,
     };
 
     // Spawn the claude process
     info!("⚡ Spawning Claude CLI process...");
-    let (mut child, mut rx) = state.agent_runner.spawn(agent_request).await?;
+    let (handle, rx) = state.agent_runner.spawn(agent_request, workspace).await?;
     info!("✓ Claude process spawned successfully");
 
-    // Get the child PID for monitoring
-    let child_pid = child.id();
-
-    // Spawn a task to monitor process exit status
-    let monitor_pid = child_pid;
-    tokio::spawn(async move {
-        match child.wait().await {
-            Ok(status) => {
-                if status.success() {
-                    info!(
-                        "✅ Process {:?} exited successfully with status: {}",
-                        monitor_pid, status
-                    );
-                } else {
-                    warn!(
-                        "⚠️  Process {:?} exited with non-zero status: {}",
-                        monitor_pid, status
-                    );
-                }
-            }
-            Err(e) => {
-                tracing::error!("❌ Failed to wait for process {:?}: {}", monitor_pid, e);
-            }
-        }
-        debug!("Process {:?} monitoring task completed", monitor_pid);
+    let run_started = run_started(&handle, placement.workdir.as_ref());
+    let new_session = should_create_session.then(|| NewSession {
+        store: state.session_store.clone(),
+        agent_type: agent_type_for_session,
+        placement,
     });
-
-    // Create SSE stream
-    let running_procs = state.running_processes.clone();
-    let session_store = state.session_store.clone();
-    let stream = async_stream::stream! {
-        let mut claude_session_id: Option<String> = None;
-        let mut output_count = 0;
-
-        // Stream JSONL lines from claude
-        while let Some(result) = rx.recv().await {
-            match result {
-                Ok(line) => {
-                    output_count += 1;
-                    if output_count == 1 {
-                        info!("📥 First output received from Claude");
-
-                        // Parse first line to extract Claude's session_id if creating session
-                        if should_create_session {
-                            if let Ok(parsed) = serde_json::from_str::<serde_json::Value>(&line) {
-                                if let Some(sid) = parsed.get("session_id").and_then(|v| v.as_str()) {
-                                    claude_session_id = Some(sid.to_string());
-                                    info!("📝 Extracted Claude session_id: {}", sid);
-
-                                    // Create session metadata with Claude's session_id
-                                    let metadata = crate::session::SessionMetadata {
-                                        session_id: sid.to_string(),
-                                        agent_type: agent_type_for_session.clone(),
-                                        created_at: std::time::SystemTime::now()
-                                            .duration_since(std::time::UNIX_EPOCH)
-                                            .unwrap()
-                                            .as_secs(),
-                                        last_used: std::time::SystemTime::now()
-                                            .duration_since(std::time::UNIX_EPOCH)
-                                            .unwrap()
-                                            .as_secs(),
-                                    };
-
-                                    if let Err(e) = session_store.save_session(&metadata).await {
-                                        warn!("⚠️  Failed to save session metadata: {}", e);
-                                    } else {
-                                        info!("💾 Saved session metadata for: {}", sid);
-
-                                        // Send session_created event
-                                        let event = Event::default()
-                                            .json_data(serde_json::json!({
-                                                "type": "session_created",
-                                                "session_id": sid
-                                            }))
-                                            .unwrap();
-                                        yield Ok(event);
-                                    }
-                                }
-                            }
-                        }
-                    }
-                    debug!("Output line {}: {} bytes", output_count, line.len());
-                    let event = Event::default()
-                        .json_data(serde_json::json!({
-                            "type": "output",
-                            "data": line
-                        }))
-                        .unwrap();
-                    yield Ok(event);
-                }
-                Err(e) => {
-                    error!("❌ Error from Claude process: {}", e);
-                    let event = Event::default()
-                        .json_data(serde_json::json!({
-                            "type": "error",
-                            "error": e.to_string()
-                        }))
-                        .unwrap();
-                    yield Ok(event);
-                    break;
-                }
-            }
-        }
-
-        if output_count == 0 {
-            warn!("⚠️  WARNING: Claude process completed with ZERO output lines!");
-            warn!("   This likely indicates a process spawning or execution failure.");
-            warn!("   Check that Claude CLI is properly installed and accessible.");
-            warn!("   On Windows, ensure .cmd files are executed through cmd.exe wrapper.");
-        } else {
-            info!("✅ Claude process completed - {} output lines sent", output_count);
-        }
-
-        // Send completion event
-        let event = Event::default()
-            .json_data(serde_json::json!({
-                "type": "completed"
-            }))
-            .unwrap();
-        yield Ok(event);
-
-        // Clean up running process if we tracked one
-        if let Some(ref sid) = claude_session_id {
-            running_procs.lock().await.remove(sid);
-            debug!("🧹 Cleaned up process for session: {}", sid);
-        }
+    let untracked = UntrackedRun {
+        running_processes: state.running_processes.clone(),
+        handle,
     };
+    let stream = agent_event_stream(
+        rx,
+        request_id,
+        run_started,
+        new_session,
+        None,
+        Some(untracked),
+        state.shutdown.clone(),
+    );
 
-    Ok(Sse::new(stream).keep_alive(KeepAlive::default()))
+    Ok(Sse::new(stream).keep_alive(KeepAlive::new().interval(config.sse_keep_alive)))
 }
 
 /// Message endpoint - send a message to an existing session
+#[utoipa::path(
+    post,
+    path = "/message/{session_id}",
+    tag = "agents",
+    params(("session_id" = String, Path, description = "Session to resume")),
+    request_body = MessageRequest,
+    responses(
+        (status = 200, description = "SSE stream; each event's data is a StreamEvent",
+            content_type = "text/event-stream", body = StreamEvent),
+        (status = 400, description = "Malformed body, session ID or flag", body = ErrorResponse),
+        (status = 403, description = "Env key not permitted by the session's profile", body = ErrorResponse),
+        (status = 404, description = "Session not found, or no cassette recorded for the request in replay mode", body = ErrorResponse),
+        (status = 409, description = "Session already has a running process", body = ErrorResponse),
+        (status = 500, description = "Process could not be spawned", body = ErrorResponse)
+    )
+)]
 async fn message(
     State(state): State<AppState>,
+    Extension(RequestId(request_id)): Extension<RequestId>,
+    caller: Caller,
     Path(session_id): Path<String>,
-    Json(payload): Json<MessageRequest>,
+    ApiJson(payload): ApiJson<MessageRequest>,
 ) -> AppResult<Sse<impl Stream<Item = Result<Event, Infallible>>>> {
     info!(
         "💬 Message request - session_id: {}, tools: {:?}, prompt_length: {} chars",
@@ -263,10 +305,29 @@ async fn message(
         payload.tools_allowed,
         payload.prompt.len()
     );
+    if state.shutdown.is_started() {
+        return Err(AppError::ShuttingDown);
+    }
+    let span = Span::current();
+    span.record("agent_type", "resumed");
+    span.record("session_id", session_id.as_str());
+    let config = state.config.current();
+    let admission = info_span!("admission");
 
     // Verify session exists and update last_used
-    state.session_store.touch_session(&session_id).await?;
-    info!("✓ Session found and updated: {}", session_id);
+    let (env, redactor, limits, workspace) = async {
+        let metadata = state.session_store.touch_session(&session_id).await?;
+        info!("✓ Session found and updated: {}", session_id);
+
+        let (env, redactor) =
+            environment::for_run(&config, metadata.profile.as_deref(), &payload.env).await?;
+        let limits = config.limits(metadata.profile.as_deref())?;
+        let workspace = workspace::session_workspace(metadata.workdir.as_deref())?;
+        Ok::<_, AppError>((env, redactor, limits, workspace))
+    }
+    .instrument(admission.clone())
+    .await?;
+    let workdir = workspace.as_ref().map(|w| w.path().to_path_buf());
 
     // Build agent request with resume
     let agent_request = AgentRequest {
@@ -276,102 +337,312 @@ async fn message(
         tools_allowed: payload.tools_allowed,
         system_append: payload.system_append,
         resume_id: Some(session_id.clone()),
+        env,
+        redactor,
+        limits,
+        caller,
     };
 
+    // Hold the lock across the spawn so two turns can't resume the same session at once
+    let mut processes = state.running_processes.lock().instrument(admission).await;
+    if processes.contains_key(&session_id) {
+        warn!("⚠️  Session already has a running process: {}", session_id);
+        return Err(AppError::SessionBusy(session_id));
+    }
+
     // Spawn the claude process
     info!("⚡ Resuming Claude session...");
-    let (child, mut rx) = state.agent_runner.spawn(agent_request).await?;
+    let (handle, rx) = state.agent_runner.spawn(agent_request, workspace).await?;
     info!("✓ Claude process resumed successfully");
+    let run_started = run_started(&handle, workdir.as_ref());
 
     // Store the process
-    state
-        .running_processes
-        .lock()
-        .await
-        .insert(session_id.clone(), child);
+    processes.insert(session_id.clone(), handle);
+    drop(processes);
     debug!("Stored resumed process for session: {}", session_id);
 
-    // Create SSE stream
-    let stream_session_id = session_id.clone();
-    let running_procs = state.running_processes.clone();
-    let stream = async_stream::stream! {
+    let guard = RunningGuard {
+        running_processes: state.running_processes.clone(),
+        session_id,
+    };
+    let stream = agent_event_stream(
+        rx,
+        request_id,
+        run_started,
+        None,
+        Some(guard),
+        None,
+        state.shutdown.clone(),
+    );
+
+    Ok(Sse::new(stream).keep_alive(KeepAlive::new().interval(config.sse_keep_alive)))
+}
+
+/// First event of every run
+fn run_started(handle: &RunHandle, workdir: Option<&PathBuf>) -> StreamEvent {
+    StreamEvent::RunStarted {
+        run_id: Some(handle.run_id().to_string()),
+        pid: handle.pid(),
+        workdir: workdir.map(|path| path.to_string_lossy().to_string()),
+    }
+}
+
+/// Where a stream records the session Claude creates on its first line
+struct NewSession {
+    store: Arc<SessionStore>,
+    agent_type: String,
+    placement: SessionPlacement,
+}
+
+/// Removes a session's entry from the running process table when the stream
+/// finishes or the client disconnects
+struct RunningGuard {
+    running_processes: Arc<Mutex<HashMap<String, RunHandle>>>,
+    session_id: String,
+}
+
+/// A `/spawn` run that is not in the running process table yet, because its
+/// session ID is only known once Claude reports it
+struct UntrackedRun {
+    running_processes: Arc<Mutex<HashMap<String, RunHandle>>>,
+    handle: RunHandle,
+}
+
+impl UntrackedRun {
+    /// Track the run under `session_id`, unless another run of the session
+    /// already is
+    async fn track(self, session_id: &str) -> Option<RunningGuard> {
+        let mut processes = self.running_processes.lock().await;
+        if processes.contains_key(session_id) {
+            debug!("Session {} already has a tracked process", session_id);
+            return None;
+        }
+        processes.insert(session_id.to_string(), self.handle);
+        drop(processes);
+        debug!("Stored spawned process for session: {}", session_id);
+        Some(RunningGuard {
+            running_processes: self.running_processes,
+            session_id: session_id.to_string(),
+        })
+    }
+}
+
+impl RunningGuard {
+    /// Remove the entry now instead of from a task once the guard is dropped
+    async fn release(mut self) {
+        let session_id = std::mem::take(&mut self.session_id);
+        self.running_processes.lock().await.remove(&session_id);
+        debug!("🧹 Cleaned up process for session: {}", session_id);
+    }
+}
+
+impl Drop for RunningGuard {
+    fn drop(&mut self) {
+        if self.session_id.is_empty() {
+            return;
+        }
+        let running_processes = self.running_processes.clone();
+        let session_id = std::mem::take(&mut self.session_id);
+        let cleanup = async move {
+            running_processes.lock().await.remove(&session_id);
+            debug!("🧹 Cleaned up process for session: {}", session_id);
+        };
+        tokio::spawn(cleanup.in_current_span());
+    }
+}
+
+fn sse_event(event: &StreamEvent) -> Result<Event, Infallible> {
+    Ok(Event::default().json_data(event).unwrap())
+}
+
+/// Convert runner events into SSE events
+///
+/// The stream starts with `run_started`. Stderr lines are forwarded as
+/// `stderr` events and never end the stream; it ends after the runner reports
+/// the process exit, with an `error` event first if the exit was classified as
+/// a failure. A `server_shutdown` event is sent once if the server starts
+/// shutting down during the run.
+fn agent_event_stream(
+    mut rx: mpsc::Receiver<AgentEvent>,
+    request_id: String,
+    run_started: StreamEvent,
+    new_session: Option<NewSession>,
+    guard: Option<RunningGuard>,
+    untracked: Option<UntrackedRun>,
+    shutdown: Shutdown,
+) -> impl Stream<Item = Result<Event, Infallible>> {
+    async_stream::stream! {
+        let mut guard = guard;
+        let mut untracked = untracked;
+        let mut shutdown_sent = false;
         let mut output_count = 0;
-        // Stream JSONL lines from claude
-        while let Some(result) = rx.recv().await {
-            match result {
-                Ok(line) => {
+        let mut completed = StreamEvent::Completed {
+            exit_code: None,
+            terminated: false,
+            workspace_retained: false,
+            redactions: 0,
+            resources: None,
+            termination: None,
+        };
+
+        yield sse_event(&run_started);
+
+        loop {
+            let next = tokio::select! {
+                event = rx.recv() => Some(event),
+                () = shutdown.started(), if !shutdown_sent => None,
+            };
+            let event = match next {
+                Some(Some(event)) => event,
+                Some(None) => break,
+                None => {
+                    shutdown_sent = true;
+                    yield sse_event(&StreamEvent::ServerShutdown {
+                        drain_timeout_secs: shutdown.drain_timeout().as_secs(),
+                    });
+                    continue;
+                }
+            };
+            match event {
+                AgentEvent::Stdout(line) => {
                     output_count += 1;
                     if output_count == 1 {
-                        info!("📥 First output received from resumed session");
+                        info!("📥 First output received from Claude");
+
+                        // Parse first line to extract Claude's session_id for
+                        // tracking the run and creating the session
+                        let sid = if new_session.is_some() || untracked.is_some() {
+                            extract_session_id(&line)
+                        } else {
+                            None
+                        };
+                        if let Some(sid) = sid {
+                            info!("📝 Extracted Claude session_id: {}", sid);
+                            Span::current().record("session_id", sid.as_str());
+
+                            if let Some(untracked) = untracked.take() {
+                                guard = untracked.track(&sid).await;
+                            }
+
+                            if let Some(ref new_session) = new_session {
+                                // Create session metadata with Claude's session_id
+                                let recorded = new_session
+                                    .store
+                                    .record_session(&sid, &new_session.agent_type, &new_session.placement)
+                                    .await;
+                                match recorded {
+                                    Err(e) => warn!("⚠️  Failed to save session metadata: {}", e),
+                                    Ok(_) => {
+                                        info!("💾 Saved session metadata for: {}", sid);
+                                        yield sse_event(&StreamEvent::SessionCreated { session_id: sid });
+                                    }
+                                }
+                            }
+                        }
                     }
-                    debug!("Resume output line {}: {} bytes", output_count, line.len());
-                    let event = Event::default()
-                        .json_data(serde_json::json!({
-                            "type": "output",
-                            "data": line
-                        }))
-                        .unwrap();
-                    yield Ok(event);
+                    debug!("Output line {}: {} bytes", output_count, line.len());
+                    yield sse_event(&StreamEvent::Output { data: line });
+                }
+                AgentEvent::Stderr(line) => {
+                    yield sse_event(&StreamEvent::Stderr { data: line });
                 }
-                Err(e) => {
-                    error!("❌ Error from resumed session: {}", e);
-                    let event = Event::default()
-                        .json_data(serde_json::json!({
-                            "type": "error",
-                            "error": e.to_string()
-                        }))
-                        .unwrap();
-                    yield Ok(event);
-                    break;
+                AgentEvent::Exited(exit) => {
+                    debug!(
+                        "Claude process exited - code: {:?}, stdout lines: {}, stderr lines: {}",
+                        exit.exit_code, exit.stdout_lines, exit.stderr_lines
+                    );
+                    if let Some(e) = exit.error {
+                        error!("❌ Claude process failed: {}", e);
+                        yield sse_event(&StreamEvent::Error(e.to_error_response(Some(&request_id))));
+                    }
+                    completed = StreamEvent::Completed {
+                        exit_code: exit.exit_code,
+                        terminated: exit.terminated,
+                        workspace_retained: exit.workspace_retained,
+                        redactions: exit.redactions,
+                        resources: exit.resources,
+                        termination: exit.termination,
+                    };
                 }
             }
         }
 
-        info!("✅ Resumed session completed - {} output lines sent", output_count);
-        // Send completion event
-        let event = Event::default()
-            .json_data(serde_json::json!({
-                "type": "completed"
-            }))
-            .unwrap();
-        yield Ok(event);
-
-        // Clean up running process
-        running_procs.lock().await.remove(&stream_session_id);
-        debug!("🧹 Cleaned up resumed session: {}", stream_session_id);
-    };
+        if output_count == 0 {
+            warn!("⚠️  WARNING: Claude process completed with ZERO output lines!");
+            warn!("   This likely indicates a process spawning or execution failure.");
+            warn!("   Check that Claude CLI is properly installed and accessible.");
+            warn!("   On Windows, ensure .cmd files are executed through cmd.exe wrapper.");
+        } else {
+            info!("✅ Claude process completed - {} output lines sent", output_count);
+        }
+
+        // Untrack the run before the client learns it ended, so a follow-up
+        // turn is not refused as busy
+        if let Some(guard) = guard.take() {
+            guard.release().await;
+        }
 
-    Ok(Sse::new(stream).keep_alive(KeepAlive::default()))
+        // Send completion event
+        yield sse_event(&completed);
+    }
 }
 
-/// Terminate endpoint - forcefully terminate a running agent process
+/// Terminate endpoint - stop a running agent process and its descendants
+///
+/// Runs started by `/message` are tracked from the start; runs started by
+/// `/spawn` once Claude has reported their session ID.
+/// On Unix the process group gets SIGTERM, then SIGKILL once the grace period
+/// has passed; the response is sent once every process is gone.
+#[utoipa::path(
+    post,
+    path = "/terminate/{session_id}",
+    tag = "agents",
+    params(("session_id" = String, Path, description = "Session whose process to terminate")),
+    responses(
+        (status = 200, description = "Process terminated", body = TerminateResponse),
+        (status = 400, description = "Malformed session ID", body = ErrorResponse),
+        (status = 404, description = "No running process for this session", body = ErrorResponse),
+        (status = 500, description = "Process could not be killed", body = ErrorResponse)
+    )
+)]
 async fn terminate(
     State(state): State<AppState>,
     Path(session_id): Path<String>,
 ) -> AppResult<impl IntoResponse> {
     info!("🛑 Terminate request - session_id: {}", session_id);
 
-    let mut processes = state.running_processes.lock().await;
+    // Terminating can take the whole grace period; other sessions must not
+    // wait for it
+    let handle = state.running_processes.lock().await.remove(&session_id);
 
-    if let Some(child) = processes.remove(&session_id) {
-        AgentRunner::terminate(child).await?;
+    if let Some(handle) = handle {
+        AgentRunner::terminate(handle).await?;
         info!("✓ Process terminated successfully: {}", session_id);
         Ok((
             StatusCode::OK,
-            Json(serde_json::json!({
-                "message": "Process terminated successfully"
-            })),
+            Json(TerminateResponse {
+                message: "Process terminated successfully".to_string(),
+            }),
         ))
     } else {
         warn!("⚠️  No running process found for session: {}", session_id);
-        Err(AppError::SessionNotFound(session_id))
+        Err(AppError::ProcessNotRunning(session_id))
     }
 }
 
 /// List sessions endpoint
-async fn list_sessions(State(state): State<AppState>) -> AppResult<Json<serde_json::Value>> {
+#[utoipa::path(
+    get,
+    path = "/sessions",
+    tag = "sessions",
+    responses(
+        (status = 200, description = "Stored sessions", body = SessionList),
+        (status = 500, description = "Session directory could not be read", body = ErrorResponse)
+    )
+)]
+async fn list_sessions(State(state): State<AppState>) -> AppResult<Json<SessionList>> {
     info!("📋 List sessions request");
-    let session_dir = &state.config.session_dir;
+    let session_dir = &state.config.current().session_dir;
     let mut sessions = Vec::new();
 
     let mut entries = tokio::fs::read_dir(session_dir).await?;
@@ -388,42 +659,418 @@ async fn list_sessions(State(state): State<AppState>) -> AppResult<Json<serde_js
 
     info!("✓ Found {} sessions", sessions.len());
     debug!("Session details: {:?}", sessions);
-    Ok(Json(serde_json::json!({ "sessions": sessions })))
+    Ok(Json(SessionList { sessions }))
+}
+
+/// Reload the configuration
+///
+/// Re-reads the config, profiles and tokens files with the server's
+/// command-line and environment overrides. Runs already started keep the
+/// configuration they started with; if the new configuration is invalid the
+/// current one stays active.
+#[utoipa::path(
+    post,
+    path = "/admin/reload",
+    tag = "server",
+    responses(
+        (status = 200, description = "Configuration reloaded", body = ReloadResponse),
+        (status = 500, description = "Configuration invalid; the previous one stays active", body = ErrorResponse)
+    )
+)]
+async fn reload(State(state): State<AppState>) -> AppResult<Json<ReloadResponse>> {
+    info!("🔄 Reload request");
+    let outcome = state
+        .reloader
+        .reload()
+        .await
+        .map_err(|e| AppError::ReloadFailed(format!("{:#}", e)))?;
+    Ok(Json(ReloadResponse {
+        restart_required: outcome
+            .restart_required
+            .into_iter()
+            .map(str::to_string)
+            .collect(),
+    }))
+}
+
+/// Time range of an audit query, in Unix seconds
+#[derive(Debug, Deserialize, utoipa::IntoParams)]
+#[into_params(parameter_in = Query)]
+struct AuditQuery {
+    /// Only runs that ended at or after this time
+    since: Option<u64>,
+
+    /// Only runs that started before this time
+    until: Option<u64>,
+}
+
+/// Query the audit log
+///
+/// Returns the records of runs active in the time range, oldest first, and
+/// whether the hash chain of the audit files from the first day in range
+/// onwards is intact.
+#[utoipa::path(
+    get,
+    path = "/admin/audit",
+    tag = "server",
+    params(AuditQuery),
+    responses(
+        (status = 200, description = "Audit records in range", body = AuditList),
+        (status = 400, description = "Malformed time range", body = ErrorResponse),
+        (status = 404, description = "No audit log is configured", body = ErrorResponse),
+        (status = 500, description = "Audit log could not be read", body = ErrorResponse)
+    )
+)]
+async fn audit(
+    State(state): State<AppState>,
+    query: Result<Query<AuditQuery>, QueryRejection>,
+) -> AppResult<Json<AuditList>> {
+    let Query(query) = query.map_err(|e| AppError::InvalidRequest(e.body_text()))?;
+    let audit = state.audit.as_ref().ok_or(AppError::AuditDisabled)?;
+    Ok(Json(audit.query(query.since, query.until).await?))
+}
+
+/// List runs recorded in the run journal, newest first
+#[utoipa::path(
+    get,
+    path = "/runs",
+    tag = "runs",
+    responses(
+        (status = 200, description = "Recorded runs", body = RunList),
+        (status = 500, description = "Run journal could not be read", body = ErrorResponse)
+    )
+)]
+async fn list_runs(State(state): State<AppState>) -> AppResult<Json<RunList>> {
+    Ok(Json(RunList {
+        runs: state.journal.list().await?,
+    }))
+}
+
+/// Get a run from the run journal
+#[utoipa::path(
+    get,
+    path = "/runs/{run_id}",
+    tag = "runs",
+    params(("run_id" = String, Path, description = "Run to fetch")),
+    responses(
+        (status = 200, description = "Recorded run", body = RunInfo),
+        (status = 400, description = "Malformed run ID", body = ErrorResponse),
+        (status = 404, description = "Run not found", body = ErrorResponse)
+    )
+)]
+async fn get_run(
+    State(state): State<AppState>,
+    Path(run_id): Path<String>,
+) -> AppResult<Json<RunInfo>> {
+    Ok(Json(state.journal.get(&run_id).await?))
+}
+
+/// Get a single session's metadata
+#[utoipa::path(
+    get,
+    path = "/sessions/{session_id}",
+    tag = "sessions",
+    params(("session_id" = String, Path, description = "Session to fetch")),
+    responses(
+        (status = 200, description = "Session metadata", body = SessionMetadata),
+        (status = 400, description = "Malformed session ID", body = ErrorResponse),
+        (status = 404, description = "Session not found", body = ErrorResponse)
+    )
+)]
+async fn get_session(
+    State(state): State<AppState>,
+    Path(session_id): Path<String>,
+) -> AppResult<Json<SessionMetadata>> {
+    Ok(Json(state.session_store.load_session(&session_id).await?))
+}
+
+/// Delete a session, removing its git worktree (the branch is kept) or
+/// temporary workspace
+#[utoipa::path(
+    delete,
+    path = "/sessions/{session_id}",
+    tag = "sessions",
+    params(("session_id" = String, Path, description = "Session to delete")),
+    responses(
+        (status = 204, description = "Session deleted"),
+        (status = 400, description = "Malformed session ID", body = ErrorResponse),
+        (status = 404, description = "Session not found", body = ErrorResponse),
+        (status = 409, description = "Session has a running process", body = ErrorResponse),
+        (status = 500, description = "Worktree or workspace could not be removed", body = ErrorResponse)
+    )
+)]
+async fn delete_session(
+    State(state): State<AppState>,
+    Path(session_id): Path<String>,
+) -> AppResult<StatusCode> {
+    info!("🗑️  Delete session request - session_id: {}", session_id);
+
+    // Hold the lock so no turn can start while the session is torn down
+    let processes = state.running_processes.lock().await;
+    if processes.contains_key(&session_id) {
+        return Err(AppError::SessionBusy(session_id));
+    }
+
+    let metadata = state.session_store.load_session(&session_id).await?;
+    if let Some(ref workdir) = metadata.workdir {
+        // A workdir the caller picked is never removed
+        let path = PathBuf::from(workdir);
+        if let Some(ref session_worktree) = metadata.worktree {
+            if path.exists() {
+                worktree::remove(session_worktree, &path, false).await?;
+            }
+        } else if metadata.temp_workspace && path.exists() {
+            tokio::fs::remove_dir_all(&path).await?;
+            info!("🧹 Removed workspace {}", path.display());
+        }
+    }
+    state.session_store.delete_session(&session_id).await?;
+    drop(processes);
+
+    info!("✓ Session deleted: {}", session_id);
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Diff of a session's git worktree against the commit it started from
+#[utoipa::path(
+    get,
+    path = "/sessions/{session_id}/diff",
+    tag = "sessions",
+    params(("session_id" = String, Path, description = "Session to diff")),
+    responses(
+        (status = 200, description = "Changes in the session's worktree", body = SessionDiff),
+        (status = 400, description = "Malformed session ID", body = ErrorResponse),
+        (status = 404, description = "Session not found or has no worktree", body = ErrorResponse),
+        (status = 500, description = "git diff failed", body = ErrorResponse)
+    )
+)]
+async fn session_diff(
+    State(state): State<AppState>,
+    Path(session_id): Path<String>,
+) -> AppResult<Json<SessionDiff>> {
+    let metadata = state.session_store.load_session(&session_id).await?;
+    let (Some(session_worktree), Some(workdir)) = (metadata.worktree, metadata.workdir) else {
+        return Err(AppError::NoWorktree(session_id));
+    };
+
+    let diff = worktree::diff(&session_worktree, &PathBuf::from(workdir)).await?;
+    Ok(Json(SessionDiff {
+        session_id,
+        branch: session_worktree.branch,
+        base_commit: session_worktree.base_commit,
+        diff,
+    }))
+}
+
+/// CORS layer for the configured origins
+fn cors_layer(cors: &CorsConfig) -> CorsLayer {
+    match cors {
+        CorsConfig::Any => CorsLayer::permissive(),
+        CorsConfig::Origins(origins) => CorsLayer::new()
+            .allow_origin(AllowOrigin::list(origins.iter().cloned()))
+            .allow_methods(Any)
+            .allow_headers(Any),
+    }
+}
+
+/// A route of the API: method, path and handler
+type Route = (Method, &'static str, MethodRouter<AppState>);
+
+fn route<H, T>(method: Method, path: &'static str, handler: H) -> Route
+where
+    H: Handler<T, AppState>,
+    T: 'static,
+{
+    let filter = MethodFilter::try_from(method.clone()).expect("standard HTTP method");
+    (method, path, on(filter, handler))
+}
+
+/// Every route `app` serves; the OpenAPI spec is tested against this table
+fn routes() -> Vec<Route> {
+    vec![
+        route(Method::GET, "/health", health),
+        route(Method::GET, "/ready", ready),
+        route(Method::POST, "/spawn", spawn),
+        route(Method::POST, "/message/:session_id", message),
+        route(Method::POST, "/terminate/:session_id", terminate),
+        route(Method::GET, "/sessions", list_sessions),
+        route(Method::GET, "/sessions/:session_id", get_session),
+        route(Method::DELETE, "/sessions/:session_id", delete_session),
+        route(Method::GET, "/sessions/:session_id/diff", session_diff),
+        route(Method::GET, "/runs", list_runs),
+        route(Method::GET, "/runs/:run_id", get_run),
+        route(Method::POST, "/admin/reload", reload),
+        route(Method::GET, "/admin/audit", audit),
+        route(Method::GET, "/metrics", metrics::render),
+        route(Method::GET, "/openapi.json", openapi::openapi_json),
+    ]
 }
 
 /// Build the router with all endpoints
 fn app(state: AppState) -> Router {
-    Router::new()
-        .route("/health", get(health))
-        .route("/spawn", post(spawn))
-        .route("/message/:session_id", post(message))
-        .route("/terminate/:session_id", post(terminate))
-        .route("/sessions", get(list_sessions))
-        .layer(TraceLayer::new_for_http())
-        .layer(CorsLayer::permissive())
+    let config = state.config.current();
+    let cors = cors_layer(&config.cors);
+    let body_limit = DefaultBodyLimit::max(config.max_request_body);
+    // Routing a path again adds the method to the ones it already has
+    routes()
+        .into_iter()
+        .fold(Router::new(), |router, (_, path, handler)| {
+            router.route(path, handler)
+        })
+        .route_layer(middleware::from_fn_with_state(
+            state.clone(),
+            metrics::track_latency,
+        ))
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            auth::require_token,
+        ))
+        .layer(body_limit)
+        .layer(middleware::from_fn(extract::request_id))
+        .layer(TraceLayer::new_for_http().make_span_with(telemetry::request_span))
+        .layer(cors)
         .with_state(state)
 }
 
-/// Start the HTTP server
-pub async fn serve(addr: &str, config: Arc<ServerConfig>) -> anyhow::Result<()> {
+/// Start the HTTP server on the configured TCP and/or Unix socket listeners
+///
+/// On SIGINT or SIGTERM the listeners stop accepting connections and new runs
+/// are refused. Running processes may finish within the configured shutdown
+/// timeout, or a second signal, before the rest are terminated; the server
+/// returns once their streams have ended.
+pub async fn serve(
+    listeners: ListenerConfig,
+    shared_config: SharedConfig,
+    reloader: Arc<Reloader>,
+) -> anyhow::Result<()> {
+    let config = shared_config.current();
     let session_store = Arc::new(SessionStore::new(&config.session_dir));
-    let agent_runner = Arc::new(AgentRunner::new(config.claude_path.clone()));
+    let journal = Arc::new(RunJournal::new(&config.session_dir));
+    for run in journal.recover(config.kill_grace_period).await? {
+        warn!(
+            "⚠️  Run {} was interrupted; resume it with /message/{}",
+            run.run_id,
+            run.session_id.as_deref().unwrap_or("<no session>")
+        );
+    }
+    let audit = AuditLog::open(&config.audit)
+        .await?
+        .map(|audit| Arc::new(audit.with_claude(config.claude.clone())));
+    let metrics = Arc::new(Metrics::new());
+    let agent_runner = Arc::new(
+        AgentRunner::new(config.claude_executable())
+            .with_transcript_dir(config.redaction.transcript_dir.clone())
+            .with_cassettes(Cassettes::from_config(&config.cassettes))
+            .with_cgroup_root(config.cgroup_root.clone())
+            .with_grace_period(config.kill_grace_period)
+            .with_event_buffer(config.event_buffer)
+            .with_journal(journal.clone())
+            .with_audit(audit.clone())
+            .with_metrics(metrics.clone()),
+    );
     let running_processes = Arc::new(Mutex::new(HashMap::new()));
+    let shutdown = Shutdown::new(config.shutdown_timeout);
 
     let state = AppState {
-        config,
-        session_store,
-        agent_runner,
+        config: shared_config,
+        reloader: reloader.clone(),
+        session_store: session_store.clone(),
+        agent_runner: agent_runner.clone(),
+        journal,
+        audit,
+        metrics,
         running_processes,
+        shutdown: shutdown.clone(),
+        started: Instant::now(),
     };
 
     let app = app(state);
-    let listener = tokio::net::TcpListener::bind(addr).await?;
 
-    info!("🚀 Server listening on http://{}", addr);
-    info!("📍 Endpoints: /health, /spawn, /message/:id, /terminate/:id, /sessions");
+    // Bind everything up front so a bad address or socket path fails startup
+    let tcp_listener = match listeners.tcp_addr {
+        Some(ref addr) => Some(tokio::net::TcpListener::bind(addr).await?),
+        None => None,
+    };
+
+    #[cfg(unix)]
+    let unix_listener = match listeners.unix_socket {
+        Some(ref socket) => Some(crate::listener::bind_unix(socket)?),
+        None => None,
+    };
+    #[cfg(not(unix))]
+    if listeners.unix_socket.is_some() {
+        anyhow::bail!("Unix domain sockets are not supported on this platform");
+    }
+
+    info!("📍 Endpoints: /health, /ready, /spawn, /message/:id, /terminate/:id, /sessions, /sessions/:id, /sessions/:id/diff, /runs, /runs/:id, /admin/reload, /admin/audit, /metrics, /openapi.json");
 
-    axum::serve(listener, app).await?;
+    // Reload on SIGHUP and config file changes until shutdown
+    let watch_token = shutdown.token();
+    tokio::spawn(async move { reloader.watch(watch_token).await });
 
+    let tcp_app = app.clone();
+    let tcp_shutdown = shutdown.token();
+    let tcp = async move {
+        if let Some(listener) = tcp_listener {
+            info!("🚀 Server listening on http://{}", listener.local_addr()?);
+            let tcp_app = tcp_app.into_make_service_with_connect_info::<SocketAddr>();
+            axum::serve(listener, tcp_app)
+                .with_graceful_shutdown(tcp_shutdown.cancelled_owned())
+                .await?;
+        }
+        Ok::<_, anyhow::Error>(())
+    };
+
+    #[cfg(unix)]
+    let unix_shutdown = shutdown.token();
+    #[cfg(unix)]
+    let unix = async move {
+        if let Some(listener) = unix_listener {
+            crate::listener::serve_unix(listener, app, unix_shutdown).await?;
+        }
+        Ok::<_, anyhow::Error>(())
+    };
+    #[cfg(not(unix))]
+    let unix = async move {
+        drop(app);
+        Ok::<_, anyhow::Error>(())
+    };
+
+    let servers = async { tokio::try_join!(tcp, unix).map(|_| ()) };
+    tokio::pin!(servers);
+    let signal = tokio::select! {
+        result = &mut servers => return result,
+        signal = shutdown::signal() => signal,
+    };
+
+    info!(
+        "🛑 {} received, shutting down; waiting up to {:?} for {} running process(es)",
+        signal,
+        shutdown.drain_timeout(),
+        agent_runner.active_runs()
+    );
+    shutdown.start();
+    tokio::select! {
+        drained = tokio::time::timeout(shutdown.drain_timeout(), agent_runner.wait_idle()) => {
+            if drained.is_err() {
+                warn!("⚠️  Shutdown timeout reached with processes still running");
+            }
+        }
+        signal = shutdown::signal() => warn!("⚠️  {} received again, not waiting for runs", signal),
+    }
+    agent_runner.terminate_all();
+    agent_runner.wait_idle().await;
+
+    // Streams still have to send their final events
+    match tokio::time::timeout(CLOSE_TIMEOUT, &mut servers).await {
+        Ok(result) => result?,
+        Err(_) => warn!("⚠️  Closing connections that are still open"),
+    }
+    session_store.sync().await;
+
+    info!("👋 Shutdown complete");
     Ok(())
 }
+
+#[cfg(test)]
+mod tests;
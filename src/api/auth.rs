@@ -0,0 +1,41 @@
+use axum::{
+    extract::{Request, State},
+    http::{header, HeaderValue},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+
+use super::AppState;
+use crate::error::AppError;
+
+/// Paths served without a token, for load balancers and API tooling
+const PUBLIC_PATHS: &[&str] = &["/health", "/ready", "/openapi.json"];
+
+/// Middleware that rejects requests without an accepted bearer token when
+/// authentication is configured
+pub async fn require_token(
+    State(state): State<AppState>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let config = state.config.current();
+    let auth = &config.auth;
+    if auth.tokens.is_empty() || PUBLIC_PATHS.contains(&request.uri().path()) {
+        return next.run(request).await;
+    }
+
+    let token = request
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+    if token.is_some_and(|token| auth.accepts(token.trim())) {
+        return next.run(request).await;
+    }
+
+    let mut response = AppError::Unauthorized.into_response();
+    response
+        .headers_mut()
+        .insert(header::WWW_AUTHENTICATE, HeaderValue::from_static("Bearer"));
+    response
+}
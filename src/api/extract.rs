@@ -0,0 +1,104 @@
+use axum::{
+    async_trait,
+    body::Body,
+    extract::{rejection::JsonRejection, ConnectInfo, FromRequest, FromRequestParts, Request},
+    http::{header, request::Parts, HeaderValue},
+    middleware::Next,
+    response::Response,
+    Json,
+};
+use q9gent_client::types::ErrorResponse;
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use uuid::Uuid;
+
+use super::AppState;
+use crate::audit::Caller;
+use crate::error::AppError;
+
+/// Header used to accept and return request IDs
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+
+/// Per-request correlation ID, available to handlers as an extension
+#[derive(Debug, Clone)]
+pub struct RequestId(pub String);
+
+/// Accept a caller-supplied request ID if it is short and printable
+fn incoming_request_id(request: &Request) -> Option<String> {
+    let value = request.headers().get(REQUEST_ID_HEADER)?.to_str().ok()?;
+    let valid =
+        !value.is_empty() && value.len() <= 128 && value.bytes().all(|b| b.is_ascii_graphic());
+    valid.then(|| value.to_string())
+}
+
+/// Middleware that assigns a request ID, echoes it in the `x-request-id`
+/// response header and stamps it into error bodies produced by `AppError`
+pub async fn request_id(mut request: Request, next: Next) -> Response {
+    let id = incoming_request_id(&request).unwrap_or_else(|| Uuid::new_v4().to_string());
+    tracing::Span::current().record("request_id", id.as_str());
+    request.extensions_mut().insert(RequestId(id.clone()));
+
+    let mut response = next.run(request).await;
+
+    if let Some(mut body) = response.extensions_mut().remove::<ErrorResponse>() {
+        body.request_id = Some(id.clone());
+        if let Ok(bytes) = serde_json::to_vec(&body) {
+            response.headers_mut().remove(header::CONTENT_LENGTH);
+            *response.body_mut() = Body::from(bytes);
+        }
+    }
+
+    if let Ok(value) = HeaderValue::from_str(&id) {
+        response.headers_mut().insert(REQUEST_ID_HEADER, value);
+    }
+
+    response
+}
+
+/// JSON extractor that reports malformed bodies as `invalid_request` errors
+/// instead of axum's plain-text rejections
+pub struct ApiJson<T>(pub T);
+
+#[async_trait]
+impl<S, T> FromRequest<S> for ApiJson<T>
+where
+    Json<T>: FromRequest<S, Rejection = JsonRejection>,
+    S: Send + Sync,
+{
+    type Rejection = AppError;
+
+    async fn from_request(request: Request, state: &S) -> Result<Self, Self::Rejection> {
+        match Json::<T>::from_request(request, state).await {
+            Ok(Json(value)) => Ok(ApiJson(value)),
+            Err(rejection) => Err(AppError::InvalidRequest(rejection.body_text())),
+        }
+    }
+}
+
+/// Who made a request: the fingerprint of the bearer token it was
+/// authenticated with, the peer address of TCP connections and its request ID
+#[async_trait]
+impl FromRequestParts<AppState> for Caller {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, state: &AppState) -> Result<Self, Infallible> {
+        let authenticated = !state.config.current().auth.tokens.is_empty();
+        let token = parts
+            .headers
+            .get(header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .filter(|_| authenticated);
+        Ok(Caller {
+            identity: token.map_or_else(
+                || "anonymous".to_string(),
+                |token| Caller::token(token.trim()),
+            ),
+            source: parts
+                .extensions
+                .get::<ConnectInfo<SocketAddr>>()
+                .map(|ConnectInfo(addr)| addr.to_string()),
+            request_id: parts.extensions.get::<RequestId>().map(|id| id.0.clone()),
+        })
+    }
+}
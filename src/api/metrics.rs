@@ -0,0 +1,68 @@
+use axum::{
+    extract::{MatchedPath, Request, State},
+    http::header,
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use std::time::Instant;
+
+use q9gent_client::types::ErrorResponse;
+
+use super::AppState;
+use crate::error::AppResult;
+use crate::metrics::Snapshot;
+
+/// Prometheus metrics
+///
+/// Spawns by agent type and outcome, active processes, queued events, time to
+/// first output line, run duration, output and stderr lines, sessions and
+/// HTTP request latency by route, in the Prometheus text format.
+#[utoipa::path(
+    get,
+    path = "/metrics",
+    tag = "server",
+    responses(
+        (status = 200, description = "Metrics in the Prometheus text exposition format", content_type = "text/plain"),
+        (status = 500, description = "Session directory could not be read", body = ErrorResponse)
+    )
+)]
+pub async fn render(State(state): State<AppState>) -> AppResult<impl IntoResponse> {
+    let snapshot = Snapshot {
+        active_processes: state.agent_runner.active_runs(),
+        queued_events: state.agent_runner.queued_events(),
+        sessions: state.session_store.count().await?,
+    };
+    Ok((
+        [(header::CONTENT_TYPE, prometheus::TEXT_FORMAT)],
+        state.metrics.render(snapshot),
+    ))
+}
+
+/// Middleware that records the latency of requests by the route they matched
+///
+/// Event streams count until their response head is sent, not until the run
+/// ends; run durations are recorded separately.
+pub async fn track_latency(
+    State(state): State<AppState>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let started = Instant::now();
+    let method = request.method().clone();
+    let route = request
+        .extensions()
+        .get::<MatchedPath>()
+        .map(|path| path.as_str().to_string());
+
+    let response = next.run(request).await;
+
+    if let Some(route) = route {
+        state.metrics.http_request(
+            method.as_str(),
+            &route,
+            response.status().as_u16(),
+            started.elapsed(),
+        );
+    }
+    response
+}
@@ -0,0 +1,194 @@
+use axum::Json;
+use utoipa::OpenApi;
+
+use q9gent_client::types::{
+    AuditList, AuditRecord, ErrorResponse, HealthResponse, MessageRequest, ReadyCheck,
+    ReadyResponse, ReloadResponse, ResourceUsage, RunInfo, RunList, RunStatus, RunnerStats,
+    SessionDiff, SessionList, SessionMetadata, SessionWorktree, SpawnRequest, StreamEvent,
+    TerminateResponse, Termination,
+};
+
+/// OpenAPI document derived from the handlers and shared API types
+#[derive(OpenApi)]
+#[openapi(
+    info(
+        title = "Q9gent",
+        description = "Lightweight server for spawning Claude Code headless processes"
+    ),
+    paths(
+        super::health,
+        super::ready,
+        super::spawn,
+        super::message,
+        super::terminate,
+        super::list_sessions,
+        super::get_session,
+        super::delete_session,
+        super::session_diff,
+        super::list_runs,
+        super::get_run,
+        super::reload,
+        super::audit,
+        super::metrics::render,
+        openapi_json,
+    ),
+    components(schemas(
+        SpawnRequest,
+        MessageRequest,
+        HealthResponse,
+        RunnerStats,
+        ReadyResponse,
+        ReadyCheck,
+        SessionMetadata,
+        SessionList,
+        SessionWorktree,
+        SessionDiff,
+        TerminateResponse,
+        ErrorResponse,
+        StreamEvent,
+        ResourceUsage,
+        Termination,
+        RunInfo,
+        RunList,
+        RunStatus,
+        ReloadResponse,
+        AuditRecord,
+        AuditList,
+    ))
+)]
+pub struct ApiDoc;
+
+/// Serve the OpenAPI document
+#[utoipa::path(
+    get,
+    path = "/openapi.json",
+    tag = "server",
+    responses((status = 200, description = "This OpenAPI document", content_type = "application/json"))
+)]
+pub async fn openapi_json() -> Json<utoipa::openapi::OpenApi> {
+    Json(ApiDoc::openapi())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::{routes, tests::test_router};
+    use axum::{
+        body::Body,
+        http::{Method, Request, StatusCode},
+        Router,
+    };
+    use tower::ServiceExt;
+
+    const METHODS: [Method; 5] = [
+        Method::GET,
+        Method::POST,
+        Method::PUT,
+        Method::DELETE,
+        Method::PATCH,
+    ];
+
+    /// Send a request and report whether a route matched it. Unmatched paths
+    /// hit the router fallback (404 with an empty body) and unmatched methods
+    /// return 405; handler errors always carry a JSON body.
+    async fn route_matches(router: &Router, method: Method, path: &str) -> bool {
+        let request = Request::builder()
+            .method(method)
+            .uri(path)
+            .body(Body::empty())
+            .unwrap();
+        let response = router.clone().oneshot(request).await.unwrap();
+        let status = response.status();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+
+        status != StatusCode::METHOD_NOT_ALLOWED
+            && !(status == StatusCode::NOT_FOUND && body.is_empty())
+    }
+
+    /// Path with every `{param}` replaced by a valid ID
+    fn concrete_path(path: &str) -> String {
+        path.split('/')
+            .map(|segment| {
+                if segment.starts_with('{') && segment.ends_with('}') {
+                    uuid::Uuid::new_v4().to_string()
+                } else {
+                    segment.to_string()
+                }
+            })
+            .collect::<Vec<_>>()
+            .join("/")
+    }
+
+    #[test]
+    fn test_routes_documented() {
+        let spec = serde_json::to_value(ApiDoc::openapi()).unwrap();
+
+        for (method, path, _) in routes() {
+            // axum's `:param` segments are `{param}` in OpenAPI
+            let documented_path = path
+                .split('/')
+                .map(|segment| match segment.strip_prefix(':') {
+                    Some(param) => format!("{{{}}}", param),
+                    None => segment.to_string(),
+                })
+                .collect::<Vec<_>>()
+                .join("/");
+            let key = method.as_str().to_lowercase();
+            assert!(
+                spec["paths"][&documented_path].get(&key).is_some(),
+                "{} {} is routed but missing from the OpenAPI spec",
+                method,
+                path
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_router_matches_spec() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let router = test_router(temp_dir.path());
+        let spec = serde_json::to_value(ApiDoc::openapi()).unwrap();
+
+        let paths = spec["paths"].as_object().unwrap();
+        assert!(!paths.is_empty());
+
+        for (path, operations) in paths {
+            let concrete = concrete_path(path);
+            let documented = operations.as_object().unwrap();
+
+            for method in METHODS {
+                let key = method.as_str().to_lowercase();
+                let matched = route_matches(&router, method.clone(), &concrete).await;
+                assert_eq!(
+                    matched,
+                    documented.contains_key(&key),
+                    "router and OpenAPI spec disagree on {} {}",
+                    key.to_uppercase(),
+                    path
+                );
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_openapi_json_served() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let router = test_router(temp_dir.path());
+
+        let request = Request::builder()
+            .uri("/openapi.json")
+            .body(Body::empty())
+            .unwrap();
+        let response = router.oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let spec: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert!(spec["openapi"].as_str().unwrap().starts_with("3."));
+        assert!(spec["components"]["schemas"]["StreamEvent"].is_object());
+    }
+}
@@ -0,0 +1,442 @@
+use super::*;
+use crate::config::{AuditConfig, ServerConfig, WorkspaceConfig};
+use axum::{
+    body::Body,
+    http::{HeaderMap, Request},
+};
+use tower::ServiceExt;
+
+mod sse;
+
+/// Router backed by a temporary session directory and a claude path that
+/// does not exist
+pub(super) fn test_router(session_dir: &std::path::Path) -> Router {
+    app(test_state(session_dir))
+}
+
+fn test_state(session_dir: &std::path::Path) -> AppState {
+    let config = ServerConfig {
+        claude_path: "/nonexistent/claude".to_string(),
+        claude_version_check: Default::default(),
+        claude: None,
+        session_dir: session_dir.to_string_lossy().to_string(),
+        workspace: WorkspaceConfig {
+            allowed_roots: vec![session_dir.to_path_buf()],
+            temp_dir: session_dir.join("workspaces"),
+            ..Default::default()
+        },
+        profiles: Default::default(),
+        env: Default::default(),
+        redaction: Default::default(),
+        cgroup_root: None,
+        default_limits: Default::default(),
+        kill_grace_period: crate::agent::DEFAULT_GRACE_PERIOD,
+        shutdown_timeout: std::time::Duration::from_secs(30),
+        event_buffer: crate::agent::DEFAULT_EVENT_BUFFER,
+        max_runs: None,
+        sse_keep_alive: std::time::Duration::from_secs(15),
+        max_request_body: 2 * 1024 * 1024,
+        cors: Default::default(),
+        auth: Default::default(),
+        audit: Default::default(),
+        cassettes: Default::default(),
+    };
+    let settings = crate::config::Settings {
+        server: config.clone(),
+        listeners: Default::default(),
+        log_filter: None,
+        logging: Default::default(),
+        telemetry: Default::default(),
+        profiles_path: None,
+        files: Vec::new(),
+    };
+    let shared = SharedConfig::new(config.clone());
+    let metrics = Arc::new(Metrics::new());
+    AppState {
+        session_store: Arc::new(SessionStore::new(&config.session_dir)),
+        agent_runner: Arc::new(
+            AgentRunner::new(config.claude_path.clone()).with_metrics(metrics.clone()),
+        ),
+        journal: Arc::new(RunJournal::new(&config.session_dir)),
+        audit: None,
+        metrics,
+        running_processes: Arc::new(Mutex::new(HashMap::new())),
+        shutdown: Shutdown::new(config.shutdown_timeout),
+        started: Instant::now(),
+        reloader: Arc::new(Reloader::new(Default::default(), &settings, shared.clone())),
+        config: shared,
+    }
+}
+
+/// Change the active configuration of `state`
+fn configure(state: &AppState, change: impl FnOnce(&mut ServerConfig)) {
+    let mut config = (*state.config.current()).clone();
+    change(&mut config);
+    state.config.replace(config);
+}
+
+async fn send(
+    router: Router,
+    request: Request<Body>,
+) -> (StatusCode, HeaderMap, serde_json::Value) {
+    let response = router.oneshot(request).await.unwrap();
+    let status = response.status();
+    let headers = response.headers().clone();
+    let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    let json = serde_json::from_slice(&body).unwrap_or(serde_json::Value::Null);
+    (status, headers, json)
+}
+
+fn post_json(uri: &str, body: &str) -> Request<Body> {
+    Request::builder()
+        .method("POST")
+        .uri(uri)
+        .header("content-type", "application/json")
+        .body(Body::from(body.to_string()))
+        .unwrap()
+}
+
+#[tokio::test]
+async fn test_malformed_body_is_invalid_request() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let router = test_router(temp_dir.path());
+
+    let (status, headers, body) = send(router, post_json("/spawn", "{\"prompt\": 1}")).await;
+
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(body["code"], "invalid_request");
+    assert_eq!(
+        body["request_id"].as_str(),
+        headers.get("x-request-id").unwrap().to_str().ok()
+    );
+}
+
+#[tokio::test]
+async fn test_managed_flag_is_invalid_flag() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let router = test_router(temp_dir.path());
+
+    let payload = r#"{"agent_type":"t","prompt":"p","flags":["--output-format","text"]}"#;
+    let (status, _, body) = send(router, post_json("/spawn", payload)).await;
+
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(body["code"], "invalid_flag");
+    assert_eq!(body["details"]["flag"], "--output-format");
+}
+
+#[tokio::test]
+async fn test_unknown_session_keeps_caller_request_id() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let router = test_router(temp_dir.path());
+
+    let mut request = post_json("/message/missing", r#"{"prompt":"hi"}"#);
+    request
+        .headers_mut()
+        .insert("x-request-id", "caller-42".parse().unwrap());
+    let (status, headers, body) = send(router, request).await;
+
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    assert_eq!(body["code"], "session_not_found");
+    assert_eq!(body["details"]["session_id"], "missing");
+    assert_eq!(body["request_id"], "caller-42");
+    assert_eq!(headers.get("x-request-id").unwrap(), "caller-42");
+}
+
+#[tokio::test]
+async fn test_terminate_without_process() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let router = test_router(temp_dir.path());
+
+    let (status, _, body) = send(router, post_json("/terminate/idle", "")).await;
+
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    assert_eq!(body["code"], "process_not_running");
+}
+
+#[tokio::test]
+async fn test_workdir_outside_allowed_roots() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let outside = tempfile::tempdir().unwrap();
+    let router = test_router(temp_dir.path());
+
+    let payload = serde_json::json!({
+        "agent_type": "t",
+        "prompt": "p",
+        "workdir": outside.path(),
+    });
+    let (status, _, body) = send(router, post_json("/spawn", &payload.to_string())).await;
+
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    assert_eq!(body["code"], "workdir_not_allowed");
+}
+
+#[tokio::test]
+async fn test_unknown_profile() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let router = test_router(temp_dir.path());
+
+    let payload = r#"{"agent_type":"t","prompt":"p","profile":"nope","create_session":true}"#;
+    let (status, _, body) = send(router, post_json("/spawn", payload)).await;
+
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(body["code"], "unknown_profile");
+    assert_eq!(body["details"]["profile"], "nope");
+}
+
+#[tokio::test]
+async fn test_request_env_not_permitted() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let router = test_router(temp_dir.path());
+
+    let payload = r#"{"agent_type":"t","prompt":"p","env":{"AWS_SECRET_ACCESS_KEY":"x"}}"#;
+    let (status, _, body) = send(router, post_json("/spawn", payload)).await;
+
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    assert_eq!(body["code"], "env_not_allowed");
+    assert_eq!(body["details"]["key"], "AWS_SECRET_ACCESS_KEY");
+}
+
+#[tokio::test]
+async fn test_no_runs_during_shutdown() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let state = test_state(temp_dir.path());
+    state.shutdown.start();
+
+    let payload = r#"{"agent_type":"t","prompt":"p"}"#;
+    let (status, _, body) = send(app(state), post_json("/spawn", payload)).await;
+
+    assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(body["code"], "shutting_down");
+}
+
+#[tokio::test]
+async fn test_health_and_ready() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let state = test_state(temp_dir.path());
+    configure(&state, |config| {
+        config.max_runs = NonZeroUsize::new(2);
+        config.auth = crate::config::AuthConfig {
+            tokens: vec!["s3cret".to_string()],
+        };
+    });
+    let get = |uri: &str| Request::builder().uri(uri).body(Body::empty()).unwrap();
+
+    let (status, _, body) = send(app(state.clone()), get("/health")).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["status"], "ok");
+    assert!(body["uptime_secs"].is_u64());
+    assert!(body.get("claude_version").is_none());
+    assert_eq!(body["runner"]["active_processes"], 0);
+    assert_eq!(body["runner"]["max_runs"], 2);
+
+    // The claude path does not exist, so only that check fails
+    let (status, _, body) = send(app(state.clone()), get("/ready")).await;
+    assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(body["ready"], false);
+    let check = |body: &serde_json::Value, name: &str| {
+        body["checks"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|check| check["name"] == name)
+            .unwrap()["ok"]
+            .clone()
+    };
+    assert_eq!(check(&body, "claude"), false);
+    assert_eq!(check(&body, "session_store"), true);
+    assert_eq!(check(&body, "capacity"), true);
+
+    state.shutdown.start();
+    let (_, _, body) = send(app(state), get("/ready")).await;
+    assert_eq!(check(&body, "capacity"), false);
+}
+
+#[tokio::test]
+async fn test_session_get_diff_and_delete() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let store = SessionStore::new(temp_dir.path());
+    store
+        .record_session("plain", "t", &SessionPlacement::default())
+        .await
+        .unwrap();
+
+    let get = |uri: &str, method: &str| {
+        Request::builder()
+            .method(method)
+            .uri(uri)
+            .body(Body::empty())
+            .unwrap()
+    };
+
+    let (status, _, body) = send(test_router(temp_dir.path()), get("/sessions/plain", "GET")).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["session_id"], "plain");
+
+    let (status, _, body) = send(
+        test_router(temp_dir.path()),
+        get("/sessions/plain/diff", "GET"),
+    )
+    .await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    assert_eq!(body["code"], "no_worktree");
+
+    let (status, _, _) = send(
+        test_router(temp_dir.path()),
+        get("/sessions/plain", "DELETE"),
+    )
+    .await;
+    assert_eq!(status, StatusCode::NO_CONTENT);
+
+    let (status, _, body) = send(
+        test_router(temp_dir.path()),
+        get("/sessions/plain", "DELETE"),
+    )
+    .await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    assert_eq!(body["code"], "session_not_found");
+}
+
+#[tokio::test]
+async fn test_runs_list_and_get() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let get = |uri: &str| Request::builder().uri(uri).body(Body::empty()).unwrap();
+
+    let (status, _, body) = send(test_router(temp_dir.path()), get("/runs")).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["runs"], serde_json::json!([]));
+
+    let (status, _, body) = send(test_router(temp_dir.path()), get("/runs/0a1b")).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    assert_eq!(body["code"], "run_not_found");
+    assert_eq!(body["details"]["run_id"], "0a1b");
+
+    let (status, _, body) = send(test_router(temp_dir.path()), get("/runs/not-a-run!")).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(body["code"], "invalid_request");
+}
+
+#[tokio::test]
+async fn test_bearer_token_required() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let state = test_state(temp_dir.path());
+    configure(&state, |config| {
+        config.auth = crate::config::AuthConfig {
+            tokens: vec!["s3cret".to_string()],
+        }
+    });
+    let request = |uri: &str, token: Option<&str>| {
+        let mut builder = Request::builder().uri(uri);
+        if let Some(token) = token {
+            builder = builder.header("authorization", format!("Bearer {}", token));
+        }
+        builder.body(Body::empty()).unwrap()
+    };
+
+    let (status, headers, body) = send(app(state.clone()), request("/sessions", None)).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    assert_eq!(headers["www-authenticate"], "Bearer");
+    assert_eq!(body["code"], "unauthorized");
+    assert!(body["request_id"].is_string());
+
+    let (status, _, _) = send(app(state.clone()), request("/sessions", Some("wrong"))).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+    let (status, _, _) = send(app(state.clone()), request("/sessions", Some("s3cret"))).await;
+    assert_eq!(status, StatusCode::OK);
+
+    let (status, _, _) = send(app(state), request("/health", None)).await;
+    assert_eq!(status, StatusCode::OK);
+}
+
+#[tokio::test]
+async fn test_request_body_limit() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let state = test_state(temp_dir.path());
+    configure(&state, |config| config.max_request_body = 64);
+
+    let prompt = "x".repeat(128);
+    let body = format!(r#"{{"agent_type":"t","prompt":"{}"}}"#, prompt);
+    let (status, _, body) = send(app(state), post_json("/spawn", &body)).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(body["code"], "invalid_request");
+    assert!(
+        body["error"].as_str().unwrap().contains("limit"),
+        "{}",
+        body
+    );
+}
+
+#[tokio::test]
+async fn test_admin_reload() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let state = test_state(temp_dir.path());
+    configure(&state, |config| config.env.passthrough.clear());
+
+    let (status, _, body) = send(app(state.clone()), post_json("/admin/reload", "")).await;
+    assert_eq!(status, StatusCode::OK);
+    // The defaults differ from the test's startup-only settings, which stay
+    let restart_required = body["restart_required"].as_array().unwrap();
+    assert!(restart_required.contains(&"runner.claude_path".into()));
+    let config = state.config.current();
+    assert_eq!(config.claude_path, "/nonexistent/claude");
+    assert!(!config.env.passthrough.is_empty());
+}
+
+#[tokio::test]
+async fn test_metrics() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let state = test_state(temp_dir.path());
+
+    let payload = r#"{"agent_type":"coder","prompt":"p"}"#;
+    let (status, _, _) = send(app(state.clone()), post_json("/spawn", payload)).await;
+    assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+
+    let request = Request::get("/metrics").body(Body::empty()).unwrap();
+    let response = app(state).oneshot(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    let text = String::from_utf8(body.to_vec()).unwrap();
+    assert!(
+        text.contains(r#"q9gent_spawns_total{agent_type="coder",outcome="spawn_failed"} 1"#),
+        "{}",
+        text
+    );
+    assert!(text.contains(
+        r#"q9gent_http_request_duration_seconds_count{method="POST",route="/spawn",status="503"} 1"#
+    ));
+    assert!(text.contains("q9gent_active_processes 0"));
+    assert!(text.contains("q9gent_sessions 0"));
+}
+
+#[tokio::test]
+async fn test_admin_audit() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let mut state = test_state(temp_dir.path());
+    let get = |uri: &str| Request::get(uri).body(Body::empty()).unwrap();
+
+    let (status, _, body) = send(app(state.clone()), get("/admin/audit")).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    assert_eq!(body["code"], "audit_disabled");
+
+    let audit = AuditConfig {
+        dir: Some(temp_dir.path().join("audit")),
+        prompt_chars: None,
+    };
+    state.audit = AuditLog::open(&audit).await.unwrap().map(Arc::new);
+    let (status, _, body) = send(
+        app(state.clone()),
+        get("/admin/audit?since=0&until=2000000000"),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["records"], serde_json::json!([]));
+    assert_eq!(body["chain_intact"], true);
+
+    let (status, _, body) = send(app(state), get("/admin/audit?since=yesterday")).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(body["code"], "invalid_request");
+}
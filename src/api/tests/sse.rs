@@ -0,0 +1,460 @@
+//! End-to-end runs through the router against the `fake-claude` binary
+
+use super::*;
+use axum::body::BodyDataStream;
+use futures::StreamExt;
+use q9gent_client::types::ErrorCode;
+use std::sync::OnceLock;
+use std::time::Duration;
+
+/// Longest wait for a single event before a test fails instead of hanging
+const EVENT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Path of the `fake-claude` binary, built on first use
+fn fake_claude() -> &'static str {
+    static BINARY: OnceLock<String> = OnceLock::new();
+    BINARY.get_or_init(|| {
+        let cargo = std::env::var("CARGO").unwrap_or_else(|_| "cargo".to_string());
+        let manifest = concat!(env!("CARGO_MANIFEST_DIR"), "/Cargo.toml");
+        let mut build = std::process::Command::new(cargo);
+        build.args([
+            "build",
+            "--quiet",
+            "-p",
+            "fake-claude",
+            "--manifest-path",
+            manifest,
+        ]);
+        if !cfg!(debug_assertions) {
+            build.arg("--release");
+        }
+        assert!(
+            build.status().unwrap().success(),
+            "building fake-claude failed"
+        );
+
+        // Test binaries run from target/<profile>/deps
+        let exe = std::env::current_exe().unwrap();
+        let profile_dir = exe.parent().unwrap().parent().unwrap();
+        let binary = profile_dir.join(format!("fake-claude{}", std::env::consts::EXE_SUFFIX));
+        binary.to_string_lossy().to_string()
+    })
+}
+
+/// State whose runner starts `fake-claude`
+fn fake_state(session_dir: &std::path::Path) -> AppState {
+    let mut state = test_state(session_dir);
+    state.agent_runner = Arc::new(
+        AgentRunner::new(fake_claude().to_string())
+            .with_journal(state.journal.clone())
+            .with_metrics(state.metrics.clone()),
+    );
+    state
+}
+
+/// Events of an SSE response, decoded as they arrive
+struct Events {
+    body: BodyDataStream,
+    buffer: Vec<u8>,
+}
+
+impl Events {
+    async fn open(state: &AppState, request: Request<Body>) -> Self {
+        let response = app(state.clone()).oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        Self {
+            body: response.into_body().into_data_stream(),
+            buffer: Vec::new(),
+        }
+    }
+
+    async fn next(&mut self) -> Option<StreamEvent> {
+        loop {
+            if let Some(end) = self.buffer.windows(2).position(|w| w == b"\n\n") {
+                let block: Vec<u8> = self.buffer.drain(..end + 2).collect();
+                let block = String::from_utf8(block).unwrap();
+                let data: Vec<&str> = block
+                    .lines()
+                    .filter_map(|line| line.strip_prefix("data:"))
+                    .map(str::trim_start)
+                    .collect();
+                // Keep-alive comments carry no data
+                if data.is_empty() {
+                    continue;
+                }
+                return Some(serde_json::from_str(&data.join("\n")).unwrap());
+            }
+            let chunk = tokio::time::timeout(EVENT_TIMEOUT, self.body.next())
+                .await
+                .expect("timed out waiting for an event")?;
+            self.buffer.extend_from_slice(&chunk.unwrap());
+        }
+    }
+
+    async fn collect(mut self) -> Vec<StreamEvent> {
+        let mut events = Vec::new();
+        while let Some(event) = self.next().await {
+            events.push(event);
+        }
+        events
+    }
+}
+
+/// `data` of the `output` events, parsed
+fn outputs(events: &[StreamEvent]) -> Vec<serde_json::Value> {
+    events
+        .iter()
+        .filter_map(|event| match event {
+            StreamEvent::Output { data } => Some(serde_json::from_str(data).unwrap()),
+            _ => None,
+        })
+        .collect()
+}
+
+fn error_code(events: &[StreamEvent]) -> Option<ErrorCode> {
+    events.iter().find_map(|event| match event {
+        StreamEvent::Error(error) => Some(error.code),
+        _ => None,
+    })
+}
+
+fn completed_exit_code(events: &[StreamEvent]) -> Option<i32> {
+    match events.last() {
+        Some(StreamEvent::Completed { exit_code, .. }) => *exit_code,
+        other => panic!("stream did not end with completed: {:?}", other),
+    }
+}
+
+/// Spawn a run creating a session and return its ID
+async fn create_session(state: &AppState) -> String {
+    let payload = r#"{"agent_type":"coder","prompt":"Hello","create_session":true}"#;
+    let events = Events::open(state, post_json("/spawn", payload))
+        .await
+        .collect()
+        .await;
+    events
+        .iter()
+        .find_map(|event| match event {
+            StreamEvent::SessionCreated { session_id } => Some(session_id.clone()),
+            _ => None,
+        })
+        .expect("session_created event")
+}
+
+#[tokio::test]
+async fn test_spawn_streams_scripted_conversation() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let state = fake_state(temp_dir.path());
+
+    let script = "say Looking at the file\ntool Read {\"file_path\":\"README.md\"}\nresult Done";
+    let payload = serde_json::json!({
+        "agent_type": "coder",
+        "prompt": script,
+        "tools_allowed": ["Read"],
+        "create_session": true,
+    });
+    let events = Events::open(&state, post_json("/spawn", &payload.to_string()))
+        .await
+        .collect()
+        .await;
+
+    let StreamEvent::RunStarted {
+        run_id: Some(ref run_id),
+        pid: Some(_),
+        ..
+    } = events[0]
+    else {
+        panic!("first event is not run_started: {:?}", events[0]);
+    };
+    let StreamEvent::SessionCreated { ref session_id } = events[1] else {
+        panic!("second event is not session_created: {:?}", events[1]);
+    };
+    let types: Vec<_> = outputs(&events)
+        .iter()
+        .map(|output| output["type"].as_str().unwrap().to_string())
+        .collect();
+    assert_eq!(
+        types,
+        ["system", "assistant", "assistant", "user", "result"]
+    );
+    let outputs = outputs(&events);
+    assert_eq!(outputs[0]["tools"], serde_json::json!(["Read"]));
+    assert_eq!(outputs[0]["session_id"], session_id.as_str());
+    assert_eq!(outputs[2]["message"]["content"][0]["name"], "Read");
+    assert_eq!(outputs[4]["result"], "Done");
+    assert_eq!(completed_exit_code(&events), Some(0));
+
+    let (status, _, body) = send(
+        app(state.clone()),
+        Request::get(format!("/sessions/{}", session_id))
+            .body(Body::empty())
+            .unwrap(),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["agent_type"], "coder");
+
+    let (status, _, body) = send(
+        app(state),
+        Request::get(format!("/runs/{}", run_id))
+            .body(Body::empty())
+            .unwrap(),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["status"], "completed");
+}
+
+#[tokio::test]
+async fn test_delete_session_removes_only_temp_workspace() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let state = fake_state(temp_dir.path());
+    let own_dir = temp_dir.path().join("own");
+    std::fs::create_dir(&own_dir).unwrap();
+
+    // A temporary workspace goes with the session, a caller's workdir stays
+    let workspaces = [
+        (serde_json::json!({"temp_workspace": true}), true),
+        (serde_json::json!({"workdir": own_dir}), false),
+    ];
+    for (mut payload, temporary) in workspaces {
+        payload["agent_type"] = "coder".into();
+        payload["prompt"] = "Hello".into();
+        payload["create_session"] = true.into();
+        let events = Events::open(&state, post_json("/spawn", &payload.to_string()))
+            .await
+            .collect()
+            .await;
+        let StreamEvent::RunStarted {
+            workdir: Some(ref workdir),
+            ..
+        } = events[0]
+        else {
+            panic!("run did not report its workdir: {:?}", events[0]);
+        };
+        let StreamEvent::SessionCreated { ref session_id } = events[1] else {
+            panic!("second event is not session_created: {:?}", events[1]);
+        };
+        assert!(std::path::Path::new(workdir).is_dir());
+
+        let uri = format!("/sessions/{}", session_id);
+        let get = Request::get(&uri).body(Body::empty()).unwrap();
+        let (_, _, body) = send(app(state.clone()), get).await;
+        assert_eq!(body["temp_workspace"].as_bool().unwrap_or(false), temporary);
+
+        let delete = Request::delete(&uri).body(Body::empty()).unwrap();
+        let (status, _, _) = send(app(state.clone()), delete).await;
+        assert_eq!(status, StatusCode::NO_CONTENT);
+        assert_eq!(std::path::Path::new(workdir).exists(), !temporary);
+    }
+}
+
+#[tokio::test]
+async fn test_message_resumes_session() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let state = fake_state(temp_dir.path());
+    let session_id = create_session(&state).await;
+
+    let payload = r#"{"prompt":"args","tools_allowed":["Bash"]}"#;
+    let uri = format!("/message/{}", session_id);
+    let events = Events::open(&state, post_json(&uri, payload))
+        .await
+        .collect()
+        .await;
+
+    let outputs = outputs(&events);
+    assert_eq!(outputs[0]["session_id"], session_id.as_str());
+    let args: Vec<String> = serde_json::from_str(
+        outputs[1]["message"]["content"][0]["text"]
+            .as_str()
+            .unwrap(),
+    )
+    .unwrap();
+    let resume = args.iter().position(|arg| arg == "--resume").unwrap();
+    assert_eq!(args[resume + 1], session_id);
+    assert!(args.windows(2).any(|w| w == ["--allowedTools", "Bash"]));
+    assert_eq!(completed_exit_code(&events), Some(0));
+}
+
+#[tokio::test]
+async fn test_terminate_running_message() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let state = fake_state(temp_dir.path());
+    let session_id = create_session(&state).await;
+
+    let uri = format!("/message/{}", session_id);
+    let payload = r#"{"prompt":"say Working on it\nhang"}"#;
+    let mut events = Events::open(&state, post_json(&uri, payload)).await;
+    // Wait until the process is running and has said something
+    loop {
+        match events.next().await.unwrap() {
+            StreamEvent::Output { data } if data.contains("Working on it") => break,
+            _ => continue,
+        }
+    }
+
+    // A second turn cannot start while the first is running
+    let (status, _, body) = send(app(state.clone()), post_json(&uri, payload)).await;
+    assert_eq!(status, StatusCode::CONFLICT);
+    assert_eq!(body["code"], "session_busy");
+
+    let terminate = format!("/terminate/{}", session_id);
+    let (status, _, _) = send(app(state.clone()), post_json(&terminate, "")).await;
+    assert_eq!(status, StatusCode::OK);
+
+    let rest = events.collect().await;
+    let Some(StreamEvent::Completed { terminated, .. }) = rest.last() else {
+        panic!("stream did not end with completed: {:?}", rest);
+    };
+    assert!(terminated);
+
+    let (status, _, body) = send(app(state), post_json(&terminate, "")).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    assert_eq!(body["code"], "process_not_running");
+}
+
+#[tokio::test]
+async fn test_terminate_running_spawn() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let state = fake_state(temp_dir.path());
+
+    let payload =
+        r#"{"agent_type":"coder","prompt":"say Working on it\nhang","create_session":true}"#;
+    let mut events = Events::open(&state, post_json("/spawn", payload)).await;
+    // The run is tracked under its session once Claude has reported it
+    let session_id = loop {
+        match events.next().await.unwrap() {
+            StreamEvent::SessionCreated { session_id } => break session_id,
+            _ => continue,
+        }
+    };
+
+    let terminate = format!("/terminate/{}", session_id);
+    let (status, _, _) = send(app(state.clone()), post_json(&terminate, "")).await;
+    assert_eq!(status, StatusCode::OK);
+
+    let rest = events.collect().await;
+    let Some(StreamEvent::Completed { terminated, .. }) = rest.last() else {
+        panic!("stream did not end with completed: {:?}", rest);
+    };
+    assert!(terminated);
+}
+
+#[tokio::test]
+async fn test_failed_runs_end_with_error_event() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let state = fake_state(temp_dir.path());
+    let spawn = |prompt: &str| {
+        let payload = serde_json::json!({ "agent_type": "t", "prompt": prompt });
+        post_json("/spawn", &payload.to_string())
+    };
+
+    // Fails before writing any stream-json
+    let events = Events::open(&state, spawn("stderr Error: Invalid API key\nexit 1"))
+        .await
+        .collect()
+        .await;
+    assert!(outputs(&events).is_empty());
+    assert!(events.contains(&StreamEvent::Stderr {
+        data: "Error: Invalid API key".to_string()
+    }));
+    assert_eq!(error_code(&events), Some(ErrorCode::AuthFailed));
+    assert_eq!(completed_exit_code(&events), Some(1));
+
+    // Fails with an error result after some output
+    let events = Events::open(&state, spawn("say Starting\nerror Usage limit reached"))
+        .await
+        .collect()
+        .await;
+    assert_eq!(outputs(&events).last().unwrap()["is_error"], true);
+    assert_eq!(error_code(&events), Some(ErrorCode::RateLimited));
+    assert_eq!(completed_exit_code(&events), Some(1));
+
+    // Exit codes without a recognizable cause are process failures
+    let events = Events::open(&state, spawn("say Partial\nexit 3"))
+        .await
+        .collect()
+        .await;
+    assert_eq!(error_code(&events), Some(ErrorCode::ProcessFailed));
+    assert_eq!(completed_exit_code(&events), Some(3));
+}
+
+#[tokio::test]
+async fn test_stderr_delays_and_raw_lines_do_not_end_run() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let state = fake_state(temp_dir.path());
+
+    let script = "stderr warning: slow network\nsleep 300\nraw not json\nsay Finished";
+    let payload = serde_json::json!({ "agent_type": "t", "prompt": script });
+    let events = Events::open(&state, post_json("/spawn", &payload.to_string()))
+        .await
+        .collect()
+        .await;
+
+    assert!(events.contains(&StreamEvent::Stderr {
+        data: "warning: slow network".to_string()
+    }));
+    assert!(events.contains(&StreamEvent::Output {
+        data: "not json".to_string()
+    }));
+    assert_eq!(error_code(&events), None);
+    assert_eq!(completed_exit_code(&events), Some(0));
+}
+
+#[tokio::test]
+async fn test_recorded_runs_replay_without_claude() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let cassettes = temp_dir.path().join("cassettes");
+    let mut state = fake_state(temp_dir.path());
+    state.agent_runner = Arc::new(
+        AgentRunner::new(fake_claude().to_string())
+            .with_cassettes(Some(Cassettes::Record(cassettes.clone()))),
+    );
+    let script = "session recorded-1\nsay Reading\ntool Read\nsleep 50\nresult Done";
+    let payload = serde_json::json!({
+        "agent_type": "coder",
+        "prompt": script,
+        "tools_allowed": ["Read"],
+        "create_session": true,
+    });
+    let recorded = Events::open(&state, post_json("/spawn", &payload.to_string()))
+        .await
+        .collect()
+        .await;
+
+    let replay_dir = tempfile::tempdir().unwrap();
+    let mut state = fake_state(replay_dir.path());
+    state.agent_runner = Arc::new(
+        AgentRunner::new("/nonexistent/claude".to_string())
+            .with_journal(state.journal.clone())
+            .with_cassettes(Some(Cassettes::Replay {
+                dir: cassettes,
+                speed: 0.0,
+            })),
+    );
+    let replayed = Events::open(&state, post_json("/spawn", &payload.to_string()))
+        .await
+        .collect()
+        .await;
+    assert_eq!(outputs(&replayed), outputs(&recorded));
+    assert!(replayed.contains(&StreamEvent::SessionCreated {
+        session_id: "recorded-1".to_string()
+    }));
+    assert_eq!(completed_exit_code(&replayed), Some(0));
+
+    let payload = r#"{"agent_type":"coder","prompt":"Something else"}"#;
+    let (status, _, body) = send(app(state.clone()), post_json("/spawn", payload)).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    assert_eq!(body["code"], "cassette_not_found");
+
+    let (status, _, body) = send(
+        app(state),
+        Request::get("/ready").body(Body::empty()).unwrap(),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(body["checks"][0]["detail"]
+        .as_str()
+        .unwrap()
+        .starts_with("replaying cassettes"));
+}
@@ -0,0 +1,409 @@
+use sha2::{Digest, Sha256};
+use std::path::{Path, PathBuf};
+use tokio::fs;
+use tokio::io::AsyncWriteExt;
+use tokio::sync::Mutex;
+use tracing::{debug, warn};
+
+use q9gent_client::types::{AuditList, AuditRecord, ResourceUsage, RunStatus};
+
+use crate::agent::AgentRequest;
+use crate::claude::ClaudeCli;
+use crate::config::AuditConfig;
+use crate::error::AppResult;
+
+/// `prev_hash` of the first record
+const GENESIS_HASH: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+
+/// Who started a run, as recorded in the audit log
+#[derive(Debug, Clone, Default)]
+pub struct Caller {
+    /// `token:<fingerprint>`, `anonymous` or `local:<user>`
+    pub identity: String,
+
+    /// Peer address of the connection, if it has one
+    pub source: Option<String>,
+
+    pub request_id: Option<String>,
+}
+
+impl Caller {
+    /// The user running `q9gent run`
+    pub fn local() -> Self {
+        let user = std::env::var("USER")
+            .or_else(|_| std::env::var("USERNAME"))
+            .unwrap_or_else(|_| "unknown".to_string());
+        Self {
+            identity: format!("local:{}", user),
+            ..Default::default()
+        }
+    }
+
+    /// A caller authenticated with `token`, identified by a fingerprint that
+    /// does not reveal the token
+    pub fn token(token: &str) -> String {
+        format!("token:{}", &sha256_hex(token.as_bytes())[..12])
+    }
+}
+
+pub fn sha256_hex(data: &[u8]) -> String {
+    Sha256::digest(data)
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+fn now() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
+
+/// UTC date of a Unix timestamp, as used in file names
+fn date(timestamp: u64) -> String {
+    time::OffsetDateTime::from_unix_timestamp(timestamp as i64)
+        .map(|time| time.date().to_string())
+        .unwrap_or_default()
+}
+
+/// Hash of a record: SHA-256 of its JSON with an empty `hash`
+fn record_hash(record: &AuditRecord) -> String {
+    let unhashed = AuditRecord {
+        hash: String::new(),
+        ..record.clone()
+    };
+    sha256_hex(&serde_json::to_vec(&unhashed).expect("audit records serialize"))
+}
+
+/// Append-only audit log of runs, one JSON record per line in
+/// `<dir>/audit-<YYYY-MM-DD>.jsonl` by the UTC day the run ended
+///
+/// Each record carries the hash of the one before it, across files, so
+/// editing, removing or reordering records breaks the chain.
+pub struct AuditLog {
+    dir: PathBuf,
+    prompt_chars: Option<usize>,
+
+    /// The claude CLI runs are started with, recorded on every run
+    claude: Option<ClaudeCli>,
+
+    /// Hash of the last record written; held while a record is appended
+    last_hash: Mutex<String>,
+}
+
+impl AuditLog {
+    /// Open the audit log in `config.dir`, continuing the chain of the
+    /// newest file
+    pub async fn open(config: &AuditConfig) -> anyhow::Result<Option<Self>> {
+        let Some(ref dir) = config.dir else {
+            return Ok(None);
+        };
+        let last_hash = async {
+            fs::create_dir_all(dir).await?;
+            match files(dir).await?.last() {
+                Some(path) => last_record_hash(path).await,
+                None => Ok(GENESIS_HASH.to_string()),
+            }
+        }
+        .await
+        .map_err(|e: std::io::Error| anyhow::anyhow!("audit.dir: {}: {}", dir.display(), e))?;
+        Ok(Some(Self {
+            dir: dir.clone(),
+            prompt_chars: config.prompt_chars,
+            claude: None,
+            last_hash: Mutex::new(last_hash),
+        }))
+    }
+
+    /// Record the claude CLI found at startup on every run
+    pub fn with_claude(mut self, claude: Option<ClaudeCli>) -> Self {
+        self.claude = claude;
+        self
+    }
+
+    /// Record of a run that has just started; [`AuditLog::finish`] completes
+    /// and appends it when the run ends
+    ///
+    /// `args` are claude's arguments; the prompt among them is hashed or
+    /// truncated as configured.
+    pub fn begin(
+        &self,
+        request: &AgentRequest,
+        args: &[String],
+        run_id: &str,
+        workdir: Option<String>,
+    ) -> AuditRecord {
+        let args = args
+            .iter()
+            .enumerate()
+            .map(
+                |(i, arg)| match i.checked_sub(1).map(|prev| args[prev].as_str()) {
+                    Some("-p") => self.prompt(arg),
+                    _ => arg.clone(),
+                },
+            )
+            .collect();
+        AuditRecord {
+            run_id: run_id.to_string(),
+            request_id: request.caller.request_id.clone(),
+            caller: request.caller.identity.clone(),
+            source: request.caller.source.clone(),
+            agent_type: request.agent_type.clone(),
+            args,
+            tools_allowed: request.tools_allowed.clone(),
+            workdir,
+            claude_path: self
+                .claude
+                .as_ref()
+                .map(|claude| claude.path.display().to_string()),
+            claude_version: self
+                .claude
+                .as_ref()
+                .and_then(|claude| claude.version.clone()),
+            started_at: now(),
+            ended_at: 0,
+            status: RunStatus::Running,
+            exit_code: None,
+            usage: None,
+            cost_usd: None,
+            resources: None,
+            prev_hash: String::new(),
+            hash: String::new(),
+        }
+    }
+
+    fn prompt(&self, prompt: &str) -> String {
+        match self.prompt_chars {
+            Some(chars) if prompt.chars().count() > chars => {
+                format!("{}…", prompt.chars().take(chars).collect::<String>())
+            }
+            Some(_) => prompt.to_string(),
+            None => format!("sha256:{}", sha256_hex(prompt.as_bytes())),
+        }
+    }
+
+    /// Complete a run's record with how it ended and append it; failures are
+    /// logged, never fail the run
+    pub async fn finish(
+        &self,
+        mut record: AuditRecord,
+        status: RunStatus,
+        exit_code: Option<i32>,
+        result: Option<&serde_json::Value>,
+        resources: Option<ResourceUsage>,
+    ) {
+        record.ended_at = now();
+        record.status = status;
+        record.exit_code = exit_code;
+        record.usage = result.and_then(|result| result.get("usage")).cloned();
+        record.cost_usd = result
+            .and_then(|result| result.get("total_cost_usd"))
+            .and_then(|cost| cost.as_f64());
+        record.resources = resources;
+
+        let run_id = record.run_id.clone();
+        match self.append(record).await {
+            Ok(()) => debug!("🧾 Audited run {}", run_id),
+            Err(e) => warn!("⚠️  Failed to write audit record of run {}: {}", run_id, e),
+        }
+    }
+
+    async fn append(&self, mut record: AuditRecord) -> std::io::Result<()> {
+        let mut last_hash = self.last_hash.lock().await;
+        record.prev_hash = last_hash.clone();
+        record.hash = record_hash(&record);
+        let mut line = serde_json::to_vec(&record)?;
+        line.push(b'\n');
+
+        let path = self
+            .dir
+            .join(format!("audit-{}.jsonl", date(record.ended_at)));
+        let mut file = fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .await?;
+        file.write_all(&line).await?;
+        file.sync_data().await?;
+        *last_hash = record.hash;
+        Ok(())
+    }
+
+    /// Records of runs active at some point between the Unix timestamps
+    /// `since` and `until`, oldest first, after verifying the chain from the
+    /// first day in range onwards
+    pub async fn query(&self, since: Option<u64>, until: Option<u64>) -> AppResult<AuditList> {
+        // Held so no record is appended while the chain is read
+        let _guard = self.last_hash.lock().await;
+        let files = files(&self.dir).await?;
+        let first = since.map_or(0, |since| {
+            let first_day = format!("audit-{}.jsonl", date(since));
+            files.partition_point(|path| path.file_name().is_some_and(|name| *name < *first_day))
+        });
+        let mut prev_hash = match first {
+            0 => GENESIS_HASH.to_string(),
+            _ => last_record_hash(&files[first - 1]).await?,
+        };
+
+        let mut records = Vec::new();
+        let mut chain_intact = true;
+        for path in &files[first..] {
+            let text = fs::read_to_string(path).await?;
+            for line in text.lines().filter(|line| !line.is_empty()) {
+                let Ok(record) = serde_json::from_str::<AuditRecord>(line) else {
+                    chain_intact = false;
+                    continue;
+                };
+                if record.prev_hash != prev_hash || record_hash(&record) != record.hash {
+                    chain_intact = false;
+                }
+                prev_hash = record.hash.clone();
+                let in_range = since.is_none_or(|since| record.ended_at >= since)
+                    && until.is_none_or(|until| record.started_at < until);
+                if in_range {
+                    records.push(record);
+                }
+            }
+        }
+        if !chain_intact {
+            warn!(
+                "⚠️  Audit log hash chain is broken in {}",
+                self.dir.display()
+            );
+        }
+        Ok(AuditList {
+            records,
+            chain_intact,
+        })
+    }
+}
+
+/// Audit files in `dir`, oldest first
+async fn files(dir: &Path) -> std::io::Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    let mut entries = fs::read_dir(dir).await?;
+    while let Some(entry) = entries.next_entry().await? {
+        let name = entry.file_name();
+        let name = name.to_string_lossy();
+        if name.starts_with("audit-") && name.ends_with(".jsonl") {
+            files.push(entry.path());
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+/// Hash of the last record in an audit file; a file without one continues
+/// the chain from the start
+async fn last_record_hash(path: &Path) -> std::io::Result<String> {
+    let text = fs::read_to_string(path).await?;
+    let Some(line) = text.lines().rev().find(|line| !line.is_empty()) else {
+        return Ok(GENESIS_HASH.to_string());
+    };
+    match serde_json::from_str::<AuditRecord>(line) {
+        Ok(record) => Ok(record.hash),
+        Err(e) => {
+            warn!(
+                "⚠️  Last audit record in {} is unreadable ({}); the chain restarts",
+                path.display(),
+                e
+            );
+            Ok(GENESIS_HASH.to_string())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(prompt: &str) -> AgentRequest {
+        AgentRequest {
+            agent_type: "reviewer".to_string(),
+            prompt: prompt.to_string(),
+            flags: Vec::new(),
+            tools_allowed: vec!["Read".to_string()],
+            system_append: None,
+            resume_id: None,
+            env: Default::default(),
+            redactor: Default::default(),
+            limits: Default::default(),
+            caller: Caller {
+                identity: Caller::token("secret-token"),
+                source: Some("10.0.0.7:51234".to_string()),
+                request_id: Some("req-1".to_string()),
+            },
+        }
+    }
+
+    fn args(prompt: &str) -> Vec<String> {
+        ["-p", prompt, "--output-format", "stream-json"]
+            .iter()
+            .map(|arg| arg.to_string())
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_records_chain_and_tampering_is_detected() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let config = AuditConfig {
+            dir: Some(temp_dir.path().to_path_buf()),
+            prompt_chars: None,
+        };
+        let log = AuditLog::open(&config).await.unwrap().unwrap();
+        for run_id in ["run-1", "run-2"] {
+            let request = request("Review the secret plan");
+            let record = log.begin(&request, &args(&request.prompt), run_id, None);
+            let result = serde_json::json!({
+                "type": "result",
+                "usage": { "input_tokens": 10, "output_tokens": 20 },
+                "total_cost_usd": 0.25,
+            });
+            log.finish(record, RunStatus::Completed, Some(0), Some(&result), None)
+                .await;
+        }
+
+        let list = log.query(None, None).await.unwrap();
+        assert!(list.chain_intact);
+        assert_eq!(list.records.len(), 2);
+        let first = &list.records[0];
+        assert_eq!(first.prev_hash, GENESIS_HASH);
+        assert_eq!(list.records[1].prev_hash, first.hash);
+        assert_eq!(first.caller, Caller::token("secret-token"));
+        assert!(!first.caller.contains("secret-token"));
+        assert_eq!(first.source.as_deref(), Some("10.0.0.7:51234"));
+        assert!(first.args[1].starts_with("sha256:"));
+        assert_eq!(first.usage.as_ref().unwrap()["output_tokens"], 20);
+        assert_eq!(first.cost_usd, Some(0.25));
+
+        // A reopened log continues the chain
+        let reopened = AuditLog::open(&config).await.unwrap().unwrap();
+        assert_eq!(*reopened.last_hash.lock().await, list.records[1].hash);
+
+        // Nothing started after the runs ended
+        let later = log.query(Some(now() + 60), None).await.unwrap();
+        assert!(later.records.is_empty());
+
+        let path = files(temp_dir.path()).await.unwrap().remove(0);
+        let text = std::fs::read_to_string(&path).unwrap();
+        std::fs::write(&path, text.replacen("reviewer", "coder", 1)).unwrap();
+        assert!(!log.query(None, None).await.unwrap().chain_intact);
+    }
+
+    #[tokio::test]
+    async fn test_prompt_truncation() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let log = AuditLog::open(&AuditConfig {
+            dir: Some(temp_dir.path().to_path_buf()),
+            prompt_chars: Some(6),
+        })
+        .await
+        .unwrap()
+        .unwrap();
+        let request = request("Review the secret plan");
+        let record = log.begin(&request, &args(&request.prompt), "run-1", None);
+        assert_eq!(record.args[1], "Review…");
+        assert_eq!(record.args[2], "--output-format");
+    }
+}
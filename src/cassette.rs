@@ -0,0 +1,315 @@
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tracing::{info, warn};
+use uuid::Uuid;
+
+use crate::agent::AgentRequest;
+use crate::audit::sha256_hex;
+use crate::config::{CassetteConfig, CassetteMode};
+use crate::error::{AppError, AppResult};
+
+/// What the runner does with cassettes
+#[derive(Debug, Clone, PartialEq)]
+pub enum Cassettes {
+    /// Start claude and save each finished run in the directory
+    Record(PathBuf),
+
+    /// Serve runs from the directory instead of starting claude, `speed`
+    /// times faster than recorded; 0 replays without delays
+    Replay { dir: PathBuf, speed: f64 },
+}
+
+impl Cassettes {
+    /// Cassette handling `config` asks for; `None` when it is off
+    pub fn from_config(config: &CassetteConfig) -> Option<Self> {
+        let dir = config.dir.clone()?;
+        match config.mode {
+            CassetteMode::Off => None,
+            CassetteMode::Record => Some(Self::Record(dir)),
+            CassetteMode::Replay => Some(Self::Replay {
+                dir,
+                speed: config.replay_speed,
+            }),
+        }
+    }
+}
+
+/// The parts of a request a cassette is recorded for, normalized so that
+/// requests differing only in whitespace or tool order share a cassette
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CassetteKey {
+    pub agent_type: String,
+    pub prompt: String,
+
+    /// Allowed tools, sorted and without duplicates
+    pub tools: Vec<String>,
+
+    /// Appended system prompt; a blank one counts as none
+    pub system_append: Option<String>,
+}
+
+impl CassetteKey {
+    pub fn new(request: &AgentRequest) -> Self {
+        let mut tools: Vec<String> = request
+            .tools_allowed
+            .iter()
+            .map(|tool| tool.trim().to_string())
+            .filter(|tool| !tool.is_empty())
+            .collect();
+        tools.sort();
+        tools.dedup();
+        Self {
+            agent_type: request.agent_type.trim().to_string(),
+            prompt: normalize_text(&request.prompt),
+            tools,
+            system_append: request
+                .system_append
+                .as_deref()
+                .map(normalize_text)
+                .filter(|text| !text.is_empty()),
+        }
+    }
+
+    /// Name of the cassette file: the SHA-256 of the key's JSON
+    pub fn file_name(&self) -> String {
+        let json = serde_json::to_vec(self).expect("cassette keys serialize");
+        format!("{}.json", sha256_hex(&json))
+    }
+}
+
+/// Text with `\n` line endings, without trailing whitespace on each line and
+/// without leading and trailing blank space
+fn normalize_text(text: &str) -> String {
+    text.lines()
+        .map(str::trim_end)
+        .collect::<Vec<_>>()
+        .join("\n")
+        .trim()
+        .to_string()
+}
+
+/// Pipe a recorded line was read from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Stream {
+    Stdout,
+    Stderr,
+}
+
+/// One line of output and when it arrived
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CassetteLine {
+    /// Milliseconds after the process started
+    pub at_ms: u64,
+    pub stream: Stream,
+    pub line: String,
+}
+
+/// A recorded run, stored as `<dir>/<key hash>.json`
+///
+/// Lines are stored as they were sent to the client, after redaction.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Cassette {
+    pub key: CassetteKey,
+
+    /// Unix time the run ended
+    pub recorded_at: u64,
+
+    /// Exit code of the process, `None` if it was killed by a signal
+    pub exit_code: Option<i32>,
+
+    /// Milliseconds from the start of the process until it exited
+    pub duration_ms: u64,
+
+    pub lines: Vec<CassetteLine>,
+}
+
+/// Time after the start of a replay at which something recorded `at_ms`
+/// after the start is due
+pub fn replay_delay(at_ms: u64, speed: f64) -> Duration {
+    if speed <= 0.0 {
+        Duration::ZERO
+    } else {
+        Duration::from_nanos((at_ms as f64 * 1_000_000.0 / speed) as u64)
+    }
+}
+
+/// Read the cassette recorded for `key` from `dir`
+pub async fn load(dir: &Path, key: &CassetteKey) -> AppResult<Cassette> {
+    let name = key.file_name();
+    let path = dir.join(&name);
+    let data = match tokio::fs::read(&path).await {
+        Ok(data) => data,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            warn!(
+                "📼 No cassette for agent type '{}' in {}",
+                key.agent_type,
+                dir.display()
+            );
+            return Err(AppError::CassetteNotFound(name));
+        }
+        Err(e) => return Err(e.into()),
+    };
+    let cassette: Cassette = serde_json::from_slice(&data)?;
+    if cassette.key != *key {
+        warn!(
+            "📼 Cassette {} was recorded for another request",
+            path.display()
+        );
+        return Err(AppError::CassetteNotFound(name));
+    }
+    Ok(cassette)
+}
+
+/// Collects a run's output as it is sent and saves it as a cassette once the
+/// run has ended
+pub struct Recorder {
+    dir: PathBuf,
+    key: CassetteKey,
+    started: Instant,
+    lines: Mutex<Vec<CassetteLine>>,
+}
+
+impl Recorder {
+    /// Start recording the run of `request`; call when its process started
+    pub fn new(dir: &Path, request: &AgentRequest) -> Self {
+        Self {
+            dir: dir.to_path_buf(),
+            key: CassetteKey::new(request),
+            started: Instant::now(),
+            lines: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Append one line of output
+    pub fn record(&self, stream: Stream, line: &str) {
+        let at_ms = self.started.elapsed().as_millis() as u64;
+        self.lines.lock().unwrap().push(CassetteLine {
+            at_ms,
+            stream,
+            line: line.to_string(),
+        });
+    }
+
+    /// Write the cassette once the process has exited, replacing an earlier
+    /// recording of the same request
+    pub async fn finish(&self, exit_code: Option<i32>) {
+        let cassette = Cassette {
+            key: self.key.clone(),
+            recorded_at: std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .unwrap()
+                .as_secs(),
+            exit_code,
+            duration_ms: self.started.elapsed().as_millis() as u64,
+            lines: std::mem::take(&mut *self.lines.lock().unwrap()),
+        };
+        let path = self.dir.join(self.key.file_name());
+        match self.write(&cassette, &path).await {
+            Ok(()) => info!("📼 Cassette recorded: {}", path.display()),
+            Err(e) => warn!("⚠️  Failed to write cassette {}: {}", path.display(), e),
+        }
+    }
+
+    /// Write through a temporary file so a replaying server never reads a
+    /// partial cassette
+    async fn write(&self, cassette: &Cassette, path: &Path) -> std::io::Result<()> {
+        tokio::fs::create_dir_all(&self.dir).await?;
+        let temp = self.dir.join(format!(".{}.tmp", Uuid::new_v4()));
+        let mut data = serde_json::to_vec_pretty(cassette)?;
+        data.push(b'\n');
+        tokio::fs::write(&temp, data).await?;
+        if let Err(e) = tokio::fs::rename(&temp, path).await {
+            let _ = tokio::fs::remove_file(&temp).await;
+            return Err(e);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::audit::Caller;
+    use crate::limits::RunLimits;
+    use crate::redact::Redactor;
+
+    fn request(prompt: &str, tools: &[&str], system_append: Option<&str>) -> AgentRequest {
+        AgentRequest {
+            agent_type: "coder".to_string(),
+            prompt: prompt.to_string(),
+            flags: vec![],
+            tools_allowed: tools.iter().map(|tool| tool.to_string()).collect(),
+            system_append: system_append.map(str::to_string),
+            resume_id: None,
+            env: Default::default(),
+            redactor: Redactor::default(),
+            limits: RunLimits::default(),
+            caller: Caller::default(),
+        }
+    }
+
+    #[test]
+    fn test_key_normalization() {
+        let key = CassetteKey::new(&request("Fix the bug\nin main.rs", &["Read", "Edit"], None));
+        let same = [
+            request(
+                "  Fix the bug  \r\nin main.rs\n\n",
+                &["Edit", "Read", "Read"],
+                None,
+            ),
+            request("Fix the bug\nin main.rs", &["Read", " Edit"], Some("  \n")),
+        ];
+        for request in same {
+            assert_eq!(CassetteKey::new(&request), key);
+            assert_eq!(CassetteKey::new(&request).file_name(), key.file_name());
+        }
+
+        let different = [
+            request("Fix the bug in main.rs", &["Read", "Edit"], None),
+            request("Fix the bug\nin main.rs", &["Read"], None),
+            request(
+                "Fix the bug\nin main.rs",
+                &["Read", "Edit"],
+                Some("Be brief"),
+            ),
+        ];
+        for request in different {
+            assert_ne!(CassetteKey::new(&request).file_name(), key.file_name());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_record_and_load() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let hello = request("Hello", &[], None);
+        let key = CassetteKey::new(&hello);
+        assert!(matches!(
+            load(temp_dir.path(), &key).await,
+            Err(AppError::CassetteNotFound(name)) if name == key.file_name()
+        ));
+
+        let recorder = Recorder::new(temp_dir.path(), &hello);
+        recorder.record(Stream::Stdout, "{\"type\":\"system\"}");
+        recorder.record(Stream::Stderr, "warning");
+        recorder.finish(Some(0)).await;
+
+        let cassette = load(temp_dir.path(), &key).await.unwrap();
+        assert_eq!(cassette.key, key);
+        assert_eq!(cassette.exit_code, Some(0));
+        assert_eq!(cassette.lines.len(), 2);
+        assert_eq!(cassette.lines[1].stream, Stream::Stderr);
+        assert!(cassette.lines[0].at_ms <= cassette.duration_ms);
+        // Only the cassette is left, no temporary file
+        assert_eq!(std::fs::read_dir(temp_dir.path()).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn test_replay_delay() {
+        assert_eq!(replay_delay(1500, 1.0), Duration::from_millis(1500));
+        assert_eq!(replay_delay(1500, 10.0), Duration::from_millis(150));
+        assert_eq!(replay_delay(1500, 0.0), Duration::ZERO);
+    }
+}
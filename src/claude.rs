@@ -0,0 +1,322 @@
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tokio::process::Command;
+use tracing::{debug, info, warn};
+
+use crate::config::ClaudeVersionCheck;
+
+/// Oldest claude CLI whose flags and stream-json output Q9gent relies on
+const MIN_VERSION: Version = (1, 0, 0);
+
+/// First major version not known to be compatible
+const UNSUPPORTED_MAJOR: u64 = 3;
+
+/// Time `claude --version` gets before it counts as failed
+const VERSION_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Major, minor and patch numbers
+type Version = (u64, u64, u64);
+
+/// The claude CLI found at startup
+#[derive(Debug, Clone, PartialEq)]
+pub struct ClaudeCli {
+    /// Absolute path of the executable
+    pub path: PathBuf,
+
+    /// Output of `claude --version`, if it could be run
+    pub version: Option<String>,
+}
+
+/// Resolve the configured claude CLI and check its version as `check` asks
+///
+/// With [`ClaudeVersionCheck::Warn`], problems are logged and the server
+/// starts anyway; with [`ClaudeVersionCheck::Strict`] they are errors.
+pub async fn discover(
+    claude_path: &str,
+    check: ClaudeVersionCheck,
+) -> anyhow::Result<Option<ClaudeCli>> {
+    if check == ClaudeVersionCheck::Off {
+        return Ok(None);
+    }
+    let fail = |message: String| -> anyhow::Result<()> {
+        match check {
+            ClaudeVersionCheck::Strict => Err(anyhow::anyhow!("runner.claude_path: {}", message)),
+            _ => {
+                warn!("⚠️  {}", message);
+                Ok(())
+            }
+        }
+    };
+
+    let Some(path) = resolve(claude_path) else {
+        fail(format!(
+            "'{}' not found in PATH or the usual npm and installer directories",
+            claude_path
+        ))?;
+        return Ok(None);
+    };
+    let version = match version(&path.to_string_lossy()).await {
+        Ok(version) => version,
+        Err(e) => {
+            fail(e)?;
+            return Ok(Some(ClaudeCli {
+                path,
+                version: None,
+            }));
+        }
+    };
+    match parse_version(&version) {
+        Some(parsed) if supported(parsed) => {
+            info!("✓ Claude CLI {} at {}", version, path.display());
+        }
+        _ => fail(format!(
+            "{} reports version '{}'; supported versions are >= {} and < {}.0.0",
+            path.display(),
+            version,
+            format_version(MIN_VERSION),
+            UNSUPPORTED_MAJOR
+        ))?,
+    }
+    Ok(Some(ClaudeCli {
+        path,
+        version: Some(version),
+    }))
+}
+
+/// Find the claude executable
+///
+/// A path with a directory is used as given; a bare name is looked up in
+/// `PATH` and then in the directories npm and Claude's installer use.
+pub fn resolve(claude_path: &str) -> Option<PathBuf> {
+    let path = Path::new(claude_path);
+    if path.is_absolute() || path.components().count() > 1 {
+        return is_executable(path)
+            .then(|| std::path::absolute(path).ok())
+            .flatten();
+    }
+    let path_dirs = std::env::var_os("PATH")
+        .map(|paths| std::env::split_paths(&paths).collect::<Vec<_>>())
+        .unwrap_or_default();
+    search(claude_path, path_dirs.into_iter().chain(install_dirs()))
+}
+
+/// First executable named `name` in `dirs`
+fn search(name: &str, dirs: impl IntoIterator<Item = PathBuf>) -> Option<PathBuf> {
+    dirs.into_iter()
+        .filter(|dir| dir.is_absolute())
+        .flat_map(|dir| candidates(name).map(move |file| dir.join(file)))
+        .find(|path| is_executable(path))
+}
+
+/// File names a command may have on this platform
+#[cfg(target_os = "windows")]
+fn candidates(name: &str) -> impl Iterator<Item = String> + '_ {
+    let extensions: &[&str] = if Path::new(name).extension().is_some() {
+        &[""]
+    } else {
+        &[".exe", ".cmd", ".bat", ""]
+    };
+    extensions.iter().map(move |ext| format!("{}{}", name, ext))
+}
+
+/// File names a command may have on this platform
+#[cfg(not(target_os = "windows"))]
+fn candidates(name: &str) -> impl Iterator<Item = String> + '_ {
+    std::iter::once(name.to_string())
+}
+
+#[cfg(unix)]
+fn is_executable(path: &Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+
+    std::fs::metadata(path)
+        .map(|metadata| metadata.is_file() && metadata.permissions().mode() & 0o111 != 0)
+        .unwrap_or(false)
+}
+
+#[cfg(not(unix))]
+fn is_executable(path: &Path) -> bool {
+    path.is_file()
+}
+
+/// Where npm global installs and Claude's native installer put `claude`
+/// when their directory is not on the server's `PATH`
+fn install_dirs() -> Vec<PathBuf> {
+    let mut dirs = Vec::new();
+    if let Some(prefix) = std::env::var_os("NPM_CONFIG_PREFIX") {
+        let prefix = PathBuf::from(prefix);
+        dirs.push(if cfg!(target_os = "windows") {
+            prefix
+        } else {
+            prefix.join("bin")
+        });
+    }
+    let home = std::env::var_os("HOME").or_else(|| std::env::var_os("USERPROFILE"));
+    if let Some(home) = home.map(PathBuf::from) {
+        dirs.push(home.join(".claude").join("local"));
+        dirs.push(home.join(".local").join("bin"));
+        dirs.push(home.join(".npm-global").join("bin"));
+        dirs.push(home.join(".volta").join("bin"));
+    }
+    #[cfg(target_os = "windows")]
+    if let Some(appdata) = std::env::var_os("APPDATA") {
+        dirs.push(PathBuf::from(appdata).join("npm"));
+    }
+    #[cfg(not(target_os = "windows"))]
+    dirs.extend([
+        PathBuf::from("/usr/local/bin"),
+        PathBuf::from("/opt/homebrew/bin"),
+    ]);
+    dirs
+}
+
+/// Program and arguments that run `claude_path` with `args`
+///
+/// On Windows, .cmd and .bat files must be executed through cmd.exe
+#[cfg(target_os = "windows")]
+pub fn command_line(claude_path: &str, args: &[String]) -> (String, Vec<String>) {
+    let lower = claude_path.to_lowercase();
+    if lower.ends_with(".cmd") || lower.ends_with(".bat") {
+        debug!("🪟 Windows: Detected .cmd/.bat file, using cmd.exe wrapper");
+        let mut cmd_args = vec!["/c".to_string(), claude_path.to_string()];
+        cmd_args.extend_from_slice(args);
+        ("cmd.exe".to_string(), cmd_args)
+    } else {
+        debug!("🪟 Windows: Direct execution of {}", claude_path);
+        (claude_path.to_string(), args.to_vec())
+    }
+}
+
+/// Program and arguments that run `claude_path` with `args`
+///
+/// On Unix-like systems, the command is executed directly
+#[cfg(not(target_os = "windows"))]
+pub fn command_line(claude_path: &str, args: &[String]) -> (String, Vec<String>) {
+    debug!("🐧 Unix: Direct execution of {}", claude_path);
+    (claude_path.to_string(), args.to_vec())
+}
+
+/// First line of `claude --version`, or why it could not be run
+pub async fn version(claude_path: &str) -> Result<String, String> {
+    let (cmd_exe, cmd_args) = command_line(claude_path, &["--version".to_string()]);
+    let output = Command::new(&cmd_exe)
+        .args(&cmd_args)
+        .stdin(std::process::Stdio::null())
+        .kill_on_drop(true)
+        .output();
+    let output = match tokio::time::timeout(VERSION_TIMEOUT, output).await {
+        Ok(Ok(output)) => output,
+        Ok(Err(e)) => return Err(format!("{}: {}", claude_path, e)),
+        Err(_) => return Err(format!("{} --version timed out", claude_path)),
+    };
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(format!(
+            "{} --version failed ({}): {}",
+            claude_path,
+            output.status,
+            stderr.trim()
+        ));
+    }
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    Ok(stdout.lines().next().unwrap_or_default().trim().to_string())
+}
+
+/// The first `major.minor.patch` in `claude --version` output such as
+/// `2.0.14 (Claude Code)`
+fn parse_version(output: &str) -> Option<Version> {
+    output.split_whitespace().find_map(|word| {
+        let core = word.trim_start_matches('v').split(['-', '+']).next()?;
+        let mut parts = core.split('.').map(|part| part.parse::<u64>().ok());
+        let version = (parts.next()??, parts.next()??, parts.next()??);
+        parts.next().is_none().then_some(version)
+    })
+}
+
+fn supported(version: Version) -> bool {
+    version >= MIN_VERSION && version.0 < UNSUPPORTED_MAJOR
+}
+
+fn format_version((major, minor, patch): Version) -> String {
+    format!("{}.{}.{}", major, minor, patch)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_version() {
+        assert_eq!(parse_version("2.0.14 (Claude Code)"), Some((2, 0, 14)));
+        assert_eq!(parse_version("claude v1.0.3-beta.1"), Some((1, 0, 3)));
+        assert_eq!(parse_version("Claude Code 1.2"), None);
+        assert_eq!(parse_version(""), None);
+
+        assert!(supported((1, 0, 0)));
+        assert!(supported((2, 9, 1)));
+        assert!(!supported((0, 2, 125)));
+        assert!(!supported((3, 0, 0)));
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_search_skips_non_executables() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let first = tempfile::tempdir().unwrap();
+        let second = tempfile::tempdir().unwrap();
+        std::fs::write(first.path().join("claude"), "not executable").unwrap();
+        let claude = second.path().join("claude");
+        std::fs::write(&claude, "#!/bin/sh\n").unwrap();
+        std::fs::set_permissions(&claude, std::fs::Permissions::from_mode(0o755)).unwrap();
+
+        let dirs = [
+            PathBuf::from("relative"),
+            first.path().to_path_buf(),
+            second.path().to_path_buf(),
+        ];
+        assert_eq!(search("claude", dirs.clone()), Some(claude.clone()));
+        assert_eq!(search("other", dirs), None);
+        assert_eq!(resolve(&claude.to_string_lossy()), Some(claude));
+    }
+
+    #[tokio::test]
+    #[cfg(unix)]
+    async fn test_discover() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let temp_dir = tempfile::tempdir().unwrap();
+        let claude = |version: &str| {
+            let path = temp_dir.path().join("claude");
+            std::fs::write(&path, format!("#!/bin/sh\necho '{}'\n", version)).unwrap();
+            std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755)).unwrap();
+            path.to_string_lossy().to_string()
+        };
+
+        let path = claude("2.0.14 (Claude Code)");
+        let found = discover(&path, ClaudeVersionCheck::Strict)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(found.version.as_deref(), Some("2.0.14 (Claude Code)"));
+
+        let path = claude("0.2.9 (Claude Code)");
+        assert!(discover(&path, ClaudeVersionCheck::Strict).await.is_err());
+        let found = discover(&path, ClaudeVersionCheck::Warn).await.unwrap();
+        assert_eq!(
+            found.unwrap().version.as_deref(),
+            Some("0.2.9 (Claude Code)")
+        );
+
+        let missing = "/nonexistent/claude";
+        assert!(discover(missing, ClaudeVersionCheck::Strict).await.is_err());
+        assert_eq!(
+            discover(missing, ClaudeVersionCheck::Warn).await.unwrap(),
+            None
+        );
+        assert_eq!(
+            discover(missing, ClaudeVersionCheck::Off).await.unwrap(),
+            None
+        );
+    }
+}
@@ -0,0 +1,552 @@
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use anyhow::{Context, Result};
+use clap::{Args, Subcommand};
+use futures::StreamExt;
+use q9gent_client::{Client, MessageRequest, SpawnRequest, StreamEvent};
+use tracing::{debug, field, info, info_span, warn, Instrument};
+
+use crate::{
+    agent::{validate_flags, AgentEvent, AgentRequest, AgentRunner},
+    audit::{AuditLog, Caller},
+    cassette::Cassettes,
+    config::ServerConfig,
+    environment,
+    error::AppError,
+    journal::RunJournal,
+    session::{extract_session_id, SessionPlacement, SessionStore},
+    workspace::{self, WorkspaceRequest},
+};
+
+/// Prompt source shared by `run` and `client spawn|message`
+#[derive(Args, Debug)]
+pub struct PromptArgs {
+    /// Prompt text
+    #[arg(
+        long,
+        conflicts_with = "prompt_file",
+        required_unless_present = "prompt_file"
+    )]
+    pub prompt: Option<String>,
+
+    /// Read the prompt from a file (`-` for stdin)
+    #[arg(long)]
+    pub prompt_file: Option<PathBuf>,
+}
+
+/// Parse a `KEY=VALUE` environment variable argument
+fn parse_env_var(s: &str) -> Result<(String, String), String> {
+    s.split_once('=')
+        .map(|(key, value)| (key.to_string(), value.to_string()))
+        .ok_or_else(|| format!("expected KEY=VALUE, got '{}'", s))
+}
+
+/// Working directory options shared by `run` and `client spawn`
+#[derive(Args, Debug)]
+pub struct WorkspaceArgs {
+    /// Run in this directory (must be inside an --allowed-workdir root)
+    #[arg(long, conflicts_with_all = ["temp_workspace", "workspace_template"])]
+    pub workdir: Option<String>,
+
+    /// Run in a fresh temporary workspace
+    #[arg(long)]
+    pub temp_workspace: bool,
+
+    /// Seed the temporary workspace with a copy of this directory
+    #[arg(long)]
+    pub workspace_template: Option<String>,
+}
+
+/// One-shot run options (`q9gent run`)
+#[derive(Args, Debug)]
+pub struct RunArgs {
+    /// Type of agent (informational, recorded in session metadata)
+    #[arg(long, default_value = "cli")]
+    pub agent_type: String,
+
+    #[command(flatten)]
+    pub prompt: PromptArgs,
+
+    /// Allowed tools (comma-separated or repeated)
+    #[arg(long = "tools", value_delimiter = ',')]
+    pub tools_allowed: Vec<String>,
+
+    /// System prompt to append
+    #[arg(long)]
+    pub system_append: Option<String>,
+
+    /// Named profile from --profiles
+    #[arg(long)]
+    pub profile: Option<String>,
+
+    #[command(flatten)]
+    pub workspace: WorkspaceArgs,
+
+    /// Environment variable for the run as KEY=VALUE (repeatable; the
+    /// profile must permit KEY)
+    #[arg(long = "env", value_parser = parse_env_var)]
+    pub env: Vec<(String, String)>,
+
+    /// Resume an existing session in the directory it was created in
+    #[arg(long, conflicts_with_all = ["create_session", "profile", "workdir", "temp_workspace", "workspace_template"])]
+    pub resume: Option<String>,
+
+    /// Record Claude's session so it can be resumed later
+    #[arg(long)]
+    pub create_session: bool,
+
+    /// Additional CLI flags passed through to claude (after `--`)
+    #[arg(last = true)]
+    pub flags: Vec<String>,
+}
+
+/// Remote client options (`q9gent client`)
+#[derive(Args, Debug)]
+pub struct ClientArgs {
+    /// Base URL of the Q9gent server
+    #[arg(long, default_value = "http://127.0.0.1:8080")]
+    pub server: String,
+
+    /// Bearer token for servers with authentication enabled
+    #[arg(long, env = "Q9GENT_TOKEN", hide_env_values = true)]
+    pub token: Option<String>,
+
+    #[command(subcommand)]
+    pub command: ClientCommand,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum ClientCommand {
+    /// Check server health
+    Health,
+
+    /// Check whether the server is ready to start runs (exits 1 if not)
+    Ready,
+
+    /// Spawn an agent and print its events as JSONL
+    Spawn {
+        /// Type of agent (informational, recorded in session metadata)
+        #[arg(long, default_value = "cli")]
+        agent_type: String,
+
+        #[command(flatten)]
+        prompt: PromptArgs,
+
+        /// Allowed tools (comma-separated or repeated)
+        #[arg(long = "tools", value_delimiter = ',')]
+        tools_allowed: Vec<String>,
+
+        /// System prompt to append
+        #[arg(long)]
+        system_append: Option<String>,
+
+        /// Named server-side profile
+        #[arg(long)]
+        profile: Option<String>,
+
+        #[command(flatten)]
+        workspace: WorkspaceArgs,
+
+        /// Environment variable for the run as KEY=VALUE (repeatable; the
+        /// profile must permit KEY)
+        #[arg(long = "env", value_parser = parse_env_var)]
+        env: Vec<(String, String)>,
+
+        /// Record Claude's session so it can be resumed later
+        #[arg(long)]
+        create_session: bool,
+
+        /// Additional CLI flags passed through to claude (after `--`)
+        #[arg(last = true)]
+        flags: Vec<String>,
+    },
+
+    /// Send a message to an existing session and print its events as JSONL
+    Message {
+        /// Session to resume
+        session_id: String,
+
+        #[command(flatten)]
+        prompt: PromptArgs,
+
+        /// Allowed tools (comma-separated or repeated)
+        #[arg(long = "tools", value_delimiter = ',')]
+        tools_allowed: Vec<String>,
+
+        /// System prompt to append
+        #[arg(long)]
+        system_append: Option<String>,
+
+        /// Environment variable for the run as KEY=VALUE (repeatable; the
+        /// profile must permit KEY)
+        #[arg(long = "env", value_parser = parse_env_var)]
+        env: Vec<(String, String)>,
+
+        /// Additional CLI flags passed through to claude (after `--`)
+        #[arg(last = true)]
+        flags: Vec<String>,
+    },
+
+    /// Terminate the running process of a session
+    Terminate {
+        /// Session whose process should be terminated
+        session_id: String,
+    },
+
+    /// List stored sessions
+    Sessions,
+
+    /// Show a stored session
+    Session { session_id: String },
+
+    /// Delete a session and its git worktree
+    DeleteSession { session_id: String },
+
+    /// Print the diff of a session's git worktree
+    Diff { session_id: String },
+
+    /// List recorded runs, newest first
+    Runs,
+
+    /// Show a recorded run
+    Run { run_id: String },
+
+    /// Reload the server's configuration
+    Reload,
+
+    /// Print audit records of runs in a time range; exits 1 if the audit
+    /// log's hash chain is broken
+    Audit {
+        /// Only runs that ended at or after this Unix timestamp
+        #[arg(long)]
+        since: Option<u64>,
+
+        /// Only runs that started before this Unix timestamp
+        #[arg(long)]
+        until: Option<u64>,
+    },
+}
+
+/// Write one event as a JSONL record to stdout
+fn emit(event: &StreamEvent) -> Result<()> {
+    let mut stdout = std::io::stdout().lock();
+    serde_json::to_writer(&mut stdout, event)?;
+    stdout.write_all(b"\n")?;
+    stdout.flush()?;
+    Ok(())
+}
+
+/// Print a JSON value on its own line
+fn print_json(value: &impl serde::Serialize) -> Result<()> {
+    println!("{}", serde_json::to_string_pretty(value)?);
+    Ok(())
+}
+
+/// Report a run that never started as an `error` event followed by
+/// `completed`, and return the exit code
+fn refused(e: &AppError) -> Result<i32> {
+    emit(&StreamEvent::Error(e.to_error_response(None)))?;
+    emit(&StreamEvent::Completed {
+        exit_code: None,
+        terminated: false,
+        workspace_retained: false,
+        redactions: 0,
+        resources: None,
+        termination: None,
+    })?;
+    Ok(1)
+}
+
+/// Read the prompt from `--prompt` or `--prompt-file`
+async fn read_prompt(args: &PromptArgs) -> Result<String> {
+    if let Some(ref prompt) = args.prompt {
+        return Ok(prompt.clone());
+    }
+
+    match args.prompt_file.as_deref() {
+        Some(path) if path.as_os_str() == "-" => {
+            let mut prompt = String::new();
+            tokio::io::AsyncReadExt::read_to_string(&mut tokio::io::stdin(), &mut prompt)
+                .await
+                .context("Failed to read prompt from stdin")?;
+            Ok(prompt)
+        }
+        Some(path) => tokio::fs::read_to_string(path)
+            .await
+            .with_context(|| format!("Failed to read prompt file {}", path.display())),
+        None => anyhow::bail!("either --prompt or --prompt-file is required"),
+    }
+}
+
+/// Run a single agent invocation, printing the same events the SSE API emits
+/// as JSONL on stdout. Returns the exit code of the claude process.
+///
+/// Logs of the run carry its agent type, session and run IDs and PID, like
+/// those of a request.
+pub async fn run(args: RunArgs, config: Arc<ServerConfig>) -> Result<i32> {
+    let span = info_span!(
+        "run",
+        agent_type = %args.agent_type,
+        session_id = args.resume.as_deref(),
+        run_id = field::Empty,
+        pid = field::Empty,
+    );
+    run_agent(args, config).instrument(span).await
+}
+
+async fn run_agent(args: RunArgs, config: Arc<ServerConfig>) -> Result<i32> {
+    let prompt = read_prompt(&args.prompt).await?;
+    let session_store = SessionStore::new(&config.session_dir);
+    let agent_runner = AgentRunner::new(config.claude_executable())
+        .with_transcript_dir(config.redaction.transcript_dir.clone())
+        .with_cassettes(Cassettes::from_config(&config.cassettes))
+        .with_cgroup_root(config.cgroup_root.clone())
+        .with_grace_period(config.kill_grace_period)
+        .with_event_buffer(config.event_buffer)
+        .with_journal(Arc::new(RunJournal::new(&config.session_dir)))
+        .with_audit(
+            AuditLog::open(&config.audit)
+                .await?
+                .map(|audit| Arc::new(audit.with_claude(config.claude.clone()))),
+        );
+
+    let request_env: BTreeMap<_, _> = args.env.into_iter().collect();
+
+    // Resuming behaves like POST /message/:session_id
+    let admitted = async {
+        if let Some(ref session_id) = args.resume {
+            let metadata = session_store.touch_session(session_id).await?;
+            info!("✓ Session found and updated: {}", session_id);
+            let run_env =
+                environment::for_run(&config, metadata.profile.as_deref(), &request_env).await?;
+            let limits = config.limits(metadata.profile.as_deref())?;
+            let workspace = workspace::session_workspace(metadata.workdir.as_deref())?;
+            Ok::<_, AppError>((workspace, run_env, limits))
+        } else {
+            validate_flags(&args.flags)?;
+            let run_env =
+                environment::for_run(&config, args.profile.as_deref(), &request_env).await?;
+            let limits = config.limits(args.profile.as_deref())?;
+            let workspace = workspace::prepare_run(
+                &config,
+                WorkspaceRequest {
+                    profile: args.profile.as_deref(),
+                    workdir: args.workspace.workdir.as_deref(),
+                    temp_workspace: args.workspace.temp_workspace,
+                    template: args.workspace.workspace_template.as_deref(),
+                    create_session: args.create_session,
+                },
+            )
+            .await?;
+            Ok((workspace, run_env, limits))
+        }
+    };
+    let (workspace, (env, redactor), limits) = match admitted.await {
+        Ok(admitted) => admitted,
+        Err(e) => return refused(&e),
+    };
+    let placement = SessionPlacement {
+        profile: args.profile.clone(),
+        workdir: workspace.as_ref().map(|w| w.path().to_path_buf()),
+        worktree: workspace.as_ref().and_then(|w| w.worktree().cloned()),
+        temp_workspace: workspace.as_ref().is_some_and(|w| w.is_temporary()),
+    };
+
+    let agent_request = AgentRequest {
+        agent_type: args.agent_type.clone(),
+        prompt,
+        flags: args.flags,
+        tools_allowed: args.tools_allowed,
+        system_append: args.system_append,
+        resume_id: args.resume,
+        env,
+        redactor,
+        limits,
+        caller: Caller::local(),
+    };
+
+    let (handle, mut rx) = match agent_runner.spawn(agent_request, workspace).await {
+        Ok(spawned) => spawned,
+        Err(e) => return refused(&e),
+    };
+
+    emit(&StreamEvent::RunStarted {
+        run_id: Some(handle.run_id().to_string()),
+        pid: handle.pid(),
+        workdir: placement
+            .workdir
+            .as_ref()
+            .map(|path| path.to_string_lossy().to_string()),
+    })?;
+
+    // Ctrl-C does not reach claude's own process group; stop it through the
+    // runner so the completed event is still printed
+    tokio::spawn(async move {
+        if tokio::signal::ctrl_c().await.is_ok() {
+            if let Err(e) = AgentRunner::terminate(handle).await {
+                warn!("⚠️  Failed to terminate Claude process: {}", e);
+            }
+        }
+    });
+
+    let mut output_count = 0;
+    let mut exit_code = None;
+    while let Some(event) = rx.recv().await {
+        match event {
+            AgentEvent::Stdout(line) => {
+                output_count += 1;
+                if output_count == 1 && args.create_session {
+                    if let Some(sid) = extract_session_id(&line) {
+                        let recorded = session_store
+                            .record_session(&sid, &args.agent_type, &placement)
+                            .await;
+                        match recorded {
+                            Ok(_) => emit(&StreamEvent::SessionCreated { session_id: sid })?,
+                            Err(e) => warn!("⚠️  Failed to save session metadata: {}", e),
+                        }
+                    }
+                }
+                emit(&StreamEvent::Output { data: line })?;
+            }
+            AgentEvent::Stderr(line) => emit(&StreamEvent::Stderr { data: line })?,
+            AgentEvent::Exited(exit) => {
+                debug!("Claude process exited with code: {:?}", exit.exit_code);
+                if let Some(e) = exit.error {
+                    emit(&StreamEvent::Error(e.to_error_response(None)))?;
+                }
+                exit_code = exit.exit_code;
+                emit(&StreamEvent::Completed {
+                    exit_code: exit.exit_code,
+                    terminated: exit.terminated,
+                    workspace_retained: exit.workspace_retained,
+                    redactions: exit.redactions,
+                    resources: exit.resources,
+                    termination: exit.termination,
+                })?;
+            }
+        }
+    }
+
+    Ok(exit_code.unwrap_or(1))
+}
+
+/// Drive a remote Q9gent server. Returns the run's exit code when the server
+/// reports one, 1 for other errors and 0 otherwise.
+pub async fn client(args: ClientArgs) -> Result<i32> {
+    let client = match args.token {
+        Some(ref token) => Client::with_token(args.server, token)?,
+        None => Client::new(args.server),
+    };
+
+    let mut events = match args.command {
+        ClientCommand::Health => {
+            print_json(&client.health().await?)?;
+            return Ok(0);
+        }
+        ClientCommand::Ready => {
+            let ready = client.ready().await?;
+            print_json(&ready)?;
+            return Ok(if ready.ready { 0 } else { 1 });
+        }
+        ClientCommand::Terminate { session_id } => {
+            print_json(&client.terminate(&session_id).await?)?;
+            return Ok(0);
+        }
+        ClientCommand::Sessions => {
+            print_json(&client.list_sessions().await?)?;
+            return Ok(0);
+        }
+        ClientCommand::Session { session_id } => {
+            print_json(&client.get_session(&session_id).await?)?;
+            return Ok(0);
+        }
+        ClientCommand::DeleteSession { session_id } => {
+            client.delete_session(&session_id).await?;
+            return Ok(0);
+        }
+        ClientCommand::Diff { session_id } => {
+            print!("{}", client.session_diff(&session_id).await?.diff);
+            return Ok(0);
+        }
+        ClientCommand::Runs => {
+            print_json(&client.list_runs().await?)?;
+            return Ok(0);
+        }
+        ClientCommand::Run { run_id } => {
+            print_json(&client.get_run(&run_id).await?)?;
+            return Ok(0);
+        }
+        ClientCommand::Reload => {
+            print_json(&client.reload().await?)?;
+            return Ok(0);
+        }
+        ClientCommand::Audit { since, until } => {
+            let audit = client.audit(since, until).await?;
+            print_json(&audit)?;
+            return Ok(if audit.chain_intact { 0 } else { 1 });
+        }
+        ClientCommand::Spawn {
+            agent_type,
+            prompt,
+            tools_allowed,
+            system_append,
+            profile,
+            workspace,
+            env,
+            create_session,
+            flags,
+        } => {
+            let request = SpawnRequest {
+                agent_type,
+                prompt: read_prompt(&prompt).await?,
+                flags,
+                tools_allowed,
+                system_append,
+                resume_id: None,
+                create_session,
+                profile,
+                workdir: workspace.workdir,
+                temp_workspace: workspace.temp_workspace,
+                workspace_template: workspace.workspace_template,
+                env: env.into_iter().collect(),
+            };
+            client.spawn(&request).await?
+        }
+        ClientCommand::Message {
+            session_id,
+            prompt,
+            tools_allowed,
+            system_append,
+            env,
+            flags,
+        } => {
+            let request = MessageRequest {
+                prompt: read_prompt(&prompt).await?,
+                flags,
+                tools_allowed,
+                system_append,
+                env: env.into_iter().collect(),
+            };
+            client.message(&session_id, &request).await?
+        }
+    };
+
+    let mut code = 0;
+    while let Some(event) = events.next().await {
+        let event = event?;
+        match event {
+            StreamEvent::Error(_) => code = code.max(1),
+            StreamEvent::Completed {
+                exit_code: Some(exit_code),
+                ..
+            } if exit_code != 0 => code = exit_code,
+            _ => {}
+        }
+        emit(&event)?;
+    }
+
+    Ok(code)
+}
@@ -1,9 +1,515 @@
+use axum::http::HeaderValue;
+use serde::Deserialize;
+use std::num::NonZeroUsize;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::watch;
+
+use crate::claude::ClaudeCli;
+use crate::error::AppResult;
+use crate::limits::RunLimits;
+use crate::profile::{Profile, Profiles};
+
+pub mod file;
+mod options;
+
+pub use options::{Options, DEFAULT_LOG_FILTER};
+
+/// Everything resolved from the config file, environment and command line
+#[derive(Debug)]
+pub struct Settings {
+    pub server: ServerConfig,
+    pub listeners: ListenerConfig,
+
+    /// Log filter, if configured
+    pub log_filter: Option<String>,
+
+    /// Log format and destination
+    pub logging: LoggingConfig,
+
+    /// Where trace spans are exported
+    pub telemetry: TelemetryConfig,
+
+    /// Profiles file the profiles were loaded from
+    pub profiles_path: Option<PathBuf>,
+
+    /// Files the settings were read from (config, profiles, tokens)
+    pub files: Vec<PathBuf>,
+}
+
+/// The active server configuration, replaced as a whole on reload
+///
+/// Requests take a snapshot with [`SharedConfig::current`] and use it to the
+/// end, so a run keeps the configuration it started with.
+#[derive(Clone)]
+pub struct SharedConfig(Arc<watch::Sender<Arc<ServerConfig>>>);
+
+impl SharedConfig {
+    pub fn new(config: ServerConfig) -> Self {
+        Self(Arc::new(watch::Sender::new(Arc::new(config))))
+    }
+
+    pub fn current(&self) -> Arc<ServerConfig> {
+        self.0.borrow().clone()
+    }
+
+    pub fn replace(&self, config: ServerConfig) {
+        self.0.send_replace(Arc::new(config));
+    }
+}
+
 /// Server configuration
 #[derive(Debug, Clone)]
 pub struct ServerConfig {
-    /// Path to the claude CLI executable
+    /// Path to the claude CLI executable, as configured
     pub claude_path: String,
 
+    /// How the claude CLI is checked at startup
+    pub claude_version_check: ClaudeVersionCheck,
+
+    /// The claude CLI found at startup; `None` until discovery ran or when
+    /// it was not found
+    pub claude: Option<ClaudeCli>,
+
     /// Directory for session metadata storage
     pub session_dir: String,
+
+    /// Where runs may execute and how temporary workspaces are handled
+    pub workspace: WorkspaceConfig,
+
+    /// Named profiles callers can select
+    pub profiles: Profiles,
+
+    /// Which environment variables claude processes receive
+    pub env: EnvConfig,
+
+    /// How claude output is redacted before it leaves the server
+    pub redaction: RedactionConfig,
+
+    /// Delegated cgroup v2 directory per-run cgroups are created in
+    pub cgroup_root: Option<PathBuf>,
+
+    /// Resource limits for runs without a profile
+    pub default_limits: RunLimits,
+
+    /// Time terminated process groups get between SIGTERM and SIGKILL
+    pub kill_grace_period: Duration,
+
+    /// Time running processes get to finish when the server shuts down
+    pub shutdown_timeout: Duration,
+
+    /// Events buffered per run before reading claude's output waits
+    pub event_buffer: usize,
+
+    /// Processes running at once beyond which `/ready` reports no capacity
+    pub max_runs: Option<NonZeroUsize>,
+
+    /// Interval of keep-alive comments on idle event streams
+    pub sse_keep_alive: Duration,
+
+    /// Largest accepted request body in bytes
+    pub max_request_body: usize,
+
+    /// Origins browsers may call the API from
+    pub cors: CorsConfig,
+
+    /// Bearer tokens required on API requests
+    pub auth: AuthConfig,
+
+    /// Where runs are audited
+    pub audit: AuditConfig,
+
+    /// Whether runs are recorded to or replayed from cassettes
+    pub cassettes: CassetteConfig,
+}
+
+/// Cross-origin access policy
+#[derive(Debug, Clone, Default, PartialEq)]
+pub enum CorsConfig {
+    /// Any origin, with any method and header
+    #[default]
+    Any,
+    /// Only these origins; empty allows none
+    Origins(Vec<HeaderValue>),
+}
+
+/// Bearer token authentication
+#[derive(Clone, Default)]
+pub struct AuthConfig {
+    /// Accepted tokens; empty disables authentication
+    pub tokens: Vec<String>,
+}
+
+impl std::fmt::Debug for AuthConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("AuthConfig")
+            .field("tokens", &self.tokens.len())
+            .finish()
+    }
+}
+
+impl AuthConfig {
+    /// Read tokens from a file with one token per line; blank lines and
+    /// lines starting with `#` are skipped
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let text = std::fs::read_to_string(path)?;
+        let tokens: Vec<String> = text
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(str::to_string)
+            .collect();
+        if tokens.is_empty() {
+            anyhow::bail!("no tokens in file");
+        }
+        Ok(Self { tokens })
+    }
+
+    /// Whether `token` is one of the accepted tokens, compared in constant
+    /// time
+    pub fn accepts(&self, token: &str) -> bool {
+        self.tokens.iter().fold(false, |found, accepted| {
+            let equal = accepted.len() == token.len()
+                && accepted
+                    .bytes()
+                    .zip(token.bytes())
+                    .fold(0, |diff, (a, b)| diff | (a ^ b))
+                    == 0;
+            found | equal
+        })
+    }
+}
+
+/// Log format and destination
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct LoggingConfig {
+    pub format: LogFormat,
+
+    /// Directory of rotated log files; `None` logs to stdout (stderr in
+    /// one-shot modes)
+    pub dir: Option<PathBuf>,
+
+    /// How often a new log file is started in `dir`
+    pub rotation: LogRotation,
+
+    /// Rotated files kept in `dir`; `None` keeps all
+    pub max_files: Option<NonZeroUsize>,
+}
+
+/// Log record format
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum LogFormat {
+    /// Human-readable lines
+    #[default]
+    Text,
+    /// One JSON object per record, with the request, run and session IDs
+    /// and PID of the run it belongs to
+    Json,
+}
+
+/// What happens when the claude CLI is missing or has an unsupported
+/// version at startup
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ClaudeVersionCheck {
+    /// Skip discovery; failures surface when a run is spawned
+    Off,
+    /// Log a warning and start anyway
+    #[default]
+    Warn,
+    /// Refuse to start
+    Strict,
+}
+
+/// Whether runs are recorded to or replayed from cassettes
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum CassetteMode {
+    /// Run claude without recording
+    #[default]
+    Off,
+    /// Run claude and save each finished run as a cassette
+    Record,
+    /// Serve runs from cassettes without starting claude
+    Replay,
+}
+
+/// Log file rotation
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum LogRotation {
+    Hourly,
+    #[default]
+    Daily,
+    Never,
+}
+
+/// OpenTelemetry span export
+///
+/// Spans are recorded and `traceparent` is propagated to claude even when
+/// no exporter is configured.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TelemetryConfig {
+    /// OTLP/HTTP endpoint spans are sent to
+    pub otlp_endpoint: Option<String>,
+
+    /// File spans are appended to as OTLP/JSON, one export request per line
+    pub otlp_file: Option<PathBuf>,
+}
+
+/// Audit log of runs
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct AuditConfig {
+    /// Directory of the daily audit files; `None` keeps no audit log
+    pub dir: Option<PathBuf>,
+
+    /// Record the first this many characters of prompts instead of their
+    /// SHA-256
+    pub prompt_chars: Option<usize>,
+}
+
+/// Recording and replay of runs
+#[derive(Debug, Clone, PartialEq)]
+pub struct CassetteConfig {
+    pub mode: CassetteMode,
+
+    /// Directory of the cassette files; required unless `mode` is off
+    pub dir: Option<PathBuf>,
+
+    /// How many times faster than recorded runs are replayed; 0 replays
+    /// without delays
+    pub replay_speed: f64,
+}
+
+impl Default for CassetteConfig {
+    fn default() -> Self {
+        Self {
+            mode: CassetteMode::Off,
+            dir: None,
+            replay_speed: 1.0,
+        }
+    }
+}
+
+/// Output redaction settings
+#[derive(Debug, Clone, Default)]
+pub struct RedactionConfig {
+    /// Rules applied to every stdout and stderr line, in addition to the
+    /// secrets a profile injects and the profile's own `redact` rules
+    pub patterns: Vec<regex::Regex>,
+
+    /// Directory for per-run transcripts of output before the rules are
+    /// applied; `None` keeps no transcripts
+    pub transcript_dir: Option<PathBuf>,
+}
+
+impl ServerConfig {
+    /// Executable claude runs are started with: the path found at startup,
+    /// or the configured one if discovery did not find it
+    pub fn claude_executable(&self) -> String {
+        match self.claude {
+            Some(ref claude) => claude.path.to_string_lossy().to_string(),
+            None => self.claude_path.clone(),
+        }
+    }
+
+    /// Look up the profile a request names, if any
+    pub fn profile(&self, name: Option<&str>) -> AppResult<Option<&Profile>> {
+        name.map(|name| self.profiles.get(name)).transpose()
+    }
+
+    /// Resource limits for runs with the named profile
+    pub fn limits(&self, profile: Option<&str>) -> AppResult<RunLimits> {
+        Ok(self
+            .profile(profile)?
+            .map(|profile| profile.limits.clone())
+            .unwrap_or_else(|| self.default_limits.clone()))
+    }
+}
+
+/// Environment policy for claude processes
+#[derive(Debug, Clone)]
+pub struct EnvConfig {
+    /// Pass the server's whole environment through instead of clearing it
+    pub inherit: bool,
+
+    /// Server variables passed through to every process when set
+    pub passthrough: Vec<String>,
+
+    /// Directory relative secret paths in profiles are resolved against
+    pub secrets_dir: Option<PathBuf>,
+}
+
+impl Default for EnvConfig {
+    fn default() -> Self {
+        Self {
+            inherit: false,
+            passthrough: crate::environment::DEFAULT_PASSTHROUGH
+                .iter()
+                .map(|key| key.to_string())
+                .collect(),
+            secrets_dir: None,
+        }
+    }
+}
+
+/// Per-run working directory settings
+#[derive(Debug, Clone)]
+pub struct WorkspaceConfig {
+    /// Directories a requested `workdir` or workspace template must be inside;
+    /// empty rejects every requested path
+    pub allowed_roots: Vec<PathBuf>,
+
+    /// Parent directory for temporary per-run workspaces
+    pub temp_dir: PathBuf,
+
+    /// What happens to a temporary workspace when its run ends
+    pub retention: WorkspaceRetention,
+}
+
+impl Default for WorkspaceConfig {
+    fn default() -> Self {
+        Self {
+            allowed_roots: Vec::new(),
+            temp_dir: std::env::temp_dir().join("q9gent-workspaces"),
+            retention: WorkspaceRetention::Delete,
+        }
+    }
+}
+
+/// Cleanup policy for temporary workspaces
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum WorkspaceRetention {
+    /// Always delete the workspace
+    Delete,
+    /// Keep the workspace only if the run failed
+    KeepOnFailure,
+    /// Never delete the workspace
+    Keep,
+}
+
+/// Listener configuration - which sockets the HTTP API is served on
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ListenerConfig {
+    /// TCP bind address (`host:port`), `None` disables the TCP listener
+    pub tcp_addr: Option<String>,
+
+    /// Optional Unix domain socket listener
+    pub unix_socket: Option<UnixSocketConfig>,
+}
+
+/// Unix domain socket listener settings
+#[derive(Debug, Clone, PartialEq)]
+pub struct UnixSocketConfig {
+    /// Filesystem path of the socket
+    pub path: PathBuf,
+
+    /// File mode applied to the socket before it accepts connections (e.g. `0o660`)
+    pub mode: Option<u32>,
+
+    /// Owner applied to the socket before it accepts connections
+    pub owner: Option<SocketOwner>,
+}
+
+/// Numeric owner for the Unix socket file; either part may be left unchanged
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SocketOwner {
+    pub uid: Option<u32>,
+    pub gid: Option<u32>,
+}
+
+/// Parse an octal file mode such as `660` or `0o660`
+pub fn parse_socket_mode(value: &str) -> Result<u32, String> {
+    let digits = value.trim_start_matches("0o");
+    let mode =
+        u32::from_str_radix(digits, 8).map_err(|_| format!("invalid octal mode '{}'", value))?;
+    if mode > 0o7777 {
+        return Err(format!("mode '{}' is out of range", value));
+    }
+    Ok(mode)
+}
+
+/// Parse a socket owner in the form `uid`, `uid:gid` or `:gid`
+pub fn parse_socket_owner(value: &str) -> Result<SocketOwner, String> {
+    let parse_id = |part: &str| -> Result<Option<u32>, String> {
+        if part.is_empty() {
+            Ok(None)
+        } else {
+            part.parse::<u32>()
+                .map(Some)
+                .map_err(|_| format!("invalid numeric id '{}' in owner '{}'", part, value))
+        }
+    };
+
+    let (uid, gid) = match value.split_once(':') {
+        Some((uid, gid)) => (parse_id(uid)?, parse_id(gid)?),
+        None => (parse_id(value)?, None),
+    };
+
+    if uid.is_none() && gid.is_none() {
+        return Err(format!("owner '{}' must specify a uid and/or gid", value));
+    }
+
+    Ok(SocketOwner { uid, gid })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_socket_mode() {
+        assert_eq!(parse_socket_mode("660").unwrap(), 0o660);
+        assert_eq!(parse_socket_mode("0o600").unwrap(), 0o600);
+        assert_eq!(parse_socket_mode("0770").unwrap(), 0o770);
+        assert!(parse_socket_mode("999").is_err());
+        assert!(parse_socket_mode("77777").is_err());
+    }
+
+    #[test]
+    fn test_auth_tokens() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let path = temp_dir.path().join("tokens");
+        std::fs::write(&path, "# ci\nsecret-one\n\n  secret-two  \n").unwrap();
+
+        let auth = AuthConfig::load(&path).unwrap();
+        assert!(auth.accepts("secret-one"));
+        assert!(auth.accepts("secret-two"));
+        assert!(!auth.accepts("secret"));
+        assert!(!auth.accepts("# ci"));
+        assert!(!format!("{:?}", auth).contains("secret"));
+
+        std::fs::write(&path, "# none yet\n").unwrap();
+        assert!(AuthConfig::load(&path).is_err());
+    }
+
+    #[test]
+    fn test_parse_socket_owner() {
+        assert_eq!(
+            parse_socket_owner("1000").unwrap(),
+            SocketOwner {
+                uid: Some(1000),
+                gid: None
+            }
+        );
+        assert_eq!(
+            parse_socket_owner("1000:50").unwrap(),
+            SocketOwner {
+                uid: Some(1000),
+                gid: Some(50)
+            }
+        );
+        assert_eq!(
+            parse_socket_owner(":50").unwrap(),
+            SocketOwner {
+                uid: None,
+                gid: Some(50)
+            }
+        );
+        assert!(parse_socket_owner(":").is_err());
+        assert!(parse_socket_owner("www-data").is_err());
+    }
 }
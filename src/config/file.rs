@@ -0,0 +1,268 @@
+use regex::Regex;
+use serde::{Deserialize, Deserializer};
+use std::num::{NonZeroU64, NonZeroUsize};
+use std::path::{Path, PathBuf};
+
+use super::{
+    parse_socket_mode, parse_socket_owner, CassetteMode, ClaudeVersionCheck, LogFormat,
+    LogRotation, SocketOwner, WorkspaceRetention,
+};
+use crate::limits::RunLimits;
+use crate::profile::deserialize_patterns;
+
+/// Contents of the `--config` TOML file
+///
+/// Every key is optional; environment variables and command-line flags
+/// override what the file sets. Relative paths are resolved against the
+/// working directory, like the flags they mirror.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ConfigFile {
+    pub listener: ListenerSection,
+    pub sessions: SessionsSection,
+    pub runner: RunnerSection,
+    pub limits: LimitsSection,
+    pub cors: CorsSection,
+    pub auth: AuthSection,
+    pub logging: LoggingSection,
+    pub telemetry: TelemetrySection,
+    pub audit: AuditSection,
+}
+
+/// `[listener]`: sockets the HTTP API is served on
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ListenerSection {
+    pub host: Option<String>,
+    pub port: Option<u16>,
+
+    /// `false` disables the TCP listener (requires `unix_socket`)
+    pub tcp: Option<bool>,
+
+    pub unix_socket: Option<PathBuf>,
+
+    /// Octal file mode as a string, e.g. `"660"`
+    #[serde(deserialize_with = "socket_mode")]
+    pub unix_socket_mode: Option<u32>,
+
+    /// `"uid"`, `"uid:gid"` or `":gid"`
+    #[serde(deserialize_with = "socket_owner")]
+    pub unix_socket_owner: Option<SocketOwner>,
+
+    /// Seconds between keep-alive comments on idle event streams
+    pub sse_keep_alive_secs: Option<NonZeroU64>,
+
+    pub shutdown_timeout_secs: Option<u64>,
+}
+
+/// `[sessions]`: session store
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SessionsSection {
+    pub dir: Option<String>,
+}
+
+/// `[runner]`: how claude processes are started
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RunnerSection {
+    pub claude_path: Option<String>,
+    pub claude_version_check: Option<ClaudeVersionCheck>,
+    pub profiles: Option<PathBuf>,
+
+    /// Events buffered per run before reading claude's output waits for the
+    /// client
+    pub event_buffer: Option<NonZeroUsize>,
+
+    /// Processes running at once beyond which `/ready` reports no capacity
+    pub max_runs: Option<NonZeroUsize>,
+
+    pub kill_grace_period_secs: Option<u64>,
+    pub allowed_workdirs: Vec<PathBuf>,
+    pub workspace_dir: Option<PathBuf>,
+    pub workspace_retention: Option<WorkspaceRetention>,
+    pub env_passthrough: Vec<String>,
+    pub inherit_env: Option<bool>,
+    pub secrets_dir: Option<PathBuf>,
+
+    #[serde(deserialize_with = "deserialize_patterns")]
+    pub redact_patterns: Vec<Regex>,
+
+    pub redact_known_secrets: Option<bool>,
+    pub transcript_dir: Option<PathBuf>,
+
+    /// `"off"`, `"record"` or `"replay"`
+    pub cassette_mode: Option<CassetteMode>,
+
+    pub cassette_dir: Option<PathBuf>,
+
+    /// Replay speed factor; 1 keeps the recorded timing, 0 removes delays
+    pub replay_speed: Option<f64>,
+}
+
+/// `[limits]`: request and resource limits
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LimitsSection {
+    pub cgroup_root: Option<PathBuf>,
+    pub max_request_body_bytes: Option<NonZeroUsize>,
+
+    /// Limits for runs without a profile, in the same form as a profile's
+    /// `[limits]`
+    pub default: Option<RunLimits>,
+}
+
+/// `[cors]`: cross-origin access from browsers
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CorsSection {
+    /// Origins allowed to call the API; `["*"]` allows any and `[]` none
+    pub allowed_origins: Option<Vec<String>>,
+}
+
+/// `[auth]`: bearer tokens required on API requests
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AuthSection {
+    /// File with one accepted token per line
+    pub tokens_file: Option<PathBuf>,
+}
+
+/// `[logging]`
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LoggingSection {
+    /// Log filter in `RUST_LOG` syntax
+    pub level: Option<String>,
+
+    /// `"text"` or `"json"`
+    pub format: Option<LogFormat>,
+
+    /// Directory of rotated log files instead of stdout
+    pub dir: Option<PathBuf>,
+
+    /// `"hourly"`, `"daily"` or `"never"`
+    pub rotation: Option<LogRotation>,
+
+    pub max_files: Option<NonZeroUsize>,
+}
+
+/// `[audit]`: append-only record of every run
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AuditSection {
+    pub dir: Option<PathBuf>,
+
+    /// Characters of the prompt to record; prompts are hashed if unset
+    pub prompt_chars: Option<usize>,
+}
+
+/// `[telemetry]`: OpenTelemetry span export
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TelemetrySection {
+    /// OTLP/HTTP collector URL; `/v1/traces` is appended if it has no path
+    pub otlp_endpoint: Option<String>,
+
+    /// File spans are appended to as OTLP/JSON lines
+    pub otlp_file: Option<PathBuf>,
+}
+
+fn socket_mode<'de, D>(deserializer: D) -> Result<Option<u32>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let mode = String::deserialize(deserializer)?;
+    parse_socket_mode(&mode)
+        .map(Some)
+        .map_err(serde::de::Error::custom)
+}
+
+fn socket_owner<'de, D>(deserializer: D) -> Result<Option<SocketOwner>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let owner = String::deserialize(deserializer)?;
+    parse_socket_owner(&owner)
+        .map(Some)
+        .map_err(serde::de::Error::custom)
+}
+
+impl ConfigFile {
+    /// Load a config file; errors name the file and show the offending key
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| anyhow::anyhow!("failed to read {}: {}", path.display(), e))?;
+        Self::parse(&text).map_err(|e| anyhow::anyhow!("invalid config {}: {}", path.display(), e))
+    }
+
+    fn parse(text: &str) -> Result<Self, toml::de::Error> {
+        toml::from_str(text)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_config_file() {
+        let file = ConfigFile::parse(
+            r#"
+            [listener]
+            port = 9000
+            unix_socket = "/run/q9gent.sock"
+            unix_socket_mode = "660"
+            unix_socket_owner = ":50"
+
+            [runner]
+            event_buffer = 16
+            workspace_retention = "keep-on-failure"
+            redact_patterns = ["sk-[a-z]+"]
+
+            [limits.default]
+            open_files = 256
+
+            [cors]
+            allowed_origins = ["https://app.example.com"]
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(file.listener.port, Some(9000));
+        assert_eq!(file.listener.unix_socket_mode, Some(0o660));
+        assert_eq!(file.listener.unix_socket_owner.unwrap().gid, Some(50));
+        assert_eq!(file.runner.event_buffer.unwrap().get(), 16);
+        assert_eq!(
+            file.runner.workspace_retention,
+            Some(WorkspaceRetention::KeepOnFailure)
+        );
+        assert_eq!(file.runner.redact_patterns.len(), 1);
+        assert_eq!(file.limits.default.unwrap().open_files, Some(256));
+        assert!(file.auth.tokens_file.is_none());
+    }
+
+    #[test]
+    fn test_errors_point_at_the_key() {
+        let cases = [
+            ("[listener]\nprot = 8080\n", "prot"),
+            ("[listener]\nport = \"http\"\n", "port = \"http\""),
+            (
+                "[listener]\nunix_socket_mode = \"999\"\n",
+                "invalid octal mode",
+            ),
+            ("[runner]\nevent_buffer = 0\n", "event_buffer = 0"),
+            ("[runner]\nredact_patterns = [\"(\"]\n", "redact_patterns"),
+            ("[logs]\nlevel = \"debug\"\n", "logs"),
+        ];
+        for (text, expected) in cases {
+            let error = ConfigFile::parse(text).unwrap_err().to_string();
+            assert!(error.contains(expected), "{}: {}", text, error);
+            assert!(
+                error.contains("line 2") || error.contains("line 1"),
+                "{}",
+                error
+            );
+        }
+    }
+}
@@ -0,0 +1,592 @@
+use anyhow::{bail, Context};
+use axum::http::HeaderValue;
+use std::num::{NonZeroU64, NonZeroUsize};
+use std::path::PathBuf;
+use std::time::Duration;
+
+use super::file::ConfigFile;
+use super::{
+    AuditConfig, AuthConfig, CassetteConfig, CassetteMode, ClaudeVersionCheck, CorsConfig,
+    EnvConfig, ListenerConfig, LogFormat, LogRotation, LoggingConfig, RedactionConfig,
+    ServerConfig, Settings, SocketOwner, TelemetryConfig, UnixSocketConfig, WorkspaceConfig,
+    WorkspaceRetention,
+};
+use crate::agent::{DEFAULT_EVENT_BUFFER, DEFAULT_GRACE_PERIOD};
+use crate::profile::Profiles;
+
+/// Server options from the command line and `Q9GENT_*` environment variables
+///
+/// Each option overrides the matching key of the `--config` file, which
+/// overrides the built-in default.
+#[derive(clap::Args, Debug, Clone, Default)]
+pub struct Options {
+    /// TOML configuration file; flags and environment variables override it
+    #[arg(long, global = true, env = "Q9GENT_CONFIG")]
+    pub config: Option<PathBuf>,
+
+    /// Server bind address [default: 127.0.0.1]
+    #[arg(short, long, env = "Q9GENT_HOST")]
+    pub host: Option<String>,
+
+    /// Server port [default: 8080]
+    #[arg(short, long, env = "Q9GENT_PORT")]
+    pub port: Option<u16>,
+
+    /// Session storage directory [default: ./sessions]
+    #[arg(short, long, global = true, env = "Q9GENT_SESSION_DIR")]
+    pub session_dir: Option<String>,
+
+    /// Path to claude CLI executable [default: claude]
+    #[arg(short, long, global = true, env = "Q9GENT_CLAUDE_PATH")]
+    pub claude_path: Option<String>,
+
+    /// What happens at startup when claude is not found or its version is
+    /// unsupported [default: warn]
+    #[arg(long, global = true, value_enum, env = "Q9GENT_CLAUDE_VERSION_CHECK")]
+    pub claude_version_check: Option<ClaudeVersionCheck>,
+
+    /// TOML file defining named profiles (`[profiles.<name>]`)
+    #[arg(long, global = true, env = "Q9GENT_PROFILES")]
+    pub profiles: Option<PathBuf>,
+
+    /// Directory runs may use as `workdir` or workspace template (repeatable)
+    #[arg(long = "allowed-workdir", global = true)]
+    pub allowed_workdirs: Vec<PathBuf>,
+
+    /// Parent directory for temporary per-run workspaces [default: system temp dir]
+    #[arg(long, global = true, env = "Q9GENT_WORKSPACE_DIR")]
+    pub workspace_dir: Option<PathBuf>,
+
+    /// What to do with a temporary workspace when its run ends [default: delete]
+    #[arg(long, global = true, value_enum, env = "Q9GENT_WORKSPACE_RETENTION")]
+    pub workspace_retention: Option<WorkspaceRetention>,
+
+    /// Server environment variable passed through to claude (repeatable;
+    /// added to PATH, HOME, LANG and the other defaults)
+    #[arg(long = "env-passthrough", global = true)]
+    pub env_passthrough: Vec<String>,
+
+    /// Directory relative secret paths in profiles are resolved against
+    #[arg(long, global = true, env = "Q9GENT_SECRETS_DIR")]
+    pub secrets_dir: Option<PathBuf>,
+
+    /// Pass the server's whole environment to claude instead of clearing it
+    #[arg(long, global = true, env = "Q9GENT_INHERIT_ENV")]
+    pub inherit_env: bool,
+
+    /// Regex redacted from claude's stdout and stderr (repeatable)
+    #[arg(long = "redact-pattern", global = true)]
+    pub redact_patterns: Vec<regex::Regex>,
+
+    /// Redact well-known credential formats (API keys, tokens, private keys)
+    #[arg(long, global = true, env = "Q9GENT_REDACT_KNOWN_SECRETS")]
+    pub redact_known_secrets: bool,
+
+    /// Keep a transcript of each run's output before redaction rules in this
+    /// directory (injected secrets stay redacted)
+    #[arg(long, global = true, env = "Q9GENT_TRANSCRIPT_DIR")]
+    pub transcript_dir: Option<PathBuf>,
+
+    /// Record each finished run as a cassette in --cassette-dir, or replay
+    /// runs from there without starting claude [default: off]
+    #[arg(long, global = true, value_enum, env = "Q9GENT_CASSETTE_MODE")]
+    pub cassette_mode: Option<CassetteMode>,
+
+    /// Directory cassettes are recorded to and replayed from
+    #[arg(long, global = true, env = "Q9GENT_CASSETTE_DIR")]
+    pub cassette_dir: Option<PathBuf>,
+
+    /// How many times faster than recorded cassettes are replayed; 0 replays
+    /// without delays [default: 1]
+    #[arg(
+        long,
+        global = true,
+        value_name = "FACTOR",
+        env = "Q9GENT_REPLAY_SPEED"
+    )]
+    pub replay_speed: Option<f64>,
+
+    /// Delegated cgroup v2 directory for per-run cgroups (Linux; used by
+    /// profiles with `[limits.cgroup]`)
+    #[arg(long, global = true, env = "Q9GENT_CGROUP_ROOT")]
+    pub cgroup_root: Option<PathBuf>,
+
+    /// Seconds a terminated claude process tree gets to exit after SIGTERM
+    /// before it is killed with SIGKILL (Unix) [default: 10]
+    #[arg(
+        long,
+        global = true,
+        value_name = "SECONDS",
+        env = "Q9GENT_KILL_GRACE_PERIOD"
+    )]
+    pub kill_grace_period: Option<u64>,
+
+    /// Events buffered per run before reading claude's output waits for the
+    /// client [default: 100]
+    #[arg(long, global = true, env = "Q9GENT_EVENT_BUFFER")]
+    pub event_buffer: Option<NonZeroUsize>,
+
+    /// Processes running at once beyond which `/ready` reports no capacity
+    /// [default: unlimited]
+    #[arg(long, global = true, env = "Q9GENT_MAX_RUNS")]
+    pub max_runs: Option<NonZeroUsize>,
+
+    /// Log filter [default: q9gent=info,tower_http=info]
+    #[arg(long, global = true, env = "RUST_LOG")]
+    pub log_level: Option<String>,
+
+    /// Log record format [default: text]
+    #[arg(long, global = true, value_enum, env = "Q9GENT_LOG_FORMAT")]
+    pub log_format: Option<LogFormat>,
+
+    /// Write logs to rotated files in this directory instead of stdout
+    #[arg(long, global = true, env = "Q9GENT_LOG_DIR")]
+    pub log_dir: Option<PathBuf>,
+
+    /// How often a new log file is started in --log-dir [default: daily]
+    #[arg(long, global = true, value_enum, env = "Q9GENT_LOG_ROTATION")]
+    pub log_rotation: Option<LogRotation>,
+
+    /// Rotated log files kept in --log-dir [default: all]
+    #[arg(long, global = true, env = "Q9GENT_LOG_MAX_FILES")]
+    pub log_max_files: Option<NonZeroUsize>,
+
+    /// Send trace spans to this OTLP/HTTP collector (e.g.
+    /// http://localhost:4318)
+    #[arg(long, global = true, env = "Q9GENT_OTLP_ENDPOINT")]
+    pub otlp_endpoint: Option<String>,
+
+    /// Append trace spans to this file as OTLP/JSON lines
+    #[arg(long, global = true, env = "Q9GENT_OTLP_FILE")]
+    pub otlp_file: Option<PathBuf>,
+
+    /// Append an audit record of every run to daily files in this directory
+    #[arg(long, global = true, env = "Q9GENT_AUDIT_DIR")]
+    pub audit_dir: Option<PathBuf>,
+
+    /// Record the first N characters of prompts in the audit log instead of
+    /// their SHA-256
+    #[arg(
+        long,
+        global = true,
+        value_name = "N",
+        env = "Q9GENT_AUDIT_PROMPT_CHARS"
+    )]
+    pub audit_prompt_chars: Option<usize>,
+
+    /// Also listen on a Unix domain socket at this path
+    #[arg(long, env = "Q9GENT_UNIX_SOCKET")]
+    pub unix_socket: Option<PathBuf>,
+
+    /// File mode for the Unix socket, in octal (e.g. 660)
+    #[arg(long, value_parser = super::parse_socket_mode, env = "Q9GENT_UNIX_SOCKET_MODE")]
+    pub unix_socket_mode: Option<u32>,
+
+    /// Numeric owner for the Unix socket as uid, uid:gid or :gid
+    #[arg(long, value_parser = super::parse_socket_owner, env = "Q9GENT_UNIX_SOCKET_OWNER")]
+    pub unix_socket_owner: Option<SocketOwner>,
+
+    /// Disable the TCP listener (requires a Unix socket)
+    #[arg(long, env = "Q9GENT_NO_TCP")]
+    pub no_tcp: bool,
+
+    /// Seconds running processes may keep going after SIGTERM/SIGINT before
+    /// they are terminated [default: 30]
+    #[arg(long, value_name = "SECONDS", env = "Q9GENT_SHUTDOWN_TIMEOUT")]
+    pub shutdown_timeout: Option<u64>,
+
+    /// Seconds between keep-alive comments on idle event streams [default: 15]
+    #[arg(long, value_name = "SECONDS", env = "Q9GENT_SSE_KEEP_ALIVE")]
+    pub sse_keep_alive: Option<NonZeroU64>,
+
+    /// Largest accepted request body in bytes [default: 2097152]
+    #[arg(long, value_name = "BYTES", env = "Q9GENT_MAX_REQUEST_BODY")]
+    pub max_request_body: Option<NonZeroUsize>,
+
+    /// Origin allowed to call the API from a browser (repeatable; `*` allows
+    /// any) [default: *]
+    #[arg(long = "cors-origin")]
+    pub cors_origins: Vec<String>,
+
+    /// File with one bearer token per line; requests without one of them
+    /// are rejected
+    #[arg(long, env = "Q9GENT_AUTH_TOKENS_FILE")]
+    pub auth_tokens_file: Option<PathBuf>,
+}
+
+const DEFAULT_SSE_KEEP_ALIVE: Duration = Duration::from_secs(15);
+const DEFAULT_MAX_REQUEST_BODY: usize = 2 * 1024 * 1024;
+const DEFAULT_SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Default log filter for the server; one-shot modes log warnings only
+pub const DEFAULT_LOG_FILTER: &str = "q9gent=info,tower_http=info";
+
+/// Options win over the file, lists included: a non-empty list replaces the
+/// file's
+fn override_list<T>(option: Vec<T>, file: Vec<T>) -> Vec<T> {
+    if option.is_empty() {
+        file
+    } else {
+        option
+    }
+}
+
+impl Options {
+    /// Read the config file, if any, apply these options over it and
+    /// validate the result
+    pub fn load(&self) -> anyhow::Result<Settings> {
+        let file = match self.config {
+            Some(ref path) => ConfigFile::load(path)?,
+            None => ConfigFile::default(),
+        };
+        self.resolve(file)
+    }
+
+    fn resolve(&self, file: ConfigFile) -> anyhow::Result<Settings> {
+        let options = self.clone();
+        let ConfigFile {
+            listener,
+            sessions,
+            runner,
+            limits,
+            cors,
+            auth,
+            logging,
+            telemetry,
+            audit,
+        } = file;
+
+        let mut workspace = WorkspaceConfig {
+            allowed_roots: override_list(options.allowed_workdirs, runner.allowed_workdirs),
+            retention: options
+                .workspace_retention
+                .or(runner.workspace_retention)
+                .unwrap_or(WorkspaceRetention::Delete),
+            ..Default::default()
+        };
+        if let Some(dir) = options.workspace_dir.or(runner.workspace_dir) {
+            workspace.temp_dir = dir;
+        }
+
+        let profiles_path = options.profiles.or(runner.profiles);
+        let profiles = match profiles_path {
+            Some(ref path) => Profiles::load(path)
+                .with_context(|| format!("Failed to load profiles from {}", path.display()))?,
+            None => Profiles::default(),
+        };
+
+        let cgroup_root = options.cgroup_root.or(limits.cgroup_root);
+        let default_limits = limits.default.unwrap_or_default();
+        if cgroup_root.is_none() {
+            if default_limits.cgroup.is_some() {
+                bail!("limits.default.cgroup is set but limits.cgroup_root (--cgroup-root) is not");
+            }
+            if let Some(name) = profiles.with_cgroup_limits().next() {
+                bail!(
+                    "profile '{}' sets [limits.cgroup] but limits.cgroup_root (--cgroup-root) is not set",
+                    name
+                );
+            }
+        }
+
+        let mut env = EnvConfig {
+            inherit: options.inherit_env || runner.inherit_env.unwrap_or(false),
+            secrets_dir: options.secrets_dir.or(runner.secrets_dir),
+            ..Default::default()
+        };
+        env.passthrough.extend(override_list(
+            options.env_passthrough,
+            runner.env_passthrough,
+        ));
+        for key in &env.passthrough {
+            crate::environment::validate_key(key)
+                .map_err(|e| anyhow::anyhow!("runner.env_passthrough: {}", e))?;
+        }
+
+        let mut redaction = RedactionConfig {
+            patterns: override_list(options.redact_patterns, runner.redact_patterns),
+            transcript_dir: options.transcript_dir.or(runner.transcript_dir),
+        };
+        if options.redact_known_secrets || runner.redact_known_secrets.unwrap_or(false) {
+            redaction.patterns.extend(crate::redact::known_formats());
+        }
+
+        let cassettes = CassetteConfig {
+            mode: options
+                .cassette_mode
+                .or(runner.cassette_mode)
+                .unwrap_or_default(),
+            dir: options.cassette_dir.or(runner.cassette_dir),
+            replay_speed: options.replay_speed.or(runner.replay_speed).unwrap_or(1.0),
+        };
+        if cassettes.mode != CassetteMode::Off && cassettes.dir.is_none() {
+            bail!("runner.cassette_mode requires runner.cassette_dir (--cassette-dir)");
+        }
+        if !cassettes.replay_speed.is_finite() || cassettes.replay_speed < 0.0 {
+            bail!(
+                "runner.replay_speed: {} is not a factor of 0 or more",
+                cassettes.replay_speed
+            );
+        }
+
+        let origins = if options.cors_origins.is_empty() {
+            cors.allowed_origins
+                .unwrap_or_else(|| vec!["*".to_string()])
+        } else {
+            options.cors_origins
+        };
+        let cors = if origins.iter().any(|origin| origin == "*") {
+            CorsConfig::Any
+        } else {
+            CorsConfig::Origins(
+                origins
+                    .iter()
+                    .map(|origin| {
+                        HeaderValue::from_str(origin).map_err(|_| {
+                            anyhow::anyhow!("cors.allowed_origins: invalid origin '{}'", origin)
+                        })
+                    })
+                    .collect::<anyhow::Result<_>>()?,
+            )
+        };
+
+        let tokens_path = options.auth_tokens_file.or(auth.tokens_file);
+        let auth = match tokens_path {
+            Some(ref path) => AuthConfig::load(path)
+                .with_context(|| format!("auth.tokens_file: {}", path.display()))?,
+            None => AuthConfig::default(),
+        };
+
+        let log_filter = options.log_level.or(logging.level);
+        if let Some(ref filter) = log_filter {
+            tracing_subscriber::EnvFilter::try_new(filter).map_err(|e| {
+                anyhow::anyhow!("logging.level: invalid filter '{}': {}", filter, e)
+            })?;
+        }
+        let dir = options.log_dir.or(logging.dir);
+        let rotation = options.log_rotation.or(logging.rotation);
+        let max_files = options.log_max_files.or(logging.max_files);
+        if dir.is_none() && (rotation.is_some() || max_files.is_some()) {
+            bail!("logging.rotation and logging.max_files require logging.dir (--log-dir)");
+        }
+        let logging = LoggingConfig {
+            format: options.log_format.or(logging.format).unwrap_or_default(),
+            dir,
+            rotation: rotation.unwrap_or_default(),
+            max_files,
+        };
+
+        let telemetry = TelemetryConfig {
+            otlp_endpoint: options.otlp_endpoint.or(telemetry.otlp_endpoint),
+            otlp_file: options.otlp_file.or(telemetry.otlp_file),
+        };
+        if let Some(ref endpoint) = telemetry.otlp_endpoint {
+            if !endpoint.starts_with("http://") && !endpoint.starts_with("https://") {
+                bail!(
+                    "telemetry.otlp_endpoint: '{}' is not an http:// or https:// URL",
+                    endpoint
+                );
+            }
+        }
+
+        let audit = AuditConfig {
+            dir: options.audit_dir.or(audit.dir),
+            prompt_chars: options.audit_prompt_chars.or(audit.prompt_chars),
+        };
+        if audit.dir.is_none() && audit.prompt_chars.is_some() {
+            bail!("audit.prompt_chars requires audit.dir (--audit-dir)");
+        }
+
+        let tcp = !options.no_tcp && listener.tcp.unwrap_or(true);
+        let unix_socket = options.unix_socket.or(listener.unix_socket);
+        let unix_socket_mode = options.unix_socket_mode.or(listener.unix_socket_mode);
+        let unix_socket_owner = options.unix_socket_owner.or(listener.unix_socket_owner);
+        if unix_socket.is_none() {
+            if !tcp {
+                bail!(
+                    "listener.tcp = false (--no-tcp) requires listener.unix_socket (--unix-socket)"
+                );
+            }
+            if unix_socket_mode.is_some() || unix_socket_owner.is_some() {
+                bail!("listener.unix_socket_mode and unix_socket_owner require listener.unix_socket (--unix-socket)");
+            }
+        }
+        let host = options
+            .host
+            .or(listener.host)
+            .unwrap_or_else(|| "127.0.0.1".to_string());
+        let port = options.port.or(listener.port).unwrap_or(8080);
+        let listeners = ListenerConfig {
+            tcp_addr: tcp.then(|| format!("{}:{}", host, port)),
+            unix_socket: unix_socket.map(|path| UnixSocketConfig {
+                path,
+                mode: unix_socket_mode,
+                owner: unix_socket_owner,
+            }),
+        };
+
+        let server = ServerConfig {
+            claude_path: options
+                .claude_path
+                .or(runner.claude_path)
+                .unwrap_or_else(|| "claude".to_string()),
+            claude_version_check: options
+                .claude_version_check
+                .or(runner.claude_version_check)
+                .unwrap_or_default(),
+            claude: None,
+            session_dir: options
+                .session_dir
+                .or(sessions.dir)
+                .unwrap_or_else(|| "./sessions".to_string()),
+            workspace,
+            profiles,
+            env,
+            redaction,
+            cgroup_root,
+            default_limits,
+            kill_grace_period: options
+                .kill_grace_period
+                .or(runner.kill_grace_period_secs)
+                .map_or(DEFAULT_GRACE_PERIOD, Duration::from_secs),
+            shutdown_timeout: options
+                .shutdown_timeout
+                .or(listener.shutdown_timeout_secs)
+                .map_or(DEFAULT_SHUTDOWN_TIMEOUT, Duration::from_secs),
+            event_buffer: options
+                .event_buffer
+                .or(runner.event_buffer)
+                .map_or(DEFAULT_EVENT_BUFFER, NonZeroUsize::get),
+            max_runs: options.max_runs.or(runner.max_runs),
+            sse_keep_alive: options
+                .sse_keep_alive
+                .or(listener.sse_keep_alive_secs)
+                .map_or(DEFAULT_SSE_KEEP_ALIVE, |secs| {
+                    Duration::from_secs(secs.get())
+                }),
+            max_request_body: options
+                .max_request_body
+                .or(limits.max_request_body_bytes)
+                .map_or(DEFAULT_MAX_REQUEST_BODY, NonZeroUsize::get),
+            cors,
+            auth,
+            audit,
+            cassettes,
+        };
+
+        let files = [self.config.clone(), profiles_path.clone(), tokens_path]
+            .into_iter()
+            .flatten()
+            .collect();
+        Ok(Settings {
+            server,
+            listeners,
+            log_filter,
+            logging,
+            telemetry,
+            profiles_path,
+            files,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn file(text: &str) -> ConfigFile {
+        toml::from_str(text).unwrap()
+    }
+
+    #[test]
+    fn test_defaults() {
+        let settings = Options::default().resolve(ConfigFile::default()).unwrap();
+        assert_eq!(settings.server.claude_path, "claude");
+        assert_eq!(settings.server.session_dir, "./sessions");
+        assert_eq!(settings.server.event_buffer, 100);
+        assert!(matches!(settings.server.cors, CorsConfig::Any));
+        assert!(settings.server.auth.tokens.is_empty());
+        assert_eq!(
+            settings.listeners.tcp_addr.as_deref(),
+            Some("127.0.0.1:8080")
+        );
+    }
+
+    #[test]
+    fn test_options_override_file() {
+        let config = file(
+            r#"
+            [listener]
+            host = "0.0.0.0"
+            port = 9000
+
+            [runner]
+            claude_path = "/opt/claude"
+            allowed_workdirs = ["/srv/a"]
+            kill_grace_period_secs = 3
+            max_runs = 4
+
+            [cors]
+            allowed_origins = ["https://app.example.com"]
+            "#,
+        );
+        let options = Options {
+            port: Some(9100),
+            allowed_workdirs: vec![PathBuf::from("/srv/b")],
+            ..Default::default()
+        };
+
+        let settings = options.resolve(config).unwrap();
+        assert_eq!(settings.listeners.tcp_addr.as_deref(), Some("0.0.0.0:9100"));
+        assert_eq!(settings.server.claude_path, "/opt/claude");
+        assert_eq!(
+            settings.server.workspace.allowed_roots,
+            vec![PathBuf::from("/srv/b")]
+        );
+        assert_eq!(settings.server.kill_grace_period, Duration::from_secs(3));
+        assert_eq!(settings.server.max_runs.map(NonZeroUsize::get), Some(4));
+        assert!(matches!(settings.server.cors, CorsConfig::Origins(ref o) if o.len() == 1));
+    }
+
+    #[test]
+    fn test_validation_names_the_key() {
+        let cases = [
+            ("[listener]\ntcp = false\n", "listener.tcp"),
+            (
+                "[listener]\nunix_socket_mode = \"600\"\n",
+                "listener.unix_socket",
+            ),
+            (
+                "[limits.default.cgroup]\npids_max = 10\n",
+                "limits.cgroup_root",
+            ),
+            ("[logging]\nlevel = \"q9gent=loud\"\n", "logging.level"),
+            (
+                "[runner]\nenv_passthrough = [\"A=B\"]\n",
+                "runner.env_passthrough",
+            ),
+            (
+                "[auth]\ntokens_file = \"/nonexistent/tokens\"\n",
+                "auth.tokens_file",
+            ),
+            ("[logging]\nmax_files = 7\n", "logging.dir"),
+            ("[audit]\nprompt_chars = 80\n", "audit.dir"),
+            (
+                "[runner]\ncassette_mode = \"replay\"\n",
+                "runner.cassette_dir",
+            ),
+            (
+                "[runner]\ncassette_dir = \"c\"\nreplay_speed = -1.0\n",
+                "runner.replay_speed",
+            ),
+            (
+                "[telemetry]\notlp_endpoint = \"localhost:4318\"\n",
+                "telemetry.otlp_endpoint",
+            ),
+        ];
+        for (text, key) in cases {
+            let error = format!("{:#}", Options::default().resolve(file(text)).unwrap_err());
+            assert!(error.contains(key), "{}: {}", text, error);
+        }
+    }
+}
@@ -0,0 +1,227 @@
+use std::collections::BTreeMap;
+
+use crate::config::{EnvConfig, ServerConfig};
+use crate::error::{AppError, AppResult};
+use crate::profile::Profile;
+use crate::redact::Redactor;
+use crate::secrets;
+
+/// Server variables passed through in addition to `--env-passthrough`;
+/// enough for claude to find node, its config directory and a locale
+#[cfg(not(windows))]
+pub const DEFAULT_PASSTHROUGH: &[&str] = &[
+    "PATH", "HOME", "USER", "LOGNAME", "SHELL", "LANG", "LC_ALL", "LC_CTYPE", "TERM", "TMPDIR",
+    "TZ",
+];
+
+/// Server variables passed through in addition to `--env-passthrough`;
+/// enough for claude to find node, its config directory and a locale
+#[cfg(windows)]
+pub const DEFAULT_PASSTHROUGH: &[&str] = &[
+    "PATH",
+    "PATHEXT",
+    "SYSTEMROOT",
+    "SYSTEMDRIVE",
+    "WINDIR",
+    "COMSPEC",
+    "TEMP",
+    "TMP",
+    "USERNAME",
+    "USERPROFILE",
+    "HOMEDRIVE",
+    "HOMEPATH",
+    "APPDATA",
+    "LOCALAPPDATA",
+    "PROGRAMDATA",
+    "PROGRAMFILES",
+];
+
+/// Reject names that cannot be set as an environment variable
+pub fn validate_key(key: &str) -> AppResult<()> {
+    if key.is_empty() || key.contains(['=', '\0']) {
+        return Err(AppError::InvalidRequest(format!(
+            "invalid environment variable name: {:?}",
+            key
+        )));
+    }
+    Ok(())
+}
+
+/// The server's own environment, without variables that are not valid UTF-8
+pub fn server_env() -> BTreeMap<String, String> {
+    std::env::vars_os()
+        .filter_map(|(key, value)| Some((key.into_string().ok()?, value.into_string().ok()?)))
+        .collect()
+}
+
+/// Value of a server variable; names are case-insensitive on Windows, where
+/// `PATH` is usually set as `Path`
+fn lookup<'a>(server: &'a BTreeMap<String, String>, key: &str) -> Option<&'a String> {
+    if cfg!(windows) {
+        server
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case(key))
+            .map(|(_, value)| value)
+    } else {
+        server.get(key)
+    }
+}
+
+/// Build the complete environment of a claude process
+///
+/// Later layers win: the variables of `server` allowed by `config`, the
+/// profile's `env`, then the request's variables. Every request variable must
+/// be listed in the profile's `request_env`.
+pub fn resolve(
+    config: &EnvConfig,
+    server: &BTreeMap<String, String>,
+    profile: Option<&Profile>,
+    request: &BTreeMap<String, String>,
+) -> AppResult<BTreeMap<String, String>> {
+    for key in request.keys() {
+        validate_key(key)?;
+        let permitted = profile.is_some_and(|p| p.request_env.iter().any(|k| k == key));
+        if !permitted {
+            return Err(AppError::EnvNotAllowed(key.clone()));
+        }
+    }
+
+    let mut env = BTreeMap::new();
+    if config.inherit {
+        env.extend(server.clone());
+    } else {
+        for key in &config.passthrough {
+            if let Some(value) = lookup(server, key) {
+                env.insert(key.clone(), value.clone());
+            }
+        }
+    }
+
+    if let Some(profile) = profile {
+        env.extend(profile.env.clone());
+    }
+    env.extend(request.clone());
+    Ok(env)
+}
+
+/// Resolve the environment of a run with the named profile, if any
+///
+/// The profile's secrets are added last, so neither the profile's `env` nor
+/// the request can override them. The returned [`Redactor`] removes their
+/// values, and matches of the server's and the profile's redaction rules,
+/// from the run's output.
+pub async fn for_run(
+    config: &ServerConfig,
+    profile: Option<&str>,
+    request: &BTreeMap<String, String>,
+) -> AppResult<(BTreeMap<String, String>, Redactor)> {
+    let profile = config.profile(profile)?;
+    let mut env = resolve(&config.env, &server_env(), profile, request)?;
+    let mut patterns = config.redaction.patterns.clone();
+
+    let Some(profile) = profile else {
+        return Ok((env, Redactor::new(patterns, [])));
+    };
+    patterns.extend(profile.redact.iter().cloned());
+    let secrets = secrets::load(profile, config.env.secrets_dir.as_deref()).await?;
+    let redactor = Redactor::new(
+        patterns,
+        secrets
+            .iter()
+            .map(|(name, value)| (name.as_str(), value.as_str())),
+    );
+    env.extend(secrets);
+    Ok((env, redactor))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resolve_layers() {
+        let server = BTreeMap::from([
+            ("PASSED".to_string(), "server".to_string()),
+            ("HIDDEN".to_string(), "secret".to_string()),
+        ]);
+        let config = EnvConfig {
+            inherit: false,
+            passthrough: vec!["PASSED".to_string()],
+            secrets_dir: None,
+        };
+        let profile = Profile {
+            env: BTreeMap::from([
+                ("PASSED".to_string(), "profile".to_string()),
+                ("MODE".to_string(), "ci".to_string()),
+            ]),
+            request_env: vec!["TICKET".to_string()],
+            ..Default::default()
+        };
+        let request = BTreeMap::from([("TICKET".to_string(), "ABC-1".to_string())]);
+
+        let env = resolve(&config, &server, Some(&profile), &request).unwrap();
+        assert_eq!(env["PASSED"], "profile");
+        assert_eq!(env["MODE"], "ci");
+        assert_eq!(env["TICKET"], "ABC-1");
+        assert!(!env.contains_key("HIDDEN"));
+
+        let inherited = resolve(
+            &EnvConfig {
+                inherit: true,
+                ..config
+            },
+            &server,
+            None,
+            &BTreeMap::new(),
+        )
+        .unwrap();
+        assert_eq!(inherited, server);
+    }
+
+    #[test]
+    fn test_passthrough_case() {
+        let server = BTreeMap::from([
+            ("Path".to_string(), "/usr/bin".to_string()),
+            ("SystemRoot".to_string(), "C:\\Windows".to_string()),
+        ]);
+        let config = EnvConfig {
+            inherit: false,
+            passthrough: vec!["PATH".to_string(), "SYSTEMROOT".to_string()],
+            secrets_dir: None,
+        };
+
+        let env = resolve(&config, &server, None, &BTreeMap::new()).unwrap();
+        if cfg!(windows) {
+            assert_eq!(env["PATH"], "/usr/bin");
+            assert_eq!(env["SYSTEMROOT"], "C:\\Windows");
+        } else {
+            assert!(env.is_empty());
+        }
+    }
+
+    #[test]
+    fn test_request_env_requires_permission() {
+        let config = EnvConfig::default();
+        let request = BTreeMap::from([("TICKET".to_string(), "ABC-1".to_string())]);
+
+        assert!(matches!(
+            resolve(&config, &BTreeMap::new(), None, &request),
+            Err(AppError::EnvNotAllowed(key)) if key == "TICKET"
+        ));
+        assert!(matches!(
+            resolve(
+                &config,
+                &BTreeMap::new(),
+                Some(&Profile::default()),
+                &request
+            ),
+            Err(AppError::EnvNotAllowed(_))
+        ));
+
+        let bad = BTreeMap::from([("A=B".to_string(), String::new())]);
+        assert!(matches!(
+            resolve(&config, &BTreeMap::new(), None, &bad),
+            Err(AppError::InvalidRequest(_))
+        ));
+    }
+}
@@ -3,19 +3,80 @@ use axum::{
     response::{IntoResponse, Response},
     Json,
 };
+use q9gent_client::types::{ErrorCode, ErrorResponse};
 use serde_json::json;
 
 #[derive(thiserror::Error, Debug)]
 pub enum AppError {
+    #[error("Invalid request: {0}")]
+    InvalidRequest(String),
+
+    #[error("Flag '{0}' is managed by Q9gent and cannot be passed through")]
+    InvalidFlag(String),
+
     #[error("Process spawn failed: {0}")]
     ProcessSpawnFailed(String),
 
     #[error("Process execution error: {0}")]
     ProcessExecutionError(String),
 
+    #[error("Claude CLI not found: {0}")]
+    ClaudeNotFound(String),
+
+    #[error("Claude CLI authentication failed: {0}")]
+    ClaudeAuthFailed(String),
+
+    #[error("Claude API rate limit reached: {0}")]
+    ClaudeRateLimited(String),
+
+    #[error("Claude rejected the session: {0}")]
+    ClaudeSessionInvalid(String),
+
     #[error("Session not found: {0}")]
     SessionNotFound(String),
 
+    #[error("Run not found: {0}")]
+    RunNotFound(String),
+
+    #[error("No running process for session: {0}")]
+    ProcessNotRunning(String),
+
+    #[error("Session already has a running process: {0}")]
+    SessionBusy(String),
+
+    #[error("Working directory is outside the allowed roots: {0}")]
+    WorkdirNotAllowed(String),
+
+    #[error("Unknown profile: {0}")]
+    UnknownProfile(String),
+
+    #[error("Environment variable is not permitted for this request: {0}")]
+    EnvNotAllowed(String),
+
+    #[error("Secret could not be read: {0}")]
+    SecretUnavailable(String),
+
+    #[error("Session has no git worktree: {0}")]
+    NoWorktree(String),
+
+    #[error("Git command failed: {0}")]
+    GitFailed(String),
+
+    #[error("Server is shutting down")]
+    ShuttingDown,
+
+    #[error("Missing or invalid bearer token")]
+    Unauthorized,
+
+    #[error("Configuration reload failed: {0}")]
+    ReloadFailed(String),
+
+    #[error("No audit log is configured")]
+    AuditDisabled,
+
+    #[error("No cassette recorded for this request: {0}")]
+    CassetteNotFound(String),
+
     #[error("IO error: {0}")]
     IoError(#[from] std::io::Error),
 
@@ -23,24 +84,152 @@ pub enum AppError {
     SerializationError(#[from] serde_json::Error),
 }
 
+impl AppError {
+    /// Stable machine-readable code for this error
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            AppError::InvalidRequest(_) => ErrorCode::InvalidRequest,
+            AppError::InvalidFlag(_) => ErrorCode::InvalidFlag,
+            AppError::ProcessSpawnFailed(_) => ErrorCode::SpawnFailed,
+            AppError::ProcessExecutionError(_) => ErrorCode::ProcessFailed,
+            AppError::ClaudeNotFound(_) => ErrorCode::ClaudeNotFound,
+            AppError::ClaudeAuthFailed(_) => ErrorCode::AuthFailed,
+            AppError::ClaudeRateLimited(_) => ErrorCode::RateLimited,
+            AppError::ClaudeSessionInvalid(_) => ErrorCode::InvalidSession,
+            AppError::SessionNotFound(_) => ErrorCode::SessionNotFound,
+            AppError::RunNotFound(_) => ErrorCode::RunNotFound,
+            AppError::ProcessNotRunning(_) => ErrorCode::ProcessNotRunning,
+            AppError::SessionBusy(_) => ErrorCode::SessionBusy,
+            AppError::WorkdirNotAllowed(_) => ErrorCode::WorkdirNotAllowed,
+            AppError::UnknownProfile(_) => ErrorCode::UnknownProfile,
+            AppError::EnvNotAllowed(_) => ErrorCode::EnvNotAllowed,
+            AppError::SecretUnavailable(_) => ErrorCode::SecretUnavailable,
+            AppError::NoWorktree(_) => ErrorCode::NoWorktree,
+            AppError::GitFailed(_) => ErrorCode::GitFailed,
+            AppError::ShuttingDown => ErrorCode::ShuttingDown,
+            AppError::Unauthorized => ErrorCode::Unauthorized,
+            AppError::ReloadFailed(_) => ErrorCode::ReloadFailed,
+            AppError::AuditDisabled => ErrorCode::AuditDisabled,
+            AppError::CassetteNotFound(_) => ErrorCode::CassetteNotFound,
+            AppError::IoError(_) | AppError::SerializationError(_) => ErrorCode::InternalError,
+        }
+    }
+
+    /// HTTP status for this error
+    pub fn status(&self) -> StatusCode {
+        match self {
+            AppError::InvalidRequest(_)
+            | AppError::InvalidFlag(_)
+            | AppError::UnknownProfile(_) => StatusCode::BAD_REQUEST,
+            AppError::SessionNotFound(_)
+            | AppError::RunNotFound(_)
+            | AppError::ProcessNotRunning(_)
+            | AppError::NoWorktree(_)
+            | AppError::AuditDisabled
+            | AppError::CassetteNotFound(_) => StatusCode::NOT_FOUND,
+            AppError::Unauthorized => StatusCode::UNAUTHORIZED,
+            AppError::SessionBusy(_) => StatusCode::CONFLICT,
+            AppError::WorkdirNotAllowed(_) | AppError::EnvNotAllowed(_) => StatusCode::FORBIDDEN,
+            AppError::ClaudeSessionInvalid(_) => StatusCode::NOT_FOUND,
+            AppError::ClaudeRateLimited(_) => StatusCode::TOO_MANY_REQUESTS,
+            AppError::ClaudeAuthFailed(_) => StatusCode::BAD_GATEWAY,
+            AppError::ClaudeNotFound(_) | AppError::ShuttingDown => StatusCode::SERVICE_UNAVAILABLE,
+            AppError::ProcessSpawnFailed(_)
+            | AppError::ProcessExecutionError(_)
+            | AppError::GitFailed(_)
+            | AppError::SecretUnavailable(_)
+            | AppError::ReloadFailed(_)
+            | AppError::IoError(_)
+            | AppError::SerializationError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    /// Structured context for the error body
+    fn details(&self) -> Option<serde_json::Value> {
+        match self {
+            AppError::InvalidFlag(flag) => Some(json!({ "flag": flag })),
+            AppError::SessionNotFound(id)
+            | AppError::ProcessNotRunning(id)
+            | AppError::SessionBusy(id)
+            | AppError::NoWorktree(id) => Some(json!({ "session_id": id })),
+            AppError::RunNotFound(id) => Some(json!({ "run_id": id })),
+            AppError::UnknownProfile(profile) => Some(json!({ "profile": profile })),
+            AppError::WorkdirNotAllowed(path) => Some(json!({ "workdir": path })),
+            AppError::EnvNotAllowed(key) => Some(json!({ "key": key })),
+            AppError::SecretUnavailable(secret) => Some(json!({ "secret": secret })),
+            AppError::CassetteNotFound(cassette) => Some(json!({ "cassette": cassette })),
+            _ => None,
+        }
+    }
+
+    /// Build the error body shared by HTTP responses and SSE `error` events
+    pub fn to_error_response(&self, request_id: Option<&str>) -> ErrorResponse {
+        ErrorResponse {
+            code: self.code(),
+            error: self.to_string(),
+            details: self.details(),
+            request_id: request_id.map(str::to_string),
+        }
+    }
+}
+
 impl IntoResponse for AppError {
     fn into_response(self) -> Response {
-        let (status, error_message) = match self {
-            AppError::ProcessSpawnFailed(msg) => (StatusCode::INTERNAL_SERVER_ERROR, msg),
-            AppError::ProcessExecutionError(msg) => (StatusCode::INTERNAL_SERVER_ERROR, msg),
-            AppError::SessionNotFound(msg) => (StatusCode::NOT_FOUND, msg),
-            AppError::IoError(err) => (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()),
-            AppError::SerializationError(err) => {
-                (StatusCode::INTERNAL_SERVER_ERROR, err.to_string())
-            }
-        };
-
-        let body = Json(json!({
-            "error": error_message,
-        }));
-
-        (status, body).into_response()
+        let body = self.to_error_response(None);
+
+        // The request ID middleware fills in `request_id` from this extension
+        let mut response = (self.status(), Json(body.clone())).into_response();
+        response.extensions_mut().insert(body);
+        response
     }
 }
 
 pub type AppResult<T> = Result<T, AppError>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_client_errors_map_to_4xx() {
+        let cases = [
+            (
+                AppError::InvalidRequest("bad".into()),
+                StatusCode::BAD_REQUEST,
+            ),
+            (
+                AppError::InvalidFlag("--print".into()),
+                StatusCode::BAD_REQUEST,
+            ),
+            (AppError::SessionNotFound("s".into()), StatusCode::NOT_FOUND),
+            (
+                AppError::ProcessNotRunning("s".into()),
+                StatusCode::NOT_FOUND,
+            ),
+            (AppError::SessionBusy("s".into()), StatusCode::CONFLICT),
+            (
+                AppError::WorkdirNotAllowed("/etc".into()),
+                StatusCode::FORBIDDEN,
+            ),
+            (
+                AppError::EnvNotAllowed("AWS_SECRET_ACCESS_KEY".into()),
+                StatusCode::FORBIDDEN,
+            ),
+        ];
+
+        for (error, status) in cases {
+            assert_eq!(error.status(), status, "{}", error);
+        }
+    }
+
+    #[test]
+    fn test_error_response_shape() {
+        let body = AppError::InvalidFlag("--output-format".into()).to_error_response(Some("req-1"));
+
+        let json = serde_json::to_value(&body).unwrap();
+        assert_eq!(json["code"], "invalid_flag");
+        assert_eq!(json["details"]["flag"], "--output-format");
+        assert_eq!(json["request_id"], "req-1");
+        assert!(json["error"].as_str().unwrap().contains("--output-format"));
+    }
+}
@@ -0,0 +1,419 @@
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tokio::fs;
+use tracing::{debug, info, warn};
+
+use q9gent_client::types::{RunInfo, RunStatus};
+
+use crate::error::{AppError, AppResult};
+use crate::session::write_atomic;
+
+/// Finished runs older than this are pruned when the server starts
+const RETENTION: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+/// A run as stored on disk: what the runs API returns plus what recovery needs
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct JournalEntry {
+    #[serde(flatten)]
+    run: RunInfo,
+
+    /// PID of the Q9gent process that started the run
+    owner_pid: u32,
+
+    /// Start time of that process, like `process_start`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    owner_start: Option<u64>,
+
+    /// Start time of the claude process in clock ticks since boot (Linux),
+    /// to tell it apart from a later process given the same PID
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    process_start: Option<u64>,
+}
+
+/// Journal of runs, one JSON file per run in `<session_dir>/runs`
+///
+/// Entries are written when a run starts and updated when Claude reports its
+/// session and when the run ends, so a run still marked running after the
+/// server stopped was interrupted.
+pub struct RunJournal {
+    dir: PathBuf,
+}
+
+fn now() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
+
+impl RunJournal {
+    pub fn new(session_dir: impl AsRef<Path>) -> Self {
+        Self {
+            dir: session_dir.as_ref().join("runs"),
+        }
+    }
+
+    /// Path of a run's entry; run IDs come from request paths
+    fn path(&self, run_id: &str) -> AppResult<PathBuf> {
+        let valid = !run_id.is_empty()
+            && run_id.len() <= 64
+            && run_id.chars().all(|c| c.is_ascii_hexdigit() || c == '-');
+        if !valid {
+            return Err(AppError::InvalidRequest(format!(
+                "invalid run ID '{}'",
+                run_id
+            )));
+        }
+        Ok(self.dir.join(format!("{}.json", run_id)))
+    }
+
+    async fn save(&self, entry: &JournalEntry) -> AppResult<()> {
+        let path = self.path(&entry.run.run_id)?;
+        fs::create_dir_all(&self.dir).await?;
+        write_atomic(&path, &serde_json::to_vec_pretty(entry)?).await?;
+        Ok(())
+    }
+
+    async fn load(&self, run_id: &str) -> AppResult<JournalEntry> {
+        let path = self.path(run_id)?;
+        match fs::read(&path).await {
+            Ok(json) => Ok(serde_json::from_slice(&json)?),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                Err(AppError::RunNotFound(run_id.to_string()))
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Change a stored run; failures are logged, never fail the run
+    async fn update(&self, run_id: &str, change: impl FnOnce(&mut RunInfo)) {
+        let updated = match self.load(run_id).await {
+            Ok(mut entry) => {
+                change(&mut entry.run);
+                self.save(&entry).await
+            }
+            Err(e) => Err(e),
+        };
+        if let Err(e) = updated {
+            warn!("⚠️  Failed to update run {} in the journal: {}", run_id, e);
+        }
+    }
+
+    /// Record a run whose process has just been spawned
+    pub async fn start(&self, run: RunInfo) {
+        let entry = JournalEntry {
+            process_start: run.pid.and_then(process_start),
+            owner_pid: std::process::id(),
+            owner_start: process_start(std::process::id()),
+            run,
+        };
+        match self.save(&entry).await {
+            Ok(()) => debug!("📒 Recorded run {}", entry.run.run_id),
+            Err(e) => warn!(
+                "⚠️  Failed to record run {} in the journal: {}",
+                entry.run.run_id, e
+            ),
+        }
+    }
+
+    /// Record the session a new run created, as soon as Claude reports it
+    pub async fn set_session(&self, run_id: &str, session_id: &str) {
+        self.update(run_id, |run| run.session_id = Some(session_id.to_string()))
+            .await;
+    }
+
+    /// Record how a run ended
+    pub async fn finish(
+        &self,
+        run_id: &str,
+        status: RunStatus,
+        exit_code: Option<i32>,
+        session_id: Option<String>,
+    ) {
+        self.update(run_id, |run| {
+            run.status = status;
+            run.exit_code = exit_code;
+            run.ended_at = Some(now());
+            if session_id.is_some() {
+                run.session_id = session_id;
+            }
+        })
+        .await;
+    }
+
+    async fn entries(&self) -> AppResult<Vec<JournalEntry>> {
+        let mut entries = Vec::new();
+        let mut dir = match fs::read_dir(&self.dir).await {
+            Ok(dir) => dir,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(entries),
+            Err(e) => return Err(e.into()),
+        };
+        while let Some(file) = dir.next_entry().await? {
+            let name = file.file_name().to_string_lossy().to_string();
+            let Some(run_id) = name.strip_suffix(".json") else {
+                continue;
+            };
+            match self.load(run_id).await {
+                Ok(entry) => entries.push(entry),
+                Err(e) => warn!("⚠️  Skipping unreadable journal entry {}: {}", name, e),
+            }
+        }
+        Ok(entries)
+    }
+
+    /// Every recorded run, newest first
+    pub async fn list(&self) -> AppResult<Vec<RunInfo>> {
+        let mut runs: Vec<_> = self
+            .entries()
+            .await?
+            .into_iter()
+            .map(|entry| entry.run)
+            .collect();
+        runs.sort_by_key(|run| std::cmp::Reverse(run.started_at));
+        Ok(runs)
+    }
+
+    pub async fn get(&self, run_id: &str) -> AppResult<RunInfo> {
+        Ok(self.load(run_id).await?.run)
+    }
+
+    /// Mark runs left running by a server that is gone as interrupted, stop
+    /// their leftover process groups and prune old finished runs
+    ///
+    /// Leftovers get SIGTERM and, after `grace_period`, SIGKILL. Returns the
+    /// interrupted runs.
+    pub async fn recover(&self, grace_period: Duration) -> AppResult<Vec<RunInfo>> {
+        let mut interrupted = Vec::new();
+        for mut entry in self.entries().await? {
+            match entry.run.status {
+                RunStatus::Running if !owner_alive(&entry) => {
+                    entry.run.status = RunStatus::Interrupted;
+                    entry.run.ended_at = Some(now());
+                    self.save(&entry).await?;
+                    interrupted.push(entry);
+                }
+                RunStatus::Running => {}
+                _ => {
+                    let expired = entry
+                        .run
+                        .ended_at
+                        .is_some_and(|ended| now().saturating_sub(ended) > RETENTION.as_secs());
+                    if expired {
+                        fs::remove_file(self.path(&entry.run.run_id)?).await?;
+                    }
+                }
+            }
+        }
+
+        #[cfg(unix)]
+        futures::future::join_all(
+            interrupted
+                .iter()
+                .map(|entry| stop_leftovers(entry, grace_period)),
+        )
+        .await;
+        #[cfg(not(unix))]
+        let _ = grace_period;
+
+        if !interrupted.is_empty() {
+            info!("📒 Marked {} run(s) as interrupted", interrupted.len());
+        }
+        Ok(interrupted.into_iter().map(|entry| entry.run).collect())
+    }
+}
+
+/// Whether the Q9gent process that started a run is still running
+///
+/// When that cannot be told, the owner is assumed to be alive so the runs of
+/// another server sharing the session directory are left alone.
+fn owner_alive(entry: &JournalEntry) -> bool {
+    let pid = entry.owner_pid;
+    // After a reboot or in another container the PID may belong to any process
+    if let (Some(recorded), Some(current)) = (entry.owner_start, process_start(pid)) {
+        return recorded == current;
+    }
+    // A restarted server, e.g. PID 1 in a container, may get its old PID back
+    if pid == std::process::id() {
+        return false;
+    }
+    #[cfg(unix)]
+    {
+        // SAFETY: signal 0 only checks that the process exists
+        let exists = unsafe { libc::kill(pid as libc::pid_t, 0) } == 0;
+        exists || std::io::Error::last_os_error().raw_os_error() == Some(libc::EPERM)
+    }
+    #[cfg(not(unix))]
+    true
+}
+
+#[cfg(unix)]
+fn process_start(pid: u32) -> Option<u64> {
+    crate::agent::tree::start_time(pid)
+}
+
+#[cfg(not(unix))]
+fn process_start(_pid: u32) -> Option<u64> {
+    None
+}
+
+/// Stop whatever is left of an interrupted run's process group
+#[cfg(unix)]
+async fn stop_leftovers(entry: &JournalEntry, grace_period: Duration) {
+    use crate::agent::tree::{start_time, ProcessTree};
+
+    let Some(pid) = entry.run.pid else {
+        return;
+    };
+    // The PID now belongs to another process, so the group is not the run's
+    if let (Some(recorded), Some(current)) = (entry.process_start, start_time(pid)) {
+        if recorded != current {
+            return;
+        }
+    }
+
+    let mut tree = ProcessTree::new(Some(pid), None, grace_period);
+    if !tree.is_alive() {
+        return;
+    }
+    warn!(
+        "⚠️  Stopping leftover processes of run {} (process group {})",
+        entry.run.run_id, pid
+    );
+    if let Err(e) = tree.terminate() {
+        warn!("Failed to terminate process group {}: {}", pid, e);
+    }
+    tree.reap().await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(run_id: &str, started_at: u64) -> RunInfo {
+        RunInfo {
+            run_id: run_id.to_string(),
+            status: RunStatus::Running,
+            agent_type: "test".to_string(),
+            session_id: None,
+            pid: None,
+            workdir: None,
+            started_at,
+            ended_at: None,
+            exit_code: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_journal_lifecycle() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let journal = RunJournal::new(temp_dir.path());
+
+        journal.start(run("0a1b", 100)).await;
+        journal.set_session("0a1b", "session-1").await;
+        journal
+            .finish("0a1b", RunStatus::Failed, Some(1), None)
+            .await;
+        journal.start(run("2c3d", 200)).await;
+
+        let runs = journal.list().await.unwrap();
+        assert_eq!(runs.len(), 2);
+        assert_eq!(runs[0].run_id, "2c3d");
+        assert_eq!(runs[1].status, RunStatus::Failed);
+        assert_eq!(runs[1].session_id.as_deref(), Some("session-1"));
+        assert_eq!(runs[1].exit_code, Some(1));
+        assert!(runs[1].ended_at.is_some());
+
+        assert!(matches!(
+            journal.get("ffff").await,
+            Err(AppError::RunNotFound(_))
+        ));
+        assert!(matches!(
+            journal.get("../x").await,
+            Err(AppError::InvalidRequest(_))
+        ));
+    }
+
+    /// Store a running entry as if started by the server `owner_pid`
+    async fn start_owned(
+        journal: &RunJournal,
+        run: RunInfo,
+        owner_pid: u32,
+        owner_start: Option<u64>,
+    ) {
+        let entry = JournalEntry {
+            process_start: run.pid.and_then(process_start),
+            owner_pid,
+            owner_start,
+            run,
+        };
+        journal.save(&entry).await.unwrap();
+    }
+
+    #[tokio::test]
+    #[cfg(unix)]
+    async fn test_recover_interrupted_runs() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let journal = RunJournal::new(temp_dir.path());
+
+        // A leftover claude process in its own group, owned by a server that
+        // is gone: a previous server whose PID this one was given
+        let mut leftover = std::process::Command::new("sleep");
+        std::os::unix::process::CommandExt::process_group(&mut leftover, 0);
+        let mut leftover = leftover.arg("30").spawn().unwrap();
+        let mut interrupted = run("0a1b", 100);
+        interrupted.pid = Some(leftover.id());
+        interrupted.session_id = Some("session-1".to_string());
+        let own_start = process_start(std::process::id()).map(|start| start + 1);
+        start_owned(&journal, interrupted, std::process::id(), own_start).await;
+
+        let mut expired = run("2c3d", 100);
+        expired.status = RunStatus::Completed;
+        expired.ended_at = Some(100);
+        journal.start(expired).await;
+
+        let recovered = journal.recover(Duration::from_secs(5)).await.unwrap();
+        assert_eq!(recovered.len(), 1);
+        assert_eq!(recovered[0].session_id.as_deref(), Some("session-1"));
+
+        let status = leftover.wait().unwrap();
+        assert!(!status.success());
+        let runs = journal.list().await.unwrap();
+        assert_eq!(runs.len(), 1);
+        assert_eq!(runs[0].status, RunStatus::Interrupted);
+    }
+
+    #[tokio::test]
+    #[cfg(unix)]
+    async fn test_recover_checks_owner_start_time() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let journal = RunJournal::new(temp_dir.path());
+
+        // Another server that is still running
+        let mut owner = std::process::Command::new("sleep")
+            .arg("30")
+            .spawn()
+            .unwrap();
+        let owner_start = process_start(owner.id());
+        start_owned(&journal, run("0a1b", 100), owner.id(), owner_start).await;
+        // A server that is gone, whose PID now belongs to that process
+        let reused = owner_start.map(|start| start + 1);
+        start_owned(&journal, run("2c3d", 100), owner.id(), reused).await;
+
+        let recovered = journal.recover(Duration::from_secs(5)).await.unwrap();
+        owner.kill().unwrap();
+        owner.wait().unwrap();
+
+        let recovered: Vec<_> = recovered.iter().map(|run| run.run_id.as_str()).collect();
+        if cfg!(target_os = "linux") {
+            assert_eq!(recovered, ["2c3d"]);
+        } else {
+            // Without start times the owner counts as alive
+            assert!(recovered.is_empty());
+        }
+        assert_eq!(
+            journal.get("0a1b").await.unwrap().status,
+            RunStatus::Running
+        );
+    }
+}
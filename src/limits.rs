@@ -0,0 +1,299 @@
+use serde::Deserialize;
+use std::path::{Path, PathBuf};
+use tokio::process::Command;
+use tracing::{debug, warn};
+use uuid::Uuid;
+
+use q9gent_client::types::ResourceUsage;
+
+/// Per-profile resource limits for claude processes (Linux only)
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RunLimits {
+    /// Virtual address space per process in MiB (`RLIMIT_AS`); Node reserves
+    /// several GiB up front, so low values stop claude from starting
+    pub address_space_mb: Option<u64>,
+
+    /// CPU seconds per process (`RLIMIT_CPU`)
+    pub cpu_seconds: Option<u64>,
+
+    /// Open file descriptors per process (`RLIMIT_NOFILE`)
+    pub open_files: Option<u64>,
+
+    /// Processes for the server's user (`RLIMIT_NPROC`)
+    pub processes: Option<u64>,
+
+    /// Limits for a cgroup v2 created for each run under `--cgroup-root`
+    pub cgroup: Option<CgroupLimits>,
+}
+
+/// Limits written to a run's cgroup v2
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CgroupLimits {
+    /// `memory.max` in MiB for the whole process tree
+    pub memory_max_mb: Option<u64>,
+
+    /// `cpu.max` as a percentage of one CPU (200 = two CPUs)
+    pub cpu_max_percent: Option<u32>,
+
+    /// `pids.max` for the whole process tree
+    pub pids_max: Option<u64>,
+}
+
+impl RunLimits {
+    /// Whether any rlimit is set
+    fn has_rlimits(&self) -> bool {
+        self.address_space_mb.is_some()
+            || self.cpu_seconds.is_some()
+            || self.open_files.is_some()
+            || self.processes.is_some()
+    }
+}
+
+/// Period `cpu.max` quotas are expressed against, in microseconds
+const CPU_PERIOD_USEC: u64 = 100_000;
+
+/// A cgroup v2 holding one run's process tree
+#[derive(Debug)]
+pub struct Cgroup {
+    path: PathBuf,
+}
+
+impl Cgroup {
+    /// Create a cgroup below `root` (which must be delegated to the server's
+    /// user with the memory, cpu and pids controllers enabled for children)
+    pub fn create(root: &Path, limits: &CgroupLimits) -> std::io::Result<Self> {
+        let path = root.join(format!("q9gent-{}", Uuid::new_v4().simple()));
+        std::fs::create_dir(&path)?;
+        let cgroup = Self { path };
+
+        let mut settings = Vec::new();
+        if let Some(mb) = limits.memory_max_mb {
+            settings.push(("memory.max", (mb * 1024 * 1024).to_string()));
+        }
+        if let Some(percent) = limits.cpu_max_percent {
+            let quota = u64::from(percent) * CPU_PERIOD_USEC / 100;
+            settings.push(("cpu.max", format!("{} {}", quota, CPU_PERIOD_USEC)));
+        }
+        if let Some(pids) = limits.pids_max {
+            settings.push(("pids.max", pids.to_string()));
+        }
+        for (file, value) in settings {
+            if let Err(e) = std::fs::write(cgroup.path.join(file), &value) {
+                cgroup.remove();
+                return Err(std::io::Error::new(
+                    e.kind(),
+                    format!("failed to set {} to {}: {}", file, value, e),
+                ));
+            }
+        }
+
+        debug!("📦 Created cgroup {}", cgroup.path.display());
+        Ok(cgroup)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Peak memory and CPU time of everything that ran in the cgroup
+    pub fn usage(&self) -> ResourceUsage {
+        let peak_memory_bytes = std::fs::read_to_string(self.path.join("memory.peak"))
+            .ok()
+            .and_then(|peak| peak.trim().parse().ok());
+        let cpu_time_ms = std::fs::read_to_string(self.path.join("cpu.stat"))
+            .ok()
+            .and_then(|stat| {
+                stat.lines()
+                    .find_map(|line| line.strip_prefix("usage_usec "))
+                    .and_then(|usec| usec.trim().parse::<u64>().ok())
+            })
+            .map(|usec| usec / 1000);
+        ResourceUsage {
+            peak_memory_bytes,
+            cpu_time_ms,
+        }
+    }
+
+    /// SIGKILL every process in the cgroup, including ones that left the
+    /// run's process group
+    #[cfg(unix)]
+    pub fn kill(&self) {
+        if let Err(e) = std::fs::write(self.path.join("cgroup.kill"), "1") {
+            warn!("⚠️  Failed to kill cgroup {}: {}", self.path.display(), e);
+        }
+    }
+
+    /// Whether any process is still in the cgroup
+    #[cfg(unix)]
+    pub fn populated(&self) -> bool {
+        std::fs::read_to_string(self.path.join("cgroup.events"))
+            .map(|events| events.lines().any(|line| line == "populated 1"))
+            .unwrap_or(false)
+    }
+
+    /// Remove the cgroup; fails while processes are still in it
+    pub fn remove(self) {
+        if let Err(e) = std::fs::remove_dir(&self.path) {
+            warn!("⚠️  Failed to remove cgroup {}: {}", self.path.display(), e);
+        }
+    }
+}
+
+/// Apply rlimits and cgroup membership to `command` in the child between
+/// fork and exec
+#[cfg(target_os = "linux")]
+pub fn apply(command: &mut Command, limits: &RunLimits, cgroup: Option<&Cgroup>) {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+
+    if !limits.has_rlimits() && cgroup.is_none() {
+        return;
+    }
+
+    const MIB: u64 = 1024 * 1024;
+    let rlimits = [
+        (libc::RLIMIT_AS, limits.address_space_mb.map(|mb| mb * MIB)),
+        (libc::RLIMIT_CPU, limits.cpu_seconds),
+        (libc::RLIMIT_NOFILE, limits.open_files),
+        (libc::RLIMIT_NPROC, limits.processes),
+    ];
+    // Allocated before fork; only async-signal-safe calls happen in the child
+    let procs = cgroup.map(|cgroup| {
+        CString::new(cgroup.path.join("cgroup.procs").as_os_str().as_bytes())
+            .expect("cgroup path contains no NUL bytes")
+    });
+
+    // SAFETY: the closure only calls setrlimit, open, write and close
+    unsafe {
+        command.pre_exec(move || {
+            if let Some(ref procs) = procs {
+                let fd = libc::open(procs.as_ptr(), libc::O_WRONLY | libc::O_CLOEXEC);
+                if fd < 0 {
+                    return Err(std::io::Error::last_os_error());
+                }
+                // Writing 0 moves the writing process
+                let written = libc::write(fd, b"0".as_ptr().cast(), 1);
+                libc::close(fd);
+                if written != 1 {
+                    return Err(std::io::Error::last_os_error());
+                }
+            }
+            for (resource, value) in rlimits {
+                if let Some(value) = value {
+                    let limit = libc::rlimit {
+                        rlim_cur: value as libc::rlim_t,
+                        rlim_max: value as libc::rlim_t,
+                    };
+                    if libc::setrlimit(resource, &limit) != 0 {
+                        return Err(std::io::Error::last_os_error());
+                    }
+                }
+            }
+            Ok(())
+        });
+    }
+}
+
+/// Resource limits are only enforced on Linux
+#[cfg(not(target_os = "linux"))]
+pub fn apply(_command: &mut Command, limits: &RunLimits, _cgroup: Option<&Cgroup>) {
+    if limits.has_rlimits() || limits.cgroup.is_some() {
+        warn!("⚠️  Resource limits are only supported on Linux and are ignored");
+    }
+}
+
+/// Block until process `pid` has exited, without reaping it, and return the
+/// resource usage of it and the children it waited for
+///
+/// The process stays a zombie so the caller can still reap it through
+/// [`tokio::process::Child::wait`].
+#[cfg(target_os = "linux")]
+pub fn wait_exited(pid: u32) -> std::io::Result<Option<ResourceUsage>> {
+    let mut info: libc::siginfo_t = unsafe { std::mem::zeroed() };
+    let mut usage: libc::rusage = unsafe { std::mem::zeroed() };
+    loop {
+        // The raw syscall takes a fifth rusage argument libc's wrapper lacks
+        let rc = unsafe {
+            libc::syscall(
+                libc::SYS_waitid,
+                libc::P_PID,
+                pid as libc::id_t,
+                &mut info as *mut libc::siginfo_t,
+                libc::WEXITED | libc::WNOWAIT,
+                &mut usage as *mut libc::rusage,
+            )
+        };
+        if rc == 0 {
+            break;
+        }
+        let e = std::io::Error::last_os_error();
+        if e.kind() != std::io::ErrorKind::Interrupted {
+            return Err(e);
+        }
+    }
+
+    let millis = |tv: libc::timeval| tv.tv_sec as u64 * 1000 + tv.tv_usec as u64 / 1000;
+    Ok(Some(ResourceUsage {
+        // ru_maxrss is in KiB
+        peak_memory_bytes: Some(usage.ru_maxrss as u64 * 1024),
+        cpu_time_ms: Some(millis(usage.ru_utime) + millis(usage.ru_stime)),
+    }))
+}
+
+/// Block until process `pid` has exited, without reaping it; resource usage
+/// is only reported on Linux
+#[cfg(all(unix, not(target_os = "linux")))]
+pub fn wait_exited(pid: u32) -> std::io::Result<Option<ResourceUsage>> {
+    let mut info: libc::siginfo_t = unsafe { std::mem::zeroed() };
+    loop {
+        let rc = unsafe {
+            libc::waitid(
+                libc::P_PID,
+                pid as libc::id_t,
+                &mut info,
+                libc::WEXITED | libc::WNOWAIT,
+            )
+        };
+        if rc == 0 {
+            return Ok(None);
+        }
+        let e = std::io::Error::last_os_error();
+        if e.kind() != std::io::ErrorKind::Interrupted {
+            return Err(e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cgroup_files() {
+        // A plain directory stands in for a delegated cgroup v2 root
+        let root = tempfile::tempdir().unwrap();
+        let limits = CgroupLimits {
+            memory_max_mb: Some(512),
+            cpu_max_percent: Some(150),
+            pids_max: Some(64),
+        };
+        let cgroup = Cgroup::create(root.path(), &limits).unwrap();
+
+        let read = |file: &str| std::fs::read_to_string(cgroup.path().join(file)).unwrap();
+        assert_eq!(read("memory.max"), "536870912");
+        assert_eq!(read("cpu.max"), "150000 100000");
+        assert_eq!(read("pids.max"), "64");
+
+        std::fs::write(cgroup.path().join("memory.peak"), "1048576\n").unwrap();
+        std::fs::write(
+            cgroup.path().join("cpu.stat"),
+            "usage_usec 2500000\nuser_usec 2000000\n",
+        )
+        .unwrap();
+        let usage = cgroup.usage();
+        assert_eq!(usage.peak_memory_bytes, Some(1048576));
+        assert_eq!(usage.cpu_time_ms, Some(2500));
+    }
+}
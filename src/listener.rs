@@ -0,0 +1,187 @@
+use std::io;
+
+use axum::Router;
+use tokio::net::UnixListener;
+use tokio::task::JoinSet;
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, info, warn};
+
+use crate::config::UnixSocketConfig;
+
+/// Bind a Unix domain socket and apply the configured mode and owner
+///
+/// A stale socket left behind by a previous run is removed first. Any other
+/// kind of file at the path is left alone and reported as an error.
+pub fn bind_unix(config: &UnixSocketConfig) -> io::Result<UnixListener> {
+    use socket2::{Domain, SockAddr, Socket, Type};
+    use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+
+    match std::fs::symlink_metadata(&config.path) {
+        Ok(metadata) if metadata.file_type().is_socket() => {
+            debug!("Removing stale socket at {}", config.path.display());
+            std::fs::remove_file(&config.path)?;
+        }
+        Ok(_) => {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!(
+                    "{} exists and is not a socket, refusing to replace it",
+                    config.path.display()
+                ),
+            ));
+        }
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+        Err(e) => return Err(e),
+    }
+
+    // Connections are refused until `listen`, so nobody can connect while
+    // the socket still has the umask's mode
+    let socket = Socket::new(Domain::UNIX, Type::STREAM, None)?;
+    socket.bind(&SockAddr::unix(&config.path)?)?;
+
+    if let Some(mode) = config.mode {
+        std::fs::set_permissions(&config.path, std::fs::Permissions::from_mode(mode))?;
+        debug!("Socket mode set to {:o}", mode);
+    }
+
+    if let Some(owner) = config.owner {
+        std::os::unix::fs::chown(&config.path, owner.uid, owner.gid)?;
+        debug!("Socket owner set to {:?}:{:?}", owner.uid, owner.gid);
+    }
+
+    socket.listen(1024)?;
+    socket.set_nonblocking(true)?;
+    UnixListener::from_std(socket.into())
+}
+
+/// Serve the router on an already bound Unix socket
+///
+/// Once `shutdown` is cancelled no connections are accepted and open ones are
+/// closed as soon as their current response has finished.
+pub async fn serve_unix(
+    listener: UnixListener,
+    app: Router,
+    shutdown: CancellationToken,
+) -> io::Result<()> {
+    use hyper_util::{rt::TokioIo, service::TowerToHyperService};
+
+    if let Ok(addr) = listener.local_addr() {
+        info!("🚀 Server listening on unix:{:?}", addr.as_pathname());
+    }
+
+    let mut connections = JoinSet::new();
+    loop {
+        // Forget connections that have ended
+        while connections.try_join_next().is_some() {}
+
+        let accepted = tokio::select! {
+            accepted = listener.accept() => accepted,
+            () = shutdown.cancelled() => break,
+        };
+        let (stream, _) = match accepted {
+            Ok(conn) => conn,
+            Err(e) => {
+                // Transient errors such as EMFILE must not take the listener down
+                warn!("⚠️  Failed to accept Unix socket connection: {}", e);
+                tokio::time::sleep(std::time::Duration::from_secs(1)).await;
+                continue;
+            }
+        };
+
+        let service = TowerToHyperService::new(app.clone());
+        let shutdown = shutdown.clone();
+        connections.spawn(async move {
+            let builder = hyper::server::conn::http1::Builder::new();
+            let conn = builder
+                .serve_connection(TokioIo::new(stream), service)
+                .with_upgrades();
+            tokio::pin!(conn);
+            let result = tokio::select! {
+                result = conn.as_mut() => result,
+                () = shutdown.cancelled() => {
+                    conn.as_mut().graceful_shutdown();
+                    conn.await
+                }
+            };
+            if let Err(e) = result {
+                debug!("Unix socket connection ended with error: {}", e);
+            }
+        });
+    }
+
+    while connections.join_next().await.is_some() {}
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::routing::get;
+    use std::os::unix::fs::PermissionsExt;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    #[tokio::test]
+    async fn test_unix_socket_mode_and_request() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let path = temp_dir.path().join("q9gent.sock");
+        let config = UnixSocketConfig {
+            path: path.clone(),
+            mode: Some(0o600),
+            owner: None,
+        };
+
+        let listener = bind_unix(&config).unwrap();
+        let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+
+        let app = Router::new().route("/health", get(|| async { "ok" }));
+        let shutdown = CancellationToken::new();
+        let server = tokio::spawn(serve_unix(listener, app, shutdown.clone()));
+
+        let mut stream = tokio::net::UnixStream::connect(&path).await.unwrap();
+        stream
+            .write_all(b"GET /health HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+            .await
+            .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+
+        assert!(response.starts_with("HTTP/1.1 200"));
+        assert!(response.ends_with("ok"));
+
+        // An idle connection does not hold up shutdown
+        let _idle = tokio::net::UnixStream::connect(&path).await.unwrap();
+        shutdown.cancel();
+        tokio::time::timeout(std::time::Duration::from_secs(5), server)
+            .await
+            .expect("listener stops on shutdown")
+            .unwrap()
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_unix_socket_replaces_stale_socket_only() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let path = temp_dir.path().join("q9gent.sock");
+        let config = UnixSocketConfig {
+            path: path.clone(),
+            mode: None,
+            owner: None,
+        };
+
+        // A socket left behind by a previous listener is replaced
+        drop(bind_unix(&config).unwrap());
+        assert!(bind_unix(&config).is_ok());
+
+        // A regular file is never removed
+        let file_path = temp_dir.path().join("not-a-socket");
+        std::fs::write(&file_path, "data").unwrap();
+        let config = UnixSocketConfig {
+            path: file_path.clone(),
+            mode: None,
+            owner: None,
+        };
+        assert!(bind_unix(&config).is_err());
+        assert!(file_path.exists());
+    }
+}
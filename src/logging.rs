@@ -0,0 +1,205 @@
+use opentelemetry::trace::TracerProvider;
+use opentelemetry_sdk::trace::SdkTracerProvider;
+use serde_json::{Map, Value};
+use std::fmt;
+use tracing::field::{Field, Visit};
+use tracing::{Event, Subscriber};
+use tracing_appender::non_blocking::WorkerGuard;
+use tracing_appender::rolling::{RollingFileAppender, Rotation};
+use tracing_subscriber::fmt::format::{JsonFields, Writer};
+use tracing_subscriber::fmt::time::{FormatTime, SystemTime};
+use tracing_subscriber::fmt::writer::BoxMakeWriter;
+use tracing_subscriber::fmt::{FmtContext, FormatEvent, FormattedFields};
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt, EnvFilter, Layer};
+
+use crate::config::{LogFormat, LogRotation, LoggingConfig};
+use crate::telemetry;
+
+/// Install the global subscriber: logs filtered by `filter` in the configured
+/// format, to stdout (stderr when stdout carries command output) or rotated
+/// files, and spans recorded with `tracer_provider`
+///
+/// The log filter applies to log output only; spans are recorded whatever
+/// the log level. Logs written to files are flushed when the returned guard
+/// is dropped.
+pub fn init(
+    filter: &str,
+    config: &LoggingConfig,
+    to_stderr: bool,
+    tracer_provider: Option<&SdkTracerProvider>,
+) -> anyhow::Result<Option<WorkerGuard>> {
+    let (writer, guard) = match config.dir {
+        Some(ref dir) => {
+            let mut appender = RollingFileAppender::builder()
+                .rotation(match config.rotation {
+                    LogRotation::Hourly => Rotation::HOURLY,
+                    LogRotation::Daily => Rotation::DAILY,
+                    LogRotation::Never => Rotation::NEVER,
+                })
+                .filename_prefix("q9gent")
+                .filename_suffix("log");
+            if let Some(max_files) = config.max_files {
+                appender = appender.max_log_files(max_files.get());
+            }
+            let appender = appender
+                .build(dir)
+                .map_err(|e| anyhow::anyhow!("logging.dir: {}: {}", dir.display(), e))?;
+            let (writer, guard) = tracing_appender::non_blocking(appender);
+            (BoxMakeWriter::new(writer), Some(guard))
+        }
+        None if to_stderr => (BoxMakeWriter::new(std::io::stderr), None),
+        None => (BoxMakeWriter::new(std::io::stdout), None),
+    };
+
+    let logs = match config.format {
+        LogFormat::Text => tracing_subscriber::fmt::layer()
+            .with_writer(writer)
+            .with_ansi(config.dir.is_none())
+            .boxed(),
+        LogFormat::Json => tracing_subscriber::fmt::layer()
+            .fmt_fields(JsonFields::new())
+            .event_format(JsonFormat)
+            .with_writer(writer)
+            .boxed(),
+    };
+    let spans = tracer_provider.map(|provider| {
+        tracing_opentelemetry::layer()
+            .with_tracer(provider.tracer("q9gent"))
+            .with_filter(tracing_subscriber::filter::filter_fn(telemetry::exported))
+    });
+    tracing_subscriber::registry()
+        .with(logs.with_filter(EnvFilter::new(filter)))
+        .with(spans)
+        .init();
+    Ok(guard)
+}
+
+/// One JSON object per line with the record's fields and those of the spans
+/// it was logged in, flattened
+///
+/// Spans carry the request ID, agent type, session ID, run ID and PID once
+/// they are known, so every record of a run can be found by any of them.
+/// Fields of inner spans and of the record itself win over outer ones.
+struct JsonFormat;
+
+impl<S> FormatEvent<S, JsonFields> for JsonFormat
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn format_event(
+        &self,
+        ctx: &FmtContext<'_, S, JsonFields>,
+        mut writer: Writer<'_>,
+        event: &Event<'_>,
+    ) -> fmt::Result {
+        let metadata = event.metadata();
+        let mut timestamp = String::new();
+        SystemTime.format_time(&mut Writer::new(&mut timestamp))?;
+
+        let mut record = Map::new();
+        record.insert("timestamp".to_string(), timestamp.into());
+        record.insert("level".to_string(), metadata.level().as_str().into());
+        record.insert("target".to_string(), metadata.target().into());
+        if let Some(scope) = ctx.event_scope() {
+            for span in scope.from_root() {
+                record.insert("span".to_string(), span.name().into());
+                let extensions = span.extensions();
+                let Some(fields) = extensions.get::<FormattedFields<JsonFields>>() else {
+                    continue;
+                };
+                if let Ok(Value::Object(fields)) = serde_json::from_str(fields) {
+                    record.extend(fields);
+                }
+            }
+        }
+        event.record(&mut JsonVisitor(&mut record));
+
+        writeln!(writer, "{}", Value::Object(record))
+    }
+}
+
+/// Collects a record's fields into a JSON object
+struct JsonVisitor<'a>(&'a mut Map<String, Value>);
+
+impl Visit for JsonVisitor<'_> {
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        self.0
+            .insert(field.name().to_string(), format!("{:?}", value).into());
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.0.insert(field.name().to_string(), value.into());
+    }
+
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        self.0.insert(field.name().to_string(), value.into());
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        self.0.insert(field.name().to_string(), value.into());
+    }
+
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        self.0.insert(field.name().to_string(), value.into());
+    }
+
+    fn record_f64(&mut self, field: &Field, value: f64) {
+        self.0.insert(field.name().to_string(), value.into());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+
+    #[test]
+    fn test_json_records_carry_span_fields() {
+        let output = Arc::new(Mutex::new(Vec::new()));
+        let sink = output.clone();
+        let subscriber = tracing_subscriber::registry().with(
+            tracing_subscriber::fmt::layer()
+                .fmt_fields(JsonFields::new())
+                .event_format(JsonFormat)
+                .with_writer(move || SharedWriter(sink.clone())),
+        );
+
+        tracing::subscriber::with_default(subscriber, || {
+            let request = tracing::info_span!(
+                "http_request",
+                request_id = "req-1",
+                run_id = tracing::field::Empty,
+                pid = tracing::field::Empty,
+            );
+            let _request = request.enter();
+            request.record("run_id", "run-1");
+            let exit = tracing::info_span!("process_exit", pid = 42);
+            let _exit = exit.enter();
+            tracing::warn!(lines = 3, "⚠️  Process exited");
+        });
+
+        let output = String::from_utf8(output.lock().unwrap().clone()).unwrap();
+        let record: Value = serde_json::from_str(output.trim()).unwrap();
+        assert_eq!(record["level"], "WARN");
+        assert_eq!(record["message"], "⚠️  Process exited");
+        assert_eq!(record["request_id"], "req-1");
+        assert_eq!(record["run_id"], "run-1");
+        assert_eq!(record["pid"], 42);
+        assert_eq!(record["lines"], 3);
+        assert_eq!(record["span"], "process_exit");
+    }
+
+    struct SharedWriter(Arc<Mutex<Vec<u8>>>);
+
+    impl std::io::Write for SharedWriter {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+}
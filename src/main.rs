@@ -1,63 +1,279 @@
 mod agent;
 mod api;
+mod audit;
+mod cassette;
+mod claude;
+mod cli;
 mod config;
+mod environment;
 mod error;
+mod journal;
+mod limits;
+#[cfg(unix)]
+mod listener;
+mod logging;
+mod metrics;
+mod profile;
+mod redact;
+mod reload;
+mod secrets;
 mod session;
+mod shutdown;
+mod telemetry;
+mod transcript;
+mod workspace;
+mod worktree;
 
-use anyhow::Result;
-use clap::Parser;
+use anyhow::{Context, Result};
+use clap::{Parser, Subcommand};
+use opentelemetry_sdk::trace::SdkTracerProvider;
 use std::sync::Arc;
-use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
 
 #[derive(Parser, Debug)]
-#[command(author, version, about, long_about = None)]
+#[command(author, version, about, long_about = None, disable_help_flag = true)]
 struct Args {
-    /// Server bind address
-    #[arg(short, long, default_value = "127.0.0.1")]
-    host: String,
+    #[command(subcommand)]
+    command: Option<Command>,
 
-    /// Server port
-    #[arg(short, long, default_value = "8080")]
-    port: u16,
+    /// Print help (`-h` is taken by --host)
+    #[arg(long, action = clap::ArgAction::Help)]
+    help: Option<bool>,
 
-    /// Session storage directory
-    #[arg(short, long, default_value = "./sessions")]
-    session_dir: String,
+    #[command(flatten)]
+    options: config::Options,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Run a single agent without the HTTP server, printing events as JSONL
+    Run(cli::RunArgs),
+
+    /// Drive a remote Q9gent server
+    Client(cli::ClientArgs),
 
-    /// Path to claude CLI executable
-    #[arg(short, long, default_value = "claude")]
-    claude_path: String,
+    /// Inspect the configuration
+    #[command(subcommand)]
+    Config(ConfigCommand),
+}
+
+#[derive(Subcommand, Debug)]
+enum ConfigCommand {
+    /// Load the config file with environment and command-line overrides,
+    /// validate it and exit non-zero on errors
+    Check,
 }
 
 #[tokio::main]
 async fn main() -> Result<()> {
-    // Initialize tracing
-    tracing_subscriber::registry()
-        .with(
-            tracing_subscriber::EnvFilter::try_from_default_env()
-                .unwrap_or_else(|_| "q9gent=info,tower_http=info".into()),
-        )
-        .with(tracing_subscriber::fmt::layer())
-        .init();
-
     let args = Args::parse();
 
-    tracing::info!("🎯 Q9gent v{} starting...", env!("CARGO_PKG_VERSION"));
-    tracing::info!("📂 Session directory: {}", args.session_dir);
-    tracing::info!("🔧 Claude CLI path: {}", args.claude_path);
+    // The client talks to a remote server and needs no local state
+    if let Some(Command::Client(client_args)) = args.command {
+        logging::init(
+            args.options.log_level.as_deref().unwrap_or("q9gent=warn"),
+            &Default::default(),
+            true,
+            None,
+        )?;
+        let code = cli::client(client_args).await?;
+        std::process::exit(code);
+    }
+
+    if let Some(Command::Config(ConfigCommand::Check)) = args.command {
+        std::process::exit(config_check(&args.options).await);
+    }
+
+    let mut settings = args.options.load().context("Invalid configuration")?;
+
+    // In run mode stdout carries the JSONL event stream, so logs go to stderr
+    let one_shot = args.command.is_some();
+    let default_filter = if one_shot {
+        "q9gent=warn"
+    } else {
+        config::DEFAULT_LOG_FILTER
+    };
+    let tracer_provider = telemetry::init(&settings.telemetry)?;
+    // Flushes logs written to files when dropped
+    let log_guard = logging::init(
+        settings.log_filter.as_deref().unwrap_or(default_filter),
+        &settings.logging,
+        one_shot,
+        Some(&tracer_provider),
+    )?;
 
-    let config = Arc::new(config::ServerConfig {
-        claude_path: args.claude_path,
-        session_dir: args.session_dir,
-    });
+    tracing::info!("🎯 Q9gent v{} starting...", env!("CARGO_PKG_VERSION"));
+    if let Some(ref path) = args.options.config {
+        tracing::info!("⚙️  Configuration: {}", path.display());
+    }
+    tracing::info!("📂 Session directory: {}", settings.server.session_dir);
+    let cassettes = &settings.server.cassettes;
+    match (cassettes.mode, cassettes.dir.as_ref()) {
+        (config::CassetteMode::Replay, Some(dir)) => {
+            tracing::info!("📼 Replaying runs from cassettes in {}", dir.display());
+        }
+        (config::CassetteMode::Record, Some(dir)) => {
+            tracing::info!("📼 Recording runs as cassettes in {}", dir.display());
+        }
+        _ => {}
+    }
+    // Replayed runs never start claude
+    if settings.server.cassettes.mode != config::CassetteMode::Replay {
+        settings.server.claude = claude::discover(
+            &settings.server.claude_path,
+            settings.server.claude_version_check,
+        )
+        .await?;
+        tracing::info!(
+            "🔧 Claude CLI path: {}",
+            settings.server.claude_executable()
+        );
+    }
+    if let Some(ref path) = settings.profiles_path {
+        tracing::info!("🧩 Profiles: {}", path.display());
+    }
+    if settings.server.env.inherit {
+        tracing::warn!("⚠️  Claude processes inherit the server's entire environment");
+    }
+    if let Some(ref dir) = settings.server.redaction.transcript_dir {
+        tracing::info!("📝 Unredacted transcripts: {}", dir.display());
+    }
+    if let Some(ref endpoint) = settings.telemetry.otlp_endpoint {
+        tracing::info!("🔭 Exporting traces to {}", endpoint);
+    }
+    if let Some(ref path) = settings.telemetry.otlp_file {
+        tracing::info!("🔭 Writing traces to {}", path.display());
+    }
+    if let Some(ref dir) = settings.logging.dir {
+        tracing::info!("🪵 Logs: {}", dir.display());
+    }
+    if !settings.server.auth.tokens.is_empty() {
+        tracing::info!(
+            "🔐 Bearer token authentication enabled ({} token(s))",
+            settings.server.auth.tokens.len()
+        );
+    }
 
     // Create session directory if it doesn't exist
-    tokio::fs::create_dir_all(&config.session_dir).await?;
+    tokio::fs::create_dir_all(&settings.server.session_dir).await?;
     tracing::debug!("✓ Session directory ready");
 
-    let addr = format!("{}:{}", args.host, args.port);
+    if let Some(Command::Run(run_args)) = args.command {
+        let code = cli::run(run_args, Arc::new(settings.server)).await?;
+        shutdown_tracer(tracer_provider).await;
+        drop(log_guard);
+        std::process::exit(code);
+    }
+
+    let config = config::SharedConfig::new(settings.server.clone());
+    let reloader = Arc::new(reload::Reloader::new(
+        args.options,
+        &settings,
+        config.clone(),
+    ));
+    let served = api::serve(settings.listeners, config, reloader).await;
+    shutdown_tracer(tracer_provider).await;
+    drop(log_guard);
+    served
+}
+
+/// Load and validate the configuration, print what it resolves to and
+/// return the exit code for `config check`
+async fn config_check(options: &config::Options) -> i32 {
+    let source = match options.config {
+        Some(ref path) => path.display().to_string(),
+        None => "defaults".to_string(),
+    };
+    let settings = match options.load() {
+        Ok(settings) => settings,
+        Err(e) => {
+            eprintln!("❌ Configuration from {} is invalid: {:#}", source, e);
+            return 1;
+        }
+    };
+
+    println!("✓ Configuration from {} is valid", source);
+    if let Some(ref addr) = settings.listeners.tcp_addr {
+        println!("  listener: http://{}", addr);
+    }
+    if let Some(ref socket) = settings.listeners.unix_socket {
+        println!("  listener: unix:{}", socket.path.display());
+    }
+    println!("  session dir: {}", settings.server.session_dir);
+    let server = &settings.server;
+    if server.cassettes.mode == config::CassetteMode::Replay {
+        println!("  claude: not started when replaying cassettes");
+    } else {
+        match claude::discover(&server.claude_path, server.claude_version_check).await {
+            Ok(Some(found)) => println!(
+                "  claude: {} ({})",
+                found.path.display(),
+                found.version.as_deref().unwrap_or("version unknown")
+            ),
+            Ok(None) => println!("  claude: {} (not found)", server.claude_path),
+            Err(e) => {
+                eprintln!("❌ {:#}", e);
+                return 1;
+            }
+        }
+    }
+    if let Some(ref path) = settings.profiles_path {
+        println!("  profiles: {}", path.display());
+    }
+    if let Some(ref dir) = server.cassettes.dir {
+        match server.cassettes.mode {
+            config::CassetteMode::Record => println!("  cassettes: record to {}", dir.display()),
+            config::CassetteMode::Replay => println!(
+                "  cassettes: replay from {} at {}x",
+                dir.display(),
+                server.cassettes.replay_speed
+            ),
+            config::CassetteMode::Off => {}
+        }
+    }
+    0
+}
+
+/// Export the spans still buffered
+async fn shutdown_tracer(tracer_provider: SdkTracerProvider) {
+    let result = tokio::task::spawn_blocking(move || tracer_provider.shutdown()).await;
+    if let Ok(Err(e)) = result {
+        eprintln!("⚠️  Failed to export remaining trace spans: {}", e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::CommandFactory;
+
+    #[test]
+    fn test_cli_definition() {
+        Args::command().debug_assert();
+    }
 
-    api::serve(&addr, config).await?;
+    #[test]
+    fn test_parse_run_subcommand() {
+        let args = Args::try_parse_from([
+            "q9gent",
+            "run",
+            "--prompt-file",
+            "task.md",
+            "--tools",
+            "Read,Grep",
+            "--claude-path",
+            "/opt/claude",
+            "--",
+            "--model",
+            "sonnet",
+        ])
+        .unwrap();
 
-    Ok(())
+        assert_eq!(args.options.claude_path.as_deref(), Some("/opt/claude"));
+        let Some(Command::Run(run)) = args.command else {
+            panic!("expected run subcommand");
+        };
+        assert_eq!(run.tools_allowed, vec!["Read", "Grep"]);
+        assert_eq!(run.flags, vec!["--model", "sonnet"]);
+        assert!(run.prompt.prompt.is_none());
+    }
 }
@@ -0,0 +1,276 @@
+use prometheus::{
+    Encoder, Histogram, HistogramOpts, HistogramVec, IntCounter, IntCounterVec, IntGauge, Opts,
+    Registry, TextEncoder,
+};
+use std::collections::HashSet;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use q9gent_client::types::RunStatus;
+
+/// Buckets for the time until claude's first stdout line, in seconds
+const FIRST_LINE_BUCKETS: &[f64] = &[0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0];
+
+/// Buckets for the duration of a run, in seconds
+const RUN_BUCKETS: &[f64] = &[
+    1.0, 5.0, 10.0, 30.0, 60.0, 120.0, 300.0, 600.0, 1800.0, 3600.0,
+];
+
+/// Distinct `agent_type` label values; later agent types count as `other`
+const MAX_AGENT_TYPES: usize = 32;
+
+/// Prometheus metrics of the server, exported by `GET /metrics`
+///
+/// Counters and histograms are updated as runs and requests happen; gauges
+/// are read from the runner and session store when the metrics are rendered.
+pub struct Metrics {
+    registry: Registry,
+    spawns: IntCounterVec,
+    time_to_first_line: Histogram,
+    run_duration: HistogramVec,
+    output_lines: IntCounter,
+    stderr_lines: IntCounter,
+    active_processes: IntGauge,
+    queued_events: IntGauge,
+    sessions: IntGauge,
+    http_request_duration: HistogramVec,
+
+    /// Agent types used as label values so far
+    agent_types: Mutex<HashSet<String>>,
+}
+
+/// What `Metrics::render` reads from the rest of the server at scrape time
+pub struct Snapshot {
+    /// Processes that have not yet exited and been reported
+    pub active_processes: usize,
+
+    /// Events produced by runs and not yet taken by their client
+    pub queued_events: usize,
+
+    /// Sessions in the session store
+    pub sessions: usize,
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        let spawns = IntCounterVec::new(
+            Opts::new(
+                "q9gent_spawns_total",
+                "Claude processes by agent type and outcome (completed, failed, terminated, spawn_failed)",
+            ),
+            &["agent_type", "outcome"],
+        )
+        .unwrap();
+        let time_to_first_line = Histogram::with_opts(
+            HistogramOpts::new(
+                "q9gent_time_to_first_line_seconds",
+                "Time from spawning claude to its first stdout line",
+            )
+            .buckets(FIRST_LINE_BUCKETS.to_vec()),
+        )
+        .unwrap();
+        let run_duration = HistogramVec::new(
+            HistogramOpts::new(
+                "q9gent_run_duration_seconds",
+                "Time from spawning claude until it exited and its output was drained",
+            )
+            .buckets(RUN_BUCKETS.to_vec()),
+            &["outcome"],
+        )
+        .unwrap();
+        let output_lines = IntCounter::new(
+            "q9gent_output_lines_total",
+            "Stream-json lines read from claude's stdout",
+        )
+        .unwrap();
+        let stderr_lines = IntCounter::new(
+            "q9gent_stderr_lines_total",
+            "Lines read from claude's stderr",
+        )
+        .unwrap();
+        let active_processes = IntGauge::new(
+            "q9gent_active_processes",
+            "Claude processes that have not yet exited and been reported",
+        )
+        .unwrap();
+        let queued_events = IntGauge::new(
+            "q9gent_queued_events",
+            "Run events buffered for clients that have not read them yet",
+        )
+        .unwrap();
+        let sessions = IntGauge::new("q9gent_sessions", "Sessions in the session store").unwrap();
+        let http_request_duration = HistogramVec::new(
+            HistogramOpts::new(
+                "q9gent_http_request_duration_seconds",
+                "Time until the response head is sent, by route; event streams are counted until they start",
+            ),
+            &["method", "route", "status"],
+        )
+        .unwrap();
+
+        let registry = Registry::new();
+        registry.register(Box::new(spawns.clone())).unwrap();
+        registry
+            .register(Box::new(time_to_first_line.clone()))
+            .unwrap();
+        registry.register(Box::new(run_duration.clone())).unwrap();
+        registry.register(Box::new(output_lines.clone())).unwrap();
+        registry.register(Box::new(stderr_lines.clone())).unwrap();
+        registry
+            .register(Box::new(active_processes.clone()))
+            .unwrap();
+        registry.register(Box::new(queued_events.clone())).unwrap();
+        registry.register(Box::new(sessions.clone())).unwrap();
+        registry
+            .register(Box::new(http_request_duration.clone()))
+            .unwrap();
+
+        Self {
+            registry,
+            spawns,
+            time_to_first_line,
+            run_duration,
+            output_lines,
+            stderr_lines,
+            active_processes,
+            queued_events,
+            sessions,
+            http_request_duration,
+            agent_types: Mutex::new(HashSet::new()),
+        }
+    }
+
+    /// Label value for a client-supplied agent type; only the first
+    /// `MAX_AGENT_TYPES` names of `[a-z0-9_-]{1,64}` get series of their own
+    fn agent_type_label(&self, agent_type: &str) -> String {
+        let valid = (1..=64).contains(&agent_type.len())
+            && agent_type
+                .bytes()
+                .all(|b| b.is_ascii_lowercase() || b.is_ascii_digit() || b == b'_' || b == b'-');
+        if valid {
+            let mut agent_types = self.agent_types.lock().unwrap();
+            if agent_types.contains(agent_type) || agent_types.len() < MAX_AGENT_TYPES {
+                agent_types.insert(agent_type.to_string());
+                return agent_type.to_string();
+            }
+        }
+        "other".to_string()
+    }
+
+    /// A process could not be started
+    pub fn spawn_failed(&self, agent_type: &str) {
+        let agent_type = self.agent_type_label(agent_type);
+        self.spawns
+            .with_label_values(&[&agent_type, "spawn_failed"])
+            .inc();
+    }
+
+    /// A process exited with the run status `status`
+    pub fn run_finished(&self, agent_type: &str, status: RunStatus, duration: Duration) {
+        let outcome = outcome(status);
+        let agent_type = self.agent_type_label(agent_type);
+        self.spawns.with_label_values(&[&agent_type, outcome]).inc();
+        self.run_duration
+            .with_label_values(&[outcome])
+            .observe(duration.as_secs_f64());
+    }
+
+    pub fn first_line(&self, elapsed: Duration) {
+        self.time_to_first_line.observe(elapsed.as_secs_f64());
+    }
+
+    pub fn output_line(&self) {
+        self.output_lines.inc();
+    }
+
+    pub fn stderr_line(&self) {
+        self.stderr_lines.inc();
+    }
+
+    /// An HTTP request to `route`, the path pattern it matched, was answered
+    pub fn http_request(&self, method: &str, route: &str, status: u16, elapsed: Duration) {
+        self.http_request_duration
+            .with_label_values(&[method, route, &status.to_string()])
+            .observe(elapsed.as_secs_f64());
+    }
+
+    /// All metrics in the Prometheus text exposition format
+    pub fn render(&self, snapshot: Snapshot) -> String {
+        self.active_processes.set(snapshot.active_processes as i64);
+        self.queued_events.set(snapshot.queued_events as i64);
+        self.sessions.set(snapshot.sessions as i64);
+
+        let mut buffer = Vec::new();
+        TextEncoder::new()
+            .encode(&self.registry.gather(), &mut buffer)
+            .expect("text encoding cannot fail");
+        String::from_utf8(buffer).expect("metrics are UTF-8")
+    }
+}
+
+/// Label of the run status a process ended with
+pub fn outcome(status: RunStatus) -> &'static str {
+    match status {
+        RunStatus::Completed => "completed",
+        RunStatus::Failed => "failed",
+        RunStatus::Terminated => "terminated",
+        RunStatus::Running | RunStatus::Interrupted => "unknown",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_metrics() {
+        let metrics = Metrics::new();
+        metrics.spawn_failed("coder");
+        metrics.run_finished("coder", RunStatus::Completed, Duration::from_secs(3));
+        metrics.first_line(Duration::from_millis(300));
+        metrics.output_line();
+        metrics.output_line();
+        metrics.http_request("POST", "/spawn", 200, Duration::from_millis(5));
+
+        let text = metrics.render(Snapshot {
+            active_processes: 2,
+            queued_events: 7,
+            sessions: 4,
+        });
+        assert!(
+            text.contains(r#"q9gent_spawns_total{agent_type="coder",outcome="spawn_failed"} 1"#)
+        );
+        assert!(text.contains(r#"q9gent_spawns_total{agent_type="coder",outcome="completed"} 1"#));
+        assert!(text.contains(r#"q9gent_run_duration_seconds_count{outcome="completed"} 1"#));
+        assert!(text.contains("q9gent_time_to_first_line_seconds_count 1"));
+        assert!(text.contains("q9gent_output_lines_total 2"));
+        assert!(text.contains("q9gent_stderr_lines_total 0"));
+        assert!(text.contains("q9gent_active_processes 2"));
+        assert!(text.contains("q9gent_queued_events 7"));
+        assert!(text.contains("q9gent_sessions 4"));
+        assert!(text.contains(
+            r#"q9gent_http_request_duration_seconds_count{method="POST",route="/spawn",status="200"} 1"#
+        ));
+    }
+
+    #[test]
+    fn test_agent_type_label_bounded() {
+        let metrics = Metrics::new();
+        assert_eq!(metrics.agent_type_label("code_helper-2"), "code_helper-2");
+        for invalid in ["", "Coder", "a b", "x\"}", &"a".repeat(65)] {
+            assert_eq!(metrics.agent_type_label(invalid), "other");
+        }
+
+        for i in 1..MAX_AGENT_TYPES {
+            metrics.agent_type_label(&format!("type-{}", i));
+        }
+        assert_eq!(metrics.agent_type_label("one-too-many"), "other");
+        assert_eq!(metrics.agent_type_label("code_helper-2"), "code_helper-2");
+    }
+}
@@ -0,0 +1,192 @@
+use regex::Regex;
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+
+use crate::environment;
+use crate::error::{AppError, AppResult};
+use crate::limits::RunLimits;
+
+/// Named run settings selected with `profile` on a spawn request
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Profile {
+    /// Local git repository; each new session gets its own worktree of it
+    pub repo: Option<PathBuf>,
+
+    /// Commit-ish new session branches start from (default: `HEAD`)
+    pub base_ref: Option<String>,
+
+    /// Prefix for session branch names
+    #[serde(default = "default_branch_prefix")]
+    pub branch_prefix: String,
+
+    /// Directory worktrees are created in (default: `<session_dir>/worktrees`)
+    pub worktree_dir: Option<PathBuf>,
+
+    /// Variables set for every run of this profile
+    #[serde(default)]
+    pub env: BTreeMap<String, String>,
+
+    /// Variables callers may set with `env` on a request
+    #[serde(default)]
+    pub request_env: Vec<String>,
+
+    /// Variables read from secret files, relative to `--secrets-dir` unless
+    /// absolute
+    #[serde(default)]
+    pub secrets: BTreeMap<String, PathBuf>,
+
+    /// Directory whose files are each injected as a secret variable named
+    /// after the file (e.g. a mounted secret volume)
+    pub secrets_from_dir: Option<PathBuf>,
+
+    /// Resource limits for this profile's processes (Linux only)
+    #[serde(default)]
+    pub limits: RunLimits,
+
+    /// Extra regexes redacted from this profile's output
+    #[serde(default, deserialize_with = "deserialize_patterns")]
+    pub redact: Vec<Regex>,
+}
+
+fn default_branch_prefix() -> String {
+    "q9gent/".to_string()
+}
+
+pub(crate) fn deserialize_patterns<'de, D>(deserializer: D) -> Result<Vec<Regex>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    Vec::<String>::deserialize(deserializer)?
+        .iter()
+        .map(|pattern| Regex::new(pattern).map_err(serde::de::Error::custom))
+        .collect()
+}
+
+/// Profiles loaded from `--profiles`
+#[derive(Debug, Clone, Default)]
+pub struct Profiles(HashMap<String, Profile>);
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ProfilesFile {
+    #[serde(default)]
+    profiles: HashMap<String, Profile>,
+}
+
+impl Profiles {
+    /// Load profiles from a TOML file with one `[profiles.<name>]` table each
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let text = std::fs::read_to_string(path)?;
+        Self::parse(&text)
+    }
+
+    fn parse(text: &str) -> anyhow::Result<Self> {
+        let file: ProfilesFile = toml::from_str(text)?;
+        for (name, profile) in &file.profiles {
+            if let Some(ref repo) = profile.repo {
+                if !repo.is_absolute() {
+                    anyhow::bail!("profile '{}': repo must be an absolute path", name);
+                }
+            }
+            for key in profile
+                .env
+                .keys()
+                .chain(&profile.request_env)
+                .chain(profile.secrets.keys())
+            {
+                environment::validate_key(key)
+                    .map_err(|e| anyhow::anyhow!("profile '{}': {}", name, e))?;
+            }
+            if let Some(key) = profile
+                .request_env
+                .iter()
+                .find(|key| profile.secrets.contains_key(*key))
+            {
+                anyhow::bail!(
+                    "profile '{}': secret {} cannot also be in request_env",
+                    name,
+                    key
+                );
+            }
+        }
+        Ok(Self(file.profiles))
+    }
+
+    /// Names of profiles that need a per-run cgroup
+    pub fn with_cgroup_limits(&self) -> impl Iterator<Item = &str> {
+        self.0
+            .iter()
+            .filter(|(_, profile)| profile.limits.cgroup.is_some())
+            .map(|(name, _)| name.as_str())
+    }
+
+    /// Look up a profile requested by a caller
+    pub fn get(&self, name: &str) -> AppResult<&Profile> {
+        self.0
+            .get(name)
+            .ok_or_else(|| AppError::UnknownProfile(name.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_profiles() {
+        let profiles = Profiles::parse(
+            r#"
+            [profiles.reviewer]
+
+            [profiles.coder]
+            repo = "/srv/repos/app"
+            base_ref = "main"
+            request_env = ["TICKET"]
+
+            [profiles.coder.env]
+            CI = "true"
+
+            [profiles.coder.secrets]
+            MCP_API_KEY = "mcp_key"
+
+            [profiles.coder.limits]
+            open_files = 1024
+
+            [profiles.coder.limits.cgroup]
+            memory_max_mb = 4096
+            "#,
+        )
+        .unwrap();
+
+        let coder = profiles.get("coder").unwrap();
+        assert_eq!(coder.repo.as_deref(), Some(Path::new("/srv/repos/app")));
+        assert_eq!(coder.branch_prefix, "q9gent/");
+        assert_eq!(coder.env["CI"], "true");
+        assert_eq!(coder.request_env, vec!["TICKET"]);
+        assert_eq!(coder.secrets["MCP_API_KEY"], Path::new("mcp_key"));
+        assert_eq!(coder.limits.open_files, Some(1024));
+        assert_eq!(
+            coder.limits.cgroup.as_ref().unwrap().memory_max_mb,
+            Some(4096)
+        );
+        assert!(profiles.get("reviewer").unwrap().repo.is_none());
+        assert!(matches!(
+            profiles.get("missing"),
+            Err(AppError::UnknownProfile(_))
+        ));
+    }
+
+    #[test]
+    fn test_invalid_profiles_rejected() {
+        assert!(Profiles::parse("[profiles.a]\nrepo = \"relative/path\"").is_err());
+        assert!(Profiles::parse("[profiles.a]\nunknown = 1").is_err());
+        assert!(Profiles::parse("[profiles.a]\nrequest_env = [\"A=B\"]").is_err());
+        assert!(Profiles::parse("[profiles.a]\nredact = [\"(unclosed\"]").is_err());
+        assert!(Profiles::parse(
+            "[profiles.a]\nrequest_env = [\"KEY\"]\n[profiles.a.secrets]\nKEY = \"key\""
+        )
+        .is_err());
+    }
+}
@@ -0,0 +1,265 @@
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime};
+use tokio::sync::Mutex;
+use tokio_util::sync::CancellationToken;
+use tracing::{error, info, warn};
+
+use crate::config::{
+    ListenerConfig, LoggingConfig, Options, ServerConfig, Settings, SharedConfig, TelemetryConfig,
+};
+
+/// How often the files the configuration was read from are checked for
+/// changes
+const WATCH_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Settings fixed when the server starts; a reload that changes them keeps
+/// the running values and reports the keys
+struct StartupSettings {
+    listeners: ListenerConfig,
+    log_filter: Option<String>,
+    logging: LoggingConfig,
+    telemetry: TelemetryConfig,
+}
+
+/// Reloads the configuration from the config file, profiles and tokens
+/// files, with the command-line and environment overrides the server started
+/// with
+pub struct Reloader {
+    options: Options,
+    config: SharedConfig,
+    startup: StartupSettings,
+
+    /// Files to watch, as of the last successful load
+    files: std::sync::Mutex<Vec<PathBuf>>,
+
+    /// Serializes reloads so two triggers cannot interleave
+    lock: Mutex<()>,
+}
+
+/// What a successful reload changed
+#[derive(Debug, Default)]
+pub struct ReloadOutcome {
+    /// Changed keys that only take effect after a restart
+    pub restart_required: Vec<&'static str>,
+}
+
+impl Reloader {
+    pub fn new(options: Options, settings: &Settings, config: SharedConfig) -> Self {
+        Self {
+            options,
+            config,
+            startup: StartupSettings {
+                listeners: settings.listeners.clone(),
+                log_filter: settings.log_filter.clone(),
+                logging: settings.logging.clone(),
+                telemetry: settings.telemetry.clone(),
+            },
+            files: std::sync::Mutex::new(settings.files.clone()),
+            lock: Mutex::new(()),
+        }
+    }
+
+    /// Load and validate the configuration again and make it active
+    ///
+    /// On error the current configuration stays active. Runs already started
+    /// keep the configuration they started with either way.
+    pub async fn reload(&self) -> anyhow::Result<ReloadOutcome> {
+        let _guard = self.lock.lock().await;
+        let options = self.options.clone();
+        let result = tokio::task::spawn_blocking(move || options.load()).await?;
+        let settings = match result {
+            Ok(settings) => settings,
+            Err(e) => {
+                error!(
+                    "❌ Reload failed, keeping the current configuration: {:#}",
+                    e
+                );
+                return Err(e);
+            }
+        };
+
+        let mut restart_required = Vec::new();
+        if settings.listeners != self.startup.listeners {
+            restart_required.push("listener");
+        }
+        if settings.log_filter != self.startup.log_filter {
+            restart_required.push("logging.level");
+        }
+        if settings.logging != self.startup.logging {
+            restart_required.push("logging");
+        }
+        if settings.telemetry != self.startup.telemetry {
+            restart_required.push("telemetry");
+        }
+        let mut server = settings.server;
+        restart_required.extend(keep_startup_values(&self.config.current(), &mut server));
+
+        *self.files.lock().unwrap() = settings.files;
+        self.config.replace(server);
+        if restart_required.is_empty() {
+            info!("🔄 Configuration reloaded");
+        } else {
+            warn!(
+                "🔄 Configuration reloaded; changes to {} take effect after a restart",
+                restart_required.join(", ")
+            );
+        }
+        Ok(ReloadOutcome { restart_required })
+    }
+
+    /// Reload on SIGHUP and whenever a watched file changes, until `stop` is
+    /// cancelled
+    pub async fn watch(&self, stop: CancellationToken) {
+        let mut hangup = hangup_signal();
+        let mut seen = self.fingerprint();
+        let mut interval = tokio::time::interval(WATCH_INTERVAL);
+        interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        loop {
+            tokio::select! {
+                _ = stop.cancelled() => return,
+                _ = recv_hangup(&mut hangup) => {
+                    info!("🔄 SIGHUP received, reloading configuration");
+                }
+                _ = interval.tick() => {
+                    let current = self.fingerprint();
+                    if current == seen {
+                        continue;
+                    }
+                    info!("🔄 Configuration files changed, reloading");
+                }
+            }
+            // Failures are logged and leave the old configuration active
+            let _ = self.reload().await;
+            seen = self.fingerprint();
+        }
+    }
+
+    /// Modification time and size of every watched file
+    fn fingerprint(&self) -> Vec<Option<(SystemTime, u64)>> {
+        self.files
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|path| {
+                let metadata = std::fs::metadata(path).ok()?;
+                Some((metadata.modified().ok()?, metadata.len()))
+            })
+            .collect()
+    }
+}
+
+/// Carry over settings the running server cannot change and return the
+/// config keys that differ
+fn keep_startup_values(running: &ServerConfig, new: &mut ServerConfig) -> Vec<&'static str> {
+    let mut changed = Vec::new();
+    macro_rules! keep {
+        ($field:ident, $key:literal) => {
+            if new.$field != running.$field {
+                changed.push($key);
+                new.$field = running.$field.clone();
+            }
+        };
+    }
+    keep!(session_dir, "sessions.dir");
+    keep!(claude_path, "runner.claude_path");
+    keep!(claude_version_check, "runner.claude_version_check");
+    // Found at startup, not configured
+    new.claude = running.claude.clone();
+    keep!(event_buffer, "runner.event_buffer");
+    keep!(kill_grace_period, "runner.kill_grace_period_secs");
+    keep!(cgroup_root, "limits.cgroup_root");
+    keep!(shutdown_timeout, "listener.shutdown_timeout_secs");
+    keep!(max_request_body, "limits.max_request_body_bytes");
+    keep!(cors, "cors.allowed_origins");
+    keep!(audit, "audit");
+    if new.redaction.transcript_dir != running.redaction.transcript_dir {
+        changed.push("runner.transcript_dir");
+        new.redaction.transcript_dir = running.redaction.transcript_dir.clone();
+    }
+    // The runner is built with its cassette settings at startup
+    let cassettes = &running.cassettes;
+    if new.cassettes.mode != cassettes.mode {
+        changed.push("runner.cassette_mode");
+    }
+    if new.cassettes.dir != cassettes.dir {
+        changed.push("runner.cassette_dir");
+    }
+    if new.cassettes.replay_speed != cassettes.replay_speed {
+        changed.push("runner.replay_speed");
+    }
+    new.cassettes = cassettes.clone();
+    changed
+}
+
+#[cfg(unix)]
+type Hangup = Option<tokio::signal::unix::Signal>;
+
+#[cfg(unix)]
+fn hangup_signal() -> Hangup {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    signal(SignalKind::hangup())
+        .map_err(|e| warn!("⚠️  Failed to install SIGHUP handler: {}", e))
+        .ok()
+}
+
+#[cfg(unix)]
+async fn recv_hangup(hangup: &mut Hangup) {
+    match hangup {
+        Some(signal) => {
+            signal.recv().await;
+        }
+        None => std::future::pending().await,
+    }
+}
+
+#[cfg(not(unix))]
+type Hangup = ();
+
+#[cfg(not(unix))]
+fn hangup_signal() -> Hangup {}
+
+#[cfg(not(unix))]
+async fn recv_hangup(_hangup: &mut Hangup) {
+    std::future::pending().await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_reload_swaps_config_and_keeps_it_on_error() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let path = temp_dir.path().join("q9gent.toml");
+        let write = |text: &str| std::fs::write(&path, text).unwrap();
+        write("[runner]\nenv_passthrough = [\"FOO\"]\n");
+
+        let options = Options {
+            config: Some(path.clone()),
+            ..Default::default()
+        };
+        let settings = options.load().unwrap();
+        let shared = SharedConfig::new(settings.server.clone());
+        let reloader = Reloader::new(options, &settings, shared.clone());
+        let started_with = shared.current();
+
+        write("[runner]\nenv_passthrough = [\"BAR\"]\nclaude_path = \"/opt/claude\"\n");
+        let outcome = reloader.reload().await.unwrap();
+        assert_eq!(outcome.restart_required, vec!["runner.claude_path"]);
+        let reloaded = shared.current();
+        assert!(reloaded.env.passthrough.contains(&"BAR".to_string()));
+        assert_eq!(reloaded.claude_path, "claude");
+        // A snapshot taken before the reload is unchanged
+        assert!(started_with.env.passthrough.contains(&"FOO".to_string()));
+
+        write("[runner]\nenv_passthrough = [\"A=B\"]\n");
+        let error = format!("{:#}", reloader.reload().await.unwrap_err());
+        assert!(error.contains("runner.env_passthrough"), "{}", error);
+        assert!(shared
+            .current()
+            .env
+            .passthrough
+            .contains(&"BAR".to_string()));
+    }
+}
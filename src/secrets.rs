@@ -0,0 +1,129 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use tracing::{debug, warn};
+
+use crate::environment;
+use crate::error::{AppError, AppResult};
+use crate::profile::Profile;
+
+/// Read the secrets a profile injects, keyed by environment variable name
+///
+/// Files named in `secrets` are resolved against `secrets_dir` when relative.
+/// Every regular, non-hidden file in the profile's `secrets_from_dir` becomes a
+/// variable named after the file; files whose name is not a valid variable
+/// name are skipped. Files are read on every run so rotated
+/// secrets are picked up; a single trailing newline is stripped.
+pub async fn load(
+    profile: &Profile,
+    secrets_dir: Option<&Path>,
+) -> AppResult<BTreeMap<String, String>> {
+    let mut secrets = BTreeMap::new();
+
+    if let Some(ref dir) = profile.secrets_from_dir {
+        let mut entries = tokio::fs::read_dir(dir).await.map_err(|e| {
+            warn!(
+                "⚠️  Failed to read secrets directory {}: {}",
+                dir.display(),
+                e
+            );
+            AppError::SecretUnavailable("secrets_from_dir".to_string())
+        })?;
+        while let Some(entry) = entries.next_entry().await? {
+            let name = entry.file_name().to_string_lossy().to_string();
+            // Skips mount bookkeeping such as Kubernetes' `..data`
+            if name.starts_with('.') || !tokio::fs::metadata(entry.path()).await?.is_file() {
+                continue;
+            }
+            if environment::validate_key(&name).is_err() {
+                warn!(
+                    "⚠️  Skipping secret file {:?} in {}: not a valid environment variable name",
+                    name,
+                    dir.display()
+                );
+                continue;
+            }
+            secrets.insert(name.clone(), read(&name, &entry.path()).await?);
+        }
+    }
+
+    for (name, file) in &profile.secrets {
+        let path = resolve(name, file, secrets_dir)?;
+        secrets.insert(name.clone(), read(name, &path).await?);
+    }
+
+    debug!(
+        "🔐 Loaded secrets: {:?}",
+        secrets.keys().collect::<Vec<_>>()
+    );
+    Ok(secrets)
+}
+
+/// Locate a secret file named in a profile
+fn resolve(name: &str, file: &Path, secrets_dir: Option<&Path>) -> AppResult<PathBuf> {
+    if file.is_absolute() {
+        return Ok(file.to_path_buf());
+    }
+    match secrets_dir {
+        Some(dir) => Ok(dir.join(file)),
+        None => {
+            warn!(
+                "⚠️  Secret {} uses a relative path but no --secrets-dir is set",
+                name
+            );
+            Err(AppError::SecretUnavailable(name.to_string()))
+        }
+    }
+}
+
+/// Read one secret; the error names the secret but never its path or content
+async fn read(name: &str, path: &Path) -> AppResult<String> {
+    let mut value = tokio::fs::read_to_string(path).await.map_err(|e| {
+        warn!(
+            "⚠️  Failed to read secret {} from {}: {}",
+            name,
+            path.display(),
+            e
+        );
+        AppError::SecretUnavailable(name.to_string())
+    })?;
+    if value.ends_with('\n') {
+        value.pop();
+        if value.ends_with('\r') {
+            value.pop();
+        }
+    }
+    Ok(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_load_files_and_directory() {
+        let secrets_dir = tempfile::tempdir().unwrap();
+        let mounted = tempfile::tempdir().unwrap();
+        std::fs::write(secrets_dir.path().join("mcp_key"), "key-123\n").unwrap();
+        std::fs::write(mounted.path().join("GITHUB_TOKEN"), "ghp_abc").unwrap();
+        std::fs::write(mounted.path().join(".hidden"), "ignored").unwrap();
+        std::fs::write(mounted.path().join("BAD=NAME"), "ignored").unwrap();
+
+        let profile = Profile {
+            secrets: BTreeMap::from([("MCP_API_KEY".to_string(), PathBuf::from("mcp_key"))]),
+            secrets_from_dir: Some(mounted.path().to_path_buf()),
+            ..Default::default()
+        };
+        let secrets = load(&profile, Some(secrets_dir.path())).await.unwrap();
+
+        assert_eq!(secrets["MCP_API_KEY"], "key-123");
+        assert_eq!(secrets["GITHUB_TOKEN"], "ghp_abc");
+        assert!(!secrets.contains_key(".hidden"));
+        assert!(!secrets.contains_key("BAD=NAME"));
+
+        // Relative paths need --secrets-dir
+        assert!(matches!(
+            load(&profile, None).await,
+            Err(AppError::SecretUnavailable(name)) if name == "MCP_API_KEY"
+        ));
+    }
+}
@@ -1,17 +1,20 @@
-use serde::{Deserialize, Serialize};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use tokio::fs;
+use tokio::io::AsyncWriteExt;
 use uuid::Uuid;
 
 use crate::error::{AppError, AppResult};
 
-/// Minimal session metadata
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct SessionMetadata {
-    pub session_id: String,
-    pub agent_type: String,
-    pub created_at: u64,
-    pub last_used: u64,
+pub use q9gent_client::types::{SessionMetadata, SessionWorktree};
+
+/// Where a new session runs, recorded so resumed turns run in the same place
+#[derive(Debug, Clone, Default)]
+pub struct SessionPlacement {
+    pub profile: Option<String>,
+    pub workdir: Option<PathBuf>,
+    pub worktree: Option<SessionWorktree>,
+    /// `workdir` is a temporary workspace the session owns
+    pub temp_workspace: bool,
 }
 
 /// Session store for persisting minimal metadata
@@ -26,23 +29,55 @@ impl SessionStore {
         }
     }
 
-    fn session_path(&self, session_id: &str) -> PathBuf {
-        self.base_dir.join(format!("{}.json", session_id))
+    /// Path of a session's metadata file. Session IDs come from request paths,
+    /// so anything that could escape `base_dir` is rejected.
+    fn session_path(&self, session_id: &str) -> AppResult<PathBuf> {
+        let valid = !session_id.is_empty()
+            && session_id.len() <= 128
+            && session_id
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+        if !valid {
+            return Err(AppError::InvalidRequest(format!(
+                "invalid session ID '{}'",
+                session_id
+            )));
+        }
+        Ok(self.base_dir.join(format!("{}.json", session_id)))
     }
 
-    /// Create a new session and persist metadata
-    pub async fn create_session(&self, agent_type: String) -> AppResult<SessionMetadata> {
-        let session_id = Uuid::new_v4().to_string();
+    /// Check that metadata can be stored by writing and removing a probe file
+    pub async fn check_writable(&self) -> std::io::Result<()> {
+        let probe = self.base_dir.join(format!(".ready-{}", Uuid::new_v4()));
+        fs::write(&probe, b"").await?;
+        fs::remove_file(&probe).await
+    }
+
+    /// Persist metadata for a session created by Claude under its own
+    /// session_id, along with where it must be resumed
+    pub async fn record_session(
+        &self,
+        session_id: &str,
+        agent_type: &str,
+        placement: &SessionPlacement,
+    ) -> AppResult<SessionMetadata> {
         let now = std::time::SystemTime::now()
             .duration_since(std::time::UNIX_EPOCH)
             .unwrap()
             .as_secs();
 
         let metadata = SessionMetadata {
-            session_id: session_id.clone(),
-            agent_type,
+            session_id: session_id.to_string(),
+            agent_type: agent_type.to_string(),
             created_at: now,
             last_used: now,
+            workdir: placement
+                .workdir
+                .as_ref()
+                .map(|path| path.to_string_lossy().to_string()),
+            profile: placement.profile.clone(),
+            worktree: placement.worktree.clone(),
+            temp_workspace: placement.temp_workspace,
         };
 
         self.save_session(&metadata).await?;
@@ -50,16 +85,37 @@ impl SessionStore {
     }
 
     /// Save session metadata to disk
+    ///
+    /// The file is replaced atomically, so a shutdown or crash mid-write never
+    /// leaves truncated metadata behind.
     pub async fn save_session(&self, metadata: &SessionMetadata) -> AppResult<()> {
-        let path = self.session_path(&metadata.session_id);
+        let path = self.session_path(&metadata.session_id)?;
         let json = serde_json::to_string_pretty(metadata)?;
-        fs::write(path, json).await?;
+        write_atomic(&path, json.as_bytes()).await?;
         Ok(())
     }
 
+    /// Make completed metadata writes durable before the server exits
+    pub async fn sync(&self) {
+        #[cfg(unix)]
+        {
+            let synced = match fs::File::open(&self.base_dir).await {
+                Ok(dir) => dir.sync_all().await,
+                Err(e) => Err(e),
+            };
+            if let Err(e) = synced {
+                tracing::warn!(
+                    "⚠️  Failed to sync session directory {}: {}",
+                    self.base_dir.display(),
+                    e
+                );
+            }
+        }
+    }
+
     /// Load session metadata from disk
     pub async fn load_session(&self, session_id: &str) -> AppResult<SessionMetadata> {
-        let path = self.session_path(session_id);
+        let path = self.session_path(session_id)?;
 
         if !path.exists() {
             return Err(AppError::SessionNotFound(session_id.to_string()));
@@ -70,17 +126,60 @@ impl SessionStore {
         Ok(metadata)
     }
 
-    /// Update the last_used timestamp
-    pub async fn touch_session(&self, session_id: &str) -> AppResult<()> {
+    /// Number of stored sessions
+    pub async fn count(&self) -> AppResult<usize> {
+        let mut count = 0;
+        let mut entries = fs::read_dir(&self.base_dir).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            if entry.file_name().to_string_lossy().ends_with(".json") {
+                count += 1;
+            }
+        }
+        Ok(count)
+    }
+
+    /// Update the last_used timestamp and return the updated metadata
+    pub async fn touch_session(&self, session_id: &str) -> AppResult<SessionMetadata> {
         let mut metadata = self.load_session(session_id).await?;
         metadata.last_used = std::time::SystemTime::now()
             .duration_since(std::time::UNIX_EPOCH)
             .unwrap()
             .as_secs();
         self.save_session(&metadata).await?;
-        Ok(())
+        Ok(metadata)
+    }
+
+    /// Delete a session's metadata file
+    pub async fn delete_session(&self, session_id: &str) -> AppResult<()> {
+        let path = self.session_path(session_id)?;
+        match fs::remove_file(path).await {
+            Ok(()) => Ok(()),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                Err(AppError::SessionNotFound(session_id.to_string()))
+            }
+            Err(e) => Err(e.into()),
+        }
     }
 }
 
+/// Replace `path` with `contents` through a synced temporary file, so readers
+/// never see a partially written file
+pub async fn write_atomic(path: &Path, contents: &[u8]) -> std::io::Result<()> {
+    let tmp = path.with_extension("json.tmp");
+    let mut file = fs::File::create(&tmp).await?;
+    file.write_all(contents).await?;
+    file.sync_all().await?;
+    fs::rename(&tmp, path).await
+}
+
+/// Extract Claude's session_id from a stream-json output line
+pub fn extract_session_id(line: &str) -> Option<String> {
+    let parsed: serde_json::Value = serde_json::from_str(line).ok()?;
+    parsed
+        .get("session_id")
+        .and_then(|v| v.as_str())
+        .map(str::to_string)
+}
+
 #[cfg(test)]
 mod tests;
@@ -1,17 +1,18 @@
 use super::*;
 
 #[tokio::test]
-async fn test_session_store_create() {
+async fn test_session_store_record() {
     let temp_dir = tempfile::tempdir().unwrap();
     let store = SessionStore::new(temp_dir.path());
 
     let metadata = store
-        .create_session("test_agent".to_string())
+        .record_session("test-123", "test_agent", &SessionPlacement::default())
         .await
         .unwrap();
 
     assert_eq!(metadata.agent_type, "test_agent");
-    assert!(!metadata.session_id.is_empty());
+    assert_eq!(metadata.session_id, "test-123");
+    assert!(store.load_session("test-123").await.is_ok());
     assert!(metadata.created_at > 0);
     assert_eq!(metadata.created_at, metadata.last_used);
 }
@@ -26,6 +27,10 @@ async fn test_session_store_save_load() {
         agent_type: "test".to_string(),
         created_at: 1000,
         last_used: 2000,
+        workdir: None,
+        profile: None,
+        worktree: None,
+        temp_workspace: false,
     };
 
     store.save_session(&metadata).await.unwrap();
@@ -35,6 +40,13 @@ async fn test_session_store_save_load() {
     assert_eq!(loaded.agent_type, metadata.agent_type);
     assert_eq!(loaded.created_at, metadata.created_at);
     assert_eq!(loaded.last_used, metadata.last_used);
+
+    // The temporary file is renamed into place
+    let files: Vec<_> = std::fs::read_dir(temp_dir.path())
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name())
+        .collect();
+    assert_eq!(files, vec!["test-123.json"]);
 }
 
 #[tokio::test]
@@ -42,7 +54,10 @@ async fn test_session_store_touch() {
     let temp_dir = tempfile::tempdir().unwrap();
     let store = SessionStore::new(temp_dir.path());
 
-    let metadata = store.create_session("test".to_string()).await.unwrap();
+    let metadata = store
+        .record_session("test-123", "test", &SessionPlacement::default())
+        .await
+        .unwrap();
     let original_last_used = metadata.last_used;
 
     // Wait to ensure timestamp changes (timestamps are in seconds)
@@ -62,3 +77,58 @@ async fn test_session_not_found() {
     let result = store.load_session("nonexistent").await;
     assert!(matches!(result, Err(AppError::SessionNotFound(_))));
 }
+
+#[tokio::test]
+async fn test_record_session() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let store = SessionStore::new(temp_dir.path());
+
+    let placement = SessionPlacement {
+        profile: Some("review".to_string()),
+        workdir: Some(temp_dir.path().to_path_buf()),
+        worktree: None,
+        temp_workspace: false,
+    };
+    store
+        .record_session("claude-abc", "reviewer", &placement)
+        .await
+        .unwrap();
+    let loaded = store.load_session("claude-abc").await.unwrap();
+
+    assert_eq!(loaded.agent_type, "reviewer");
+    assert_eq!(loaded.created_at, loaded.last_used);
+    assert_eq!(
+        loaded.workdir.as_deref(),
+        Some(temp_dir.path().to_string_lossy().as_ref())
+    );
+    assert_eq!(loaded.profile.as_deref(), Some("review"));
+
+    store.delete_session("claude-abc").await.unwrap();
+    assert!(matches!(
+        store.load_session("claude-abc").await,
+        Err(AppError::SessionNotFound(_))
+    ));
+    assert!(matches!(
+        store.delete_session("claude-abc").await,
+        Err(AppError::SessionNotFound(_))
+    ));
+}
+
+#[test]
+fn test_extract_session_id() {
+    let line = r#"{"type":"system","subtype":"init","session_id":"abc-123"}"#;
+    assert_eq!(extract_session_id(line), Some("abc-123".to_string()));
+    assert_eq!(extract_session_id(r#"{"type":"system"}"#), None);
+    assert_eq!(extract_session_id("not json"), None);
+}
+
+#[tokio::test]
+async fn test_invalid_session_id_rejected() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let store = SessionStore::new(temp_dir.path());
+
+    for id in ["../escape", "a/b", "", "x.json"] {
+        let result = store.load_session(id).await;
+        assert!(matches!(result, Err(AppError::InvalidRequest(_))), "{}", id);
+    }
+}
@@ -0,0 +1,69 @@
+use std::time::Duration;
+use tokio_util::sync::CancellationToken;
+
+/// Shutdown state shared by the listeners, handlers and event streams
+#[derive(Clone)]
+pub struct Shutdown {
+    token: CancellationToken,
+    drain_timeout: Duration,
+}
+
+impl Shutdown {
+    /// `drain_timeout` is how long running processes may keep going once
+    /// shutdown has started
+    pub fn new(drain_timeout: Duration) -> Self {
+        Self {
+            token: CancellationToken::new(),
+            drain_timeout,
+        }
+    }
+
+    pub fn drain_timeout(&self) -> Duration {
+        self.drain_timeout
+    }
+
+    /// Stop accepting connections and runs
+    pub fn start(&self) {
+        self.token.cancel();
+    }
+
+    pub fn is_started(&self) -> bool {
+        self.token.is_cancelled()
+    }
+
+    /// Resolves once shutdown has started
+    pub async fn started(&self) {
+        self.token.cancelled().await
+    }
+
+    /// Token cancelled when shutdown starts
+    pub fn token(&self) -> CancellationToken {
+        self.token.clone()
+    }
+}
+
+/// Wait for SIGINT (Ctrl-C) or, on Unix, SIGTERM and return its name
+pub async fn signal() -> &'static str {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        match signal(SignalKind::terminate()) {
+            Ok(mut sigterm) => tokio::select! {
+                _ = tokio::signal::ctrl_c() => "SIGINT",
+                _ = sigterm.recv() => "SIGTERM",
+            },
+            Err(e) => {
+                tracing::warn!("⚠️  Failed to install SIGTERM handler: {}", e);
+                let _ = tokio::signal::ctrl_c().await;
+                "SIGINT"
+            }
+        }
+    }
+
+    #[cfg(not(unix))]
+    {
+        let _ = tokio::signal::ctrl_c().await;
+        "Ctrl-C"
+    }
+}
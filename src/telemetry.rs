@@ -0,0 +1,213 @@
+use axum::http::Request;
+use opentelemetry_http::HeaderExtractor;
+use opentelemetry_otlp::WithExportConfig;
+use opentelemetry_proto::tonic::collector::trace::v1::ExportTraceServiceRequest;
+use opentelemetry_proto::transform::common::tonic::ResourceAttributesWithSchema;
+use opentelemetry_proto::transform::trace::tonic::group_spans_by_resource_and_scope;
+use opentelemetry_sdk::error::{OTelSdkError, OTelSdkResult};
+use opentelemetry_sdk::propagation::TraceContextPropagator;
+use opentelemetry_sdk::trace::{SdkTracerProvider, SpanData, SpanExporter};
+use opentelemetry_sdk::Resource;
+use std::collections::HashMap;
+use std::io::Write;
+use std::path::Path;
+use tracing::{field, Level, Metadata, Span};
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+use crate::config::TelemetryConfig;
+
+/// Set up W3C trace context propagation and a tracer provider exporting to
+/// the configured OTLP endpoint and file
+///
+/// The provider must be shut down before the process exits so buffered
+/// spans are exported.
+pub fn init(config: &TelemetryConfig) -> anyhow::Result<SdkTracerProvider> {
+    opentelemetry::global::set_text_map_propagator(TraceContextPropagator::new());
+
+    let resource = Resource::builder().with_service_name("q9gent").build();
+    let mut provider = SdkTracerProvider::builder().with_resource(resource.clone());
+    if let Some(ref endpoint) = config.otlp_endpoint {
+        let exporter = opentelemetry_otlp::SpanExporter::builder()
+            .with_http()
+            .with_endpoint(traces_url(endpoint))
+            .build()
+            .map_err(|e| anyhow::anyhow!("telemetry.otlp_endpoint: {}", e))?;
+        provider = provider.with_batch_exporter(exporter);
+    }
+    if let Some(ref path) = config.otlp_file {
+        let exporter = FileExporter::create(path, &resource)
+            .map_err(|e| anyhow::anyhow!("telemetry.otlp_file: {}: {}", path.display(), e))?;
+        provider = provider.with_batch_exporter(exporter);
+    }
+    Ok(provider.build())
+}
+
+/// Collector URL for spans; a bare collector address gets the OTLP/HTTP
+/// traces path
+fn traces_url(endpoint: &str) -> String {
+    let endpoint = endpoint.trim_end_matches('/');
+    let has_path = endpoint
+        .split_once("://")
+        .is_some_and(|(_, rest)| rest.contains('/'));
+    if has_path {
+        endpoint.to_string()
+    } else {
+        format!("{}/v1/traces", endpoint)
+    }
+}
+
+/// Which spans and events become part of exported traces: Q9gent's own spans
+/// and its warnings and errors, as span events
+pub fn exported(metadata: &Metadata<'_>) -> bool {
+    metadata.target().starts_with("q9gent")
+        && (metadata.is_span() || *metadata.level() <= Level::WARN)
+}
+
+/// Span of one HTTP request, continuing the caller's trace when the request
+/// carries a W3C `traceparent` header
+///
+/// Handlers record the agent type, session and run IDs and the PID of the
+/// process once they know them.
+pub fn request_span<B>(request: &Request<B>) -> Span {
+    let span = tracing::info_span!(
+        "http_request",
+        otel.kind = "server",
+        http.request.method = %request.method(),
+        url.path = request.uri().path(),
+        request_id = field::Empty,
+        agent_type = field::Empty,
+        session_id = field::Empty,
+        run_id = field::Empty,
+        pid = field::Empty,
+    );
+    let parent = opentelemetry::global::get_text_map_propagator(|propagator| {
+        propagator.extract(&HeaderExtractor(request.headers()))
+    });
+    // Fails only when the span is disabled, in which case nothing is exported
+    let _ = span.set_parent(parent);
+    span
+}
+
+/// `TRACEPARENT` and `TRACESTATE` variables continuing the trace of `span` in
+/// a child process
+pub fn trace_env(span: &Span) -> Vec<(String, String)> {
+    let mut carrier = HashMap::new();
+    opentelemetry::global::get_text_map_propagator(|propagator| {
+        propagator.inject_context(&span.context(), &mut carrier)
+    });
+    carrier
+        .into_iter()
+        .map(|(key, value)| (key.to_uppercase(), value))
+        .collect()
+}
+
+/// Appends spans to a file as OTLP/JSON, one `ExportTraceServiceRequest` per
+/// line, as read by the collector's `otlpjsonfile` receiver
+#[derive(Debug)]
+struct FileExporter {
+    file: std::sync::Mutex<std::fs::File>,
+    resource: ResourceAttributesWithSchema,
+}
+
+impl FileExporter {
+    fn create(path: &Path, resource: &Resource) -> std::io::Result<Self> {
+        let file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)?;
+        Ok(Self {
+            file: std::sync::Mutex::new(file),
+            resource: resource.into(),
+        })
+    }
+}
+
+impl SpanExporter for FileExporter {
+    async fn export(&self, batch: Vec<SpanData>) -> OTelSdkResult {
+        let request = ExportTraceServiceRequest {
+            resource_spans: group_spans_by_resource_and_scope(batch, &self.resource),
+        };
+        let mut line = serde_json::to_vec(&request)
+            .map_err(|e| OTelSdkError::InternalFailure(e.to_string()))?;
+        line.push(b'\n');
+        self.file
+            .lock()
+            .unwrap()
+            .write_all(&line)
+            .map_err(|e| OTelSdkError::InternalFailure(e.to_string()))
+    }
+
+    fn set_resource(&mut self, resource: &Resource) {
+        self.resource = resource.into();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use opentelemetry::trace::TracerProvider;
+    use tracing_subscriber::layer::SubscriberExt;
+
+    #[test]
+    fn test_traces_url() {
+        assert_eq!(
+            traces_url("http://localhost:4318"),
+            "http://localhost:4318/v1/traces"
+        );
+        assert_eq!(
+            traces_url("https://collector/"),
+            "https://collector/v1/traces"
+        );
+        assert_eq!(
+            traces_url("https://collector/custom/traces"),
+            "https://collector/custom/traces"
+        );
+    }
+
+    #[test]
+    fn test_trace_propagation_and_file_export() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let path = temp_dir.path().join("spans.jsonl");
+        let provider = init(&TelemetryConfig {
+            otlp_endpoint: None,
+            otlp_file: Some(path.clone()),
+        })
+        .unwrap();
+        let subscriber = tracing_subscriber::registry()
+            .with(tracing_opentelemetry::layer().with_tracer(provider.tracer("q9gent")));
+
+        let trace_id = "4bf92f3577b34da6a3ce929d0e0e4736";
+        let env = tracing::subscriber::with_default(subscriber, || {
+            let request = Request::get("/spawn")
+                .header(
+                    "traceparent",
+                    format!("00-{}-00f067aa0ba902b7-01", trace_id),
+                )
+                .body(())
+                .unwrap();
+            let span = request_span(&request);
+            span.record("agent_type", "coder");
+            let _entered = span.enter();
+            trace_env(&Span::current())
+        });
+
+        let traceparent = env
+            .iter()
+            .find(|(key, _)| key == "TRACEPARENT")
+            .map(|(_, value)| value.as_str())
+            .unwrap();
+        assert!(traceparent.starts_with(&format!("00-{}-", trace_id)));
+        assert!(!traceparent.contains("00f067aa0ba902b7"));
+
+        provider.force_flush().unwrap();
+        let exported = std::fs::read_to_string(&path).unwrap();
+        assert!(
+            exported.contains("\"name\":\"http_request\""),
+            "{}",
+            exported
+        );
+        assert!(exported.contains(trace_id));
+        assert!(exported.contains("coder"));
+        provider.shutdown().unwrap();
+    }
+}
@@ -0,0 +1,96 @@
+use std::path::{Path, PathBuf};
+use tokio::io::{AsyncWriteExt, BufWriter};
+use tokio::sync::Mutex;
+use tracing::{info, warn};
+use uuid::Uuid;
+
+/// On-disk JSONL record of a run's output before redaction rules are applied
+///
+/// Each line is `{"stream": "stdout"|"stderr", "line": "..."}`. Injected
+/// secret values are already replaced. Transcripts are only written to disk,
+/// never returned through the API.
+pub struct Transcript {
+    path: PathBuf,
+    file: Mutex<BufWriter<tokio::fs::File>>,
+}
+
+impl Transcript {
+    /// Create a new transcript file in `dir`, readable only by the server user
+    pub async fn create(dir: &Path) -> std::io::Result<Self> {
+        tokio::fs::create_dir_all(dir).await?;
+        let path = dir.join(format!("{}.jsonl", Uuid::new_v4()));
+
+        let mut options = tokio::fs::OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        options.mode(0o600);
+        let file = options.open(&path).await?;
+
+        Ok(Self {
+            path,
+            file: Mutex::new(BufWriter::new(file)),
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Append one line of output
+    pub async fn record(&self, stream: &str, line: &str) {
+        let mut record = serde_json::json!({ "stream": stream, "line": line }).to_string();
+        record.push('\n');
+        if let Err(e) = self.file.lock().await.write_all(record.as_bytes()).await {
+            warn!(
+                "⚠️  Failed to write transcript {}: {}",
+                self.path.display(),
+                e
+            );
+        }
+    }
+
+    /// Flush buffered lines once the run has finished
+    pub async fn finish(&self) {
+        match self.file.lock().await.flush().await {
+            Ok(()) => info!("📝 Transcript written to {}", self.path.display()),
+            Err(e) => warn!(
+                "⚠️  Failed to flush transcript {}: {}",
+                self.path.display(),
+                e
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_transcript_records_lines() {
+        let dir = tempfile::tempdir().unwrap();
+        let transcript = Transcript::create(dir.path()).await.unwrap();
+        transcript.record("stdout", "{\"type\":\"system\"}").await;
+        transcript.record("stderr", "warning").await;
+        transcript.finish().await;
+
+        let text = std::fs::read_to_string(transcript.path()).unwrap();
+        let lines: Vec<serde_json::Value> = text
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0]["line"], "{\"type\":\"system\"}");
+        assert_eq!(lines[1]["stream"], "stderr");
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = std::fs::metadata(transcript.path())
+                .unwrap()
+                .permissions()
+                .mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+    }
+}
@@ -0,0 +1,410 @@
+use std::io;
+use std::path::{Path, PathBuf};
+use tracing::{debug, info, warn};
+use uuid::Uuid;
+
+use q9gent_client::types::SessionWorktree;
+
+use crate::config::{ServerConfig, WorkspaceConfig, WorkspaceRetention};
+use crate::error::{AppError, AppResult};
+use crate::worktree;
+
+/// Directory a run executes in
+#[derive(Debug)]
+pub struct Workspace {
+    path: PathBuf,
+    kind: WorkspaceKind,
+    retention: WorkspaceRetention,
+    /// Keep the workspace if the run starts a Claude session, which will be
+    /// resumed in the same directory
+    keep_for_session: bool,
+}
+
+#[derive(Debug)]
+enum WorkspaceKind {
+    /// A directory that outlives the run and is never cleaned up
+    Existing,
+    /// A directory created for this run
+    Temporary,
+    /// A git worktree created for a new session
+    Worktree(SessionWorktree),
+}
+
+impl Workspace {
+    /// An existing directory that is never cleaned up
+    pub fn existing(path: PathBuf) -> Self {
+        Self {
+            path,
+            kind: WorkspaceKind::Existing,
+            retention: WorkspaceRetention::Keep,
+            keep_for_session: false,
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Git worktree of this workspace, if it was created for a new session
+    pub fn worktree(&self) -> Option<&SessionWorktree> {
+        match self.kind {
+            WorkspaceKind::Worktree(ref worktree) => Some(worktree),
+            _ => None,
+        }
+    }
+
+    /// Whether the workspace is a temporary directory created for the run
+    pub fn is_temporary(&self) -> bool {
+        matches!(self.kind, WorkspaceKind::Temporary)
+    }
+
+    /// Keep the workspace regardless of policy if the run starts a session
+    pub fn keep_for_session(&mut self) {
+        self.keep_for_session = true;
+    }
+
+    /// Remove the workspace's directory (and a worktree's branch)
+    async fn remove(&self) -> AppResult<()> {
+        match self.kind {
+            WorkspaceKind::Existing => Ok(()),
+            WorkspaceKind::Temporary => Ok(tokio::fs::remove_dir_all(&self.path).await?),
+            WorkspaceKind::Worktree(ref worktree) => {
+                worktree::remove(worktree, &self.path, true).await
+            }
+        }
+    }
+
+    /// Remove a created workspace whose run never started
+    pub async fn discard(self) {
+        if let Err(e) = self.remove().await {
+            warn!(
+                "⚠️  Failed to remove workspace {}: {}",
+                self.path.display(),
+                e
+            );
+        }
+    }
+
+    /// Apply the retention policy once the run has ended. Returns whether a
+    /// workspace created for the run was kept on disk.
+    pub async fn finish(self, failed: bool, session_started: bool) -> bool {
+        if matches!(self.kind, WorkspaceKind::Existing) {
+            return false;
+        }
+
+        let keep = (self.keep_for_session && session_started)
+            || match self.retention {
+                WorkspaceRetention::Keep => true,
+                WorkspaceRetention::KeepOnFailure => failed,
+                WorkspaceRetention::Delete => false,
+            };
+
+        if keep {
+            info!("📁 Keeping workspace {}", self.path.display());
+            return true;
+        }
+
+        match self.remove().await {
+            Ok(()) => {
+                debug!("🧹 Removed workspace {}", self.path.display());
+                false
+            }
+            Err(e) => {
+                warn!(
+                    "⚠️  Failed to remove workspace {}: {}",
+                    self.path.display(),
+                    e
+                );
+                true
+            }
+        }
+    }
+}
+
+/// Where a new run should execute, as requested by the caller
+#[derive(Debug, Default)]
+pub struct WorkspaceRequest<'a> {
+    pub profile: Option<&'a str>,
+    pub workdir: Option<&'a str>,
+    pub temp_workspace: bool,
+    pub template: Option<&'a str>,
+    pub create_session: bool,
+}
+
+/// Resolve the workspace for a new run. A profile with a repository gives
+/// every new session its own git worktree; otherwise the requested directory
+/// or temporary workspace is used.
+pub async fn prepare_run(
+    config: &ServerConfig,
+    request: WorkspaceRequest<'_>,
+) -> AppResult<Option<Workspace>> {
+    let profile = match request.profile {
+        Some(name) => Some(config.profiles.get(name)?),
+        None => None,
+    };
+
+    let mut workspace = match profile {
+        Some(profile) if profile.repo.is_some() => {
+            if !request.create_session {
+                return Err(AppError::InvalidRequest(format!(
+                    "profile '{}' gives each session a git worktree and requires create_session",
+                    request.profile.unwrap_or_default()
+                )));
+            }
+            if request.workdir.is_some() || request.temp_workspace || request.template.is_some() {
+                return Err(AppError::InvalidRequest(format!(
+                    "profile '{}' chooses the working directory itself",
+                    request.profile.unwrap_or_default()
+                )));
+            }
+
+            let default_dir = Path::new(&config.session_dir).join("worktrees");
+            let (path, worktree) = worktree::create(profile, &default_dir).await?;
+            Some(Workspace {
+                path,
+                kind: WorkspaceKind::Worktree(worktree),
+                retention: config.workspace.retention,
+                keep_for_session: false,
+            })
+        }
+        _ => {
+            prepare(
+                &config.workspace,
+                request.workdir,
+                request.temp_workspace,
+                request.template,
+            )
+            .await?
+        }
+    };
+
+    if request.create_session {
+        if let Some(ref mut workspace) = workspace {
+            workspace.keep_for_session();
+        }
+    }
+
+    Ok(workspace)
+}
+
+/// Resolve the workspace for a run from the requested directory or temporary
+/// workspace options. Returns `None` when the run should inherit the server's
+/// working directory.
+async fn prepare(
+    config: &WorkspaceConfig,
+    workdir: Option<&str>,
+    temp_workspace: bool,
+    template: Option<&str>,
+) -> AppResult<Option<Workspace>> {
+    let temp_workspace = temp_workspace || template.is_some();
+
+    if workdir.is_some() && temp_workspace {
+        return Err(AppError::InvalidRequest(
+            "workdir cannot be combined with a temporary workspace".to_string(),
+        ));
+    }
+
+    if let Some(workdir) = workdir {
+        let path = resolve_allowed(config, workdir).await?;
+        return Ok(Some(Workspace::existing(path)));
+    }
+
+    if !temp_workspace {
+        return Ok(None);
+    }
+
+    let template = match template {
+        Some(template) => Some(resolve_allowed(config, template).await?),
+        None => None,
+    };
+
+    tokio::fs::create_dir_all(&config.temp_dir).await?;
+    let path = config.temp_dir.join(format!("run-{}", Uuid::new_v4()));
+    tokio::fs::create_dir(&path).await?;
+
+    let workspace = Workspace {
+        path,
+        kind: WorkspaceKind::Temporary,
+        retention: config.retention,
+        keep_for_session: false,
+    };
+
+    if let Some(template) = template {
+        let destination = workspace.path.clone();
+        let copied = tokio::task::spawn_blocking(move || copy_dir(&template, &destination))
+            .await
+            .unwrap_or_else(|e| Err(io::Error::other(e)));
+        if let Err(e) = copied {
+            workspace.discard().await;
+            return Err(e.into());
+        }
+    }
+
+    info!("📁 Created workspace {}", workspace.path.display());
+    Ok(Some(workspace))
+}
+
+/// Workspace a resumed session runs in: the directory recorded when it was
+/// created, which must still exist
+pub fn session_workspace(workdir: Option<&str>) -> AppResult<Option<Workspace>> {
+    let Some(workdir) = workdir else {
+        return Ok(None);
+    };
+    let path = PathBuf::from(workdir);
+    if !path.is_dir() {
+        return Err(AppError::InvalidRequest(format!(
+            "session working directory '{}' no longer exists",
+            workdir
+        )));
+    }
+    Ok(Some(Workspace::existing(path)))
+}
+
+/// Canonicalize a requested directory and check it lies inside an allowed
+/// root. Symlinks are resolved first, so they cannot escape a root.
+async fn resolve_allowed(config: &WorkspaceConfig, requested: &str) -> AppResult<PathBuf> {
+    let path = tokio::fs::canonicalize(requested)
+        .await
+        .map_err(|e| AppError::InvalidRequest(format!("directory '{}': {}", requested, e)))?;
+
+    for root in &config.allowed_roots {
+        let Ok(root) = tokio::fs::canonicalize(root).await else {
+            continue;
+        };
+        if path.starts_with(&root) {
+            if !path.is_dir() {
+                return Err(AppError::InvalidRequest(format!(
+                    "'{}' is not a directory",
+                    requested
+                )));
+            }
+            return Ok(path);
+        }
+    }
+
+    Err(AppError::WorkdirNotAllowed(requested.to_string()))
+}
+
+/// Recursively copy a template directory. Symlinks are recreated rather than
+/// followed so a template cannot pull in files from outside its root.
+fn copy_dir(source: &Path, destination: &Path) -> io::Result<()> {
+    for entry in std::fs::read_dir(source)? {
+        let entry = entry?;
+        let file_type = entry.file_type()?;
+        let target = destination.join(entry.file_name());
+
+        if file_type.is_dir() {
+            std::fs::create_dir(&target)?;
+            copy_dir(&entry.path(), &target)?;
+        } else if file_type.is_symlink() {
+            #[cfg(unix)]
+            std::os::unix::fs::symlink(std::fs::read_link(entry.path())?, &target)?;
+            #[cfg(not(unix))]
+            debug!("Skipping symlink {} in template", entry.path().display());
+        } else {
+            std::fs::copy(entry.path(), &target)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(root: &Path, temp_dir: &Path, retention: WorkspaceRetention) -> WorkspaceConfig {
+        WorkspaceConfig {
+            allowed_roots: vec![root.to_path_buf()],
+            temp_dir: temp_dir.to_path_buf(),
+            retention,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_workdir_must_be_inside_root() {
+        let root = tempfile::tempdir().unwrap();
+        let outside = tempfile::tempdir().unwrap();
+        let project = root.path().join("project");
+        std::fs::create_dir(&project).unwrap();
+        let config = config(root.path(), outside.path(), WorkspaceRetention::Delete);
+
+        let workspace = prepare(&config, project.to_str(), false, None)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(workspace.path(), project.canonicalize().unwrap());
+        assert!(!workspace.finish(false, false).await);
+        assert!(project.exists());
+
+        let result = prepare(&config, outside.path().to_str(), false, None).await;
+        assert!(matches!(result, Err(AppError::WorkdirNotAllowed(_))));
+
+        let escape = project.join("..").join("..");
+        let result = prepare(&config, escape.to_str(), false, None).await;
+        assert!(matches!(result, Err(AppError::WorkdirNotAllowed(_))));
+    }
+
+    #[tokio::test]
+    async fn test_temp_workspace_from_template() {
+        let root = tempfile::tempdir().unwrap();
+        let temp_dir = tempfile::tempdir().unwrap();
+        let template = root.path().join("template");
+        std::fs::create_dir_all(template.join("src")).unwrap();
+        std::fs::write(template.join("src/main.rs"), "fn main() {}").unwrap();
+        let config = config(root.path(), temp_dir.path(), WorkspaceRetention::Delete);
+
+        let workspace = prepare(&config, None, false, template.to_str())
+            .await
+            .unwrap()
+            .unwrap();
+        let path = workspace.path().to_path_buf();
+        assert!(path.starts_with(temp_dir.path()));
+        assert_eq!(
+            std::fs::read_to_string(path.join("src/main.rs")).unwrap(),
+            "fn main() {}"
+        );
+
+        assert!(!workspace.finish(false, false).await);
+        assert!(!path.exists());
+    }
+
+    #[tokio::test]
+    async fn test_retention_policy() {
+        let root = tempfile::tempdir().unwrap();
+        let temp_dir = tempfile::tempdir().unwrap();
+        let config = config(
+            root.path(),
+            temp_dir.path(),
+            WorkspaceRetention::KeepOnFailure,
+        );
+
+        let workspace = prepare(&config, None, true, None).await.unwrap().unwrap();
+        let path = workspace.path().to_path_buf();
+        assert!(workspace.finish(true, false).await);
+        assert!(path.exists());
+
+        let workspace = prepare(&config, None, true, None).await.unwrap().unwrap();
+        let path = workspace.path().to_path_buf();
+        assert!(!workspace.finish(false, false).await);
+        assert!(!path.exists());
+
+        // Kept for a session only if the run actually started one
+        let mut workspace = prepare(&config, None, true, None).await.unwrap().unwrap();
+        workspace.keep_for_session();
+        assert!(workspace.finish(false, true).await);
+
+        let mut workspace = prepare(&config, None, true, None).await.unwrap().unwrap();
+        let path = workspace.path().to_path_buf();
+        workspace.keep_for_session();
+        assert!(!workspace.finish(false, false).await);
+        assert!(!path.exists());
+    }
+
+    #[tokio::test]
+    async fn test_workdir_conflicts_with_temp_workspace() {
+        let config = WorkspaceConfig::default();
+        let result = prepare(&config, Some("/tmp"), true, None).await;
+        assert!(matches!(result, Err(AppError::InvalidRequest(_))));
+        assert!(prepare(&config, None, false, None).await.unwrap().is_none());
+    }
+}
@@ -0,0 +1,212 @@
+use std::path::{Path, PathBuf};
+use tokio::process::Command;
+use tracing::{debug, info, warn};
+use uuid::Uuid;
+
+use q9gent_client::types::SessionWorktree;
+
+use crate::error::{AppError, AppResult};
+use crate::profile::Profile;
+
+/// Run a git command in `dir` and return its stdout
+async fn git(dir: &Path, args: &[&str], index_file: Option<&Path>) -> AppResult<String> {
+    debug!("git -C {} {}", dir.display(), args.join(" "));
+    let mut command = Command::new("git");
+    command.arg("-C").arg(dir).args(args);
+    if let Some(index_file) = index_file {
+        command.env("GIT_INDEX_FILE", index_file);
+    }
+
+    let output = command
+        .output()
+        .await
+        .map_err(|e| AppError::GitFailed(format!("could not run git: {}", e)))?;
+
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(AppError::GitFailed(format!(
+            "git {} exited with {}: {}",
+            args.first().unwrap_or(&""),
+            output.status,
+            stderr.trim()
+        )));
+    }
+
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// Create a worktree of the profile's repository on a new branch
+///
+/// Returns the worktree's path and what a session needs to diff or remove it.
+pub async fn create(
+    profile: &Profile,
+    default_dir: &Path,
+) -> AppResult<(PathBuf, SessionWorktree)> {
+    let repo = profile
+        .repo
+        .as_deref()
+        .ok_or_else(|| AppError::InvalidRequest("profile has no repository".to_string()))?;
+    let base_ref = profile.base_ref.as_deref().unwrap_or("HEAD");
+
+    let base_commit = git(
+        repo,
+        &["rev-parse", "--verify", &format!("{}^{{commit}}", base_ref)],
+        None,
+    )
+    .await?
+    .trim()
+    .to_string();
+
+    let id = Uuid::new_v4().simple().to_string();
+    let branch = format!("{}{}", profile.branch_prefix, id);
+    let worktree_dir = profile.worktree_dir.as_deref().unwrap_or(default_dir);
+    tokio::fs::create_dir_all(worktree_dir).await?;
+    // git resolves relative paths against the repository, not our cwd
+    let path = tokio::fs::canonicalize(worktree_dir).await?.join(&id);
+
+    git(
+        repo,
+        &[
+            "worktree",
+            "add",
+            "-b",
+            &branch,
+            &path.to_string_lossy(),
+            &base_commit,
+        ],
+        None,
+    )
+    .await?;
+
+    info!(
+        "🌿 Created worktree {} on branch {}",
+        path.display(),
+        branch
+    );
+    Ok((
+        path,
+        SessionWorktree {
+            repo: repo.to_string_lossy().to_string(),
+            branch,
+            base_commit,
+        },
+    ))
+}
+
+/// Remove a worktree, optionally deleting its branch too
+pub async fn remove(worktree: &SessionWorktree, path: &Path, delete_branch: bool) -> AppResult<()> {
+    let repo = Path::new(&worktree.repo);
+    git(
+        repo,
+        &["worktree", "remove", "--force", &path.to_string_lossy()],
+        None,
+    )
+    .await?;
+
+    if delete_branch {
+        if let Err(e) = git(repo, &["branch", "-D", &worktree.branch], None).await {
+            warn!("⚠️  Failed to delete branch {}: {}", worktree.branch, e);
+        }
+    }
+
+    info!("🧹 Removed worktree {}", path.display());
+    Ok(())
+}
+
+/// Unified diff from the base commit to the worktree's current files,
+/// including uncommitted and untracked (but not ignored) files
+///
+/// The files are staged into a throwaway index so the worktree's own index
+/// is left untouched.
+pub async fn diff(worktree: &SessionWorktree, path: &Path) -> AppResult<String> {
+    let index_dir = scratch_dir()?;
+    let index_file = index_dir.join("index");
+
+    let result = async {
+        git(path, &["add", "--all"], Some(&index_file)).await?;
+        git(
+            path,
+            &["diff", "--cached", "--no-color", &worktree.base_commit],
+            Some(&index_file),
+        )
+        .await
+    }
+    .await;
+
+    let _ = tokio::fs::remove_dir_all(&index_dir).await;
+    result
+}
+
+/// Private directory for a throwaway git index
+fn scratch_dir() -> AppResult<PathBuf> {
+    let dir = std::env::temp_dir().join(format!("q9gent-index-{}", Uuid::new_v4()));
+    std::fs::create_dir(&dir)?;
+    Ok(dir)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Initialise a repository with one commit
+    async fn init_repo(dir: &Path) {
+        for args in [
+            vec!["init", "-q", "-b", "main"],
+            vec!["config", "user.email", "test@example.com"],
+            vec!["config", "user.name", "Test"],
+        ] {
+            git(dir, &args, None).await.unwrap();
+        }
+        std::fs::write(dir.join("README.md"), "hello\n").unwrap();
+        git(dir, &["add", "README.md"], None).await.unwrap();
+        git(dir, &["commit", "-q", "-m", "init"], None)
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_worktree_lifecycle() {
+        let repo = tempfile::tempdir().unwrap();
+        let worktrees = tempfile::tempdir().unwrap();
+        init_repo(repo.path()).await;
+
+        let profile = Profile {
+            repo: Some(repo.path().to_path_buf()),
+            branch_prefix: "agent/".to_string(),
+            ..Default::default()
+        };
+        let (path, worktree) = create(&profile, worktrees.path()).await.unwrap();
+        assert!(path.join("README.md").exists());
+        assert!(worktree.branch.starts_with("agent/"));
+
+        std::fs::write(path.join("README.md"), "hello\nworld\n").unwrap();
+        std::fs::write(path.join("new.txt"), "untracked\n").unwrap();
+        let changes = diff(&worktree, &path).await.unwrap();
+        assert!(changes.contains("+world"));
+        assert!(changes.contains("new.txt"));
+
+        // The worktree's own index is unchanged
+        let status = git(&path, &["status", "--porcelain"], None).await.unwrap();
+        assert!(status.contains("?? new.txt"));
+
+        remove(&worktree, &path, true).await.unwrap();
+        assert!(!path.exists());
+        let branches = git(repo.path(), &["branch", "--list"], None).await.unwrap();
+        assert!(!branches.contains(&worktree.branch));
+    }
+
+    #[tokio::test]
+    async fn test_unknown_base_ref() {
+        let repo = tempfile::tempdir().unwrap();
+        let worktrees = tempfile::tempdir().unwrap();
+        init_repo(repo.path()).await;
+
+        let profile = Profile {
+            repo: Some(repo.path().to_path_buf()),
+            base_ref: Some("no-such-branch".to_string()),
+            ..Default::default()
+        };
+        let result = create(&profile, worktrees.path()).await;
+        assert!(matches!(result, Err(AppError::GitFailed(_))));
+    }
+}